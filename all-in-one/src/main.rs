@@ -0,0 +1,129 @@
+use crossbeam_channel::unbounded;
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use ucdp::cli::{self, Args};
+use ucdp::config::{self, Validation};
+use ucdp::shutdown;
+use ucdp::stream::consumer::{ReloadableEventsConsumer, StreamConsumerBuilder};
+use ucdp::stream::events::Events;
+use ucdp::stream::producer::StreamProducerBuilder;
+use ucdp::telemetry;
+
+fn validate(validation: &mut Validation) {
+    telemetry::validate(validation);
+    shutdown::validate(validation);
+    StreamProducerBuilder::validate(validation);
+    StreamConsumerBuilder::validate(validation);
+    gateway::ucdp::web::validate(validation);
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let config = match cli::init(&args, validate) {
+        Ok(Some(config)) => config,
+        Ok(None) => return Ok(()),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let source = args.source();
+
+    let tracer_provider = telemetry::init(&config, "ucdp").map_err(std::io::Error::other)?;
+
+    let terminate = shutdown::on_terminate(shutdown::drain_timeout(&config))?;
+
+    let (sender, receiver) = unbounded::<Events>();
+
+    // Start thread that will receive events to send them to the stream
+    let stream_producer_thread =
+        ucdp::stream::producer::spawn_stream_producer_thread(&config, receiver)
+            .map_err(std::io::Error::other)?;
+
+    // Start thread that will consume the stream, as the workers do
+    // The destination follows the changes of the configuration file, as the gateway lookups do
+    let events_consumer =
+        Arc::new(ReloadableEventsConsumer::new(&config).map_err(std::io::Error::other)?);
+    let stream_consumer = StreamConsumerBuilder::build_with_events_consumer(
+        &config,
+        Box::new(events_consumer.clone()),
+    )
+    .map_err(std::io::Error::other)?;
+    let _config_watcher = config::watch(&source, validate, move |config| {
+        events_consumer
+            .reload(config)
+            .map_err(|error| error.to_string())
+    })
+    .map_err(std::io::Error::other)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let stream_consumer_thread = shutdown::spawn({
+        let stop = stop.clone();
+        // Consumers spawn tasks and set timeouts, they need a tokio runtime of their own
+        move || {
+            actix_web::rt::System::new().block_on(async {
+                while !stop.load(Ordering::SeqCst) {
+                    stream_consumer.consume().await;
+                }
+                stream_consumer.close().await;
+            })
+        }
+    });
+
+    // Start web service, it stops accepting requests on SIGTERM
+    let res = gateway::ucdp::web::run_http_server(&config, &source, sender).await;
+
+    // Drain the producer first so that the consumer gets all the events, both by the same deadline
+    let deadline = terminate.deadline();
+    if !stream_producer_thread.join_until(deadline) {
+        warn!("Drain timeout elapsed, events may have been lost");
+    }
+    stop.store(true, Ordering::SeqCst);
+    if !stream_consumer_thread.join_until(deadline) {
+        warn!("Drain timeout elapsed, events may have been lost");
+    }
+
+    telemetry::shutdown(tracer_provider);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use gateway::ucdp::dal::{
+        AuthorizedPartnersByUserBuilder, EventSchemasBuilder, PartnersBuilder,
+    };
+    use ucdp::config::{Config, Source, Validation};
+    use ucdp::stream::consumer::StreamConsumerBuilder;
+    use ucdp::stream::producer::StreamProducerBuilder;
+
+    #[actix_rt::test]
+    async fn all_in_one_config_ok() {
+        let config = Config::load(&Source::new("config/Main", None)).unwrap();
+
+        let mut validation = Validation::new(&config);
+        super::validate(&mut validation);
+        assert!(validation.finish().is_ok());
+
+        assert!(StreamProducerBuilder::build(&config).is_ok());
+        assert!(StreamConsumerBuilder::build(&config).is_ok());
+        assert!(EventSchemasBuilder::build(&config).is_ok());
+
+        // Seeded partner and consent match the README example
+        let partners = PartnersBuilder::build(&config).unwrap();
+        let partner = partners
+            .get_partner("0x0000000000000000000000000000000000000123")
+            .await
+            .unwrap();
+        assert!(partner.enabled);
+
+        let authorized_partners_by_user = AuthorizedPartnersByUserBuilder::build(&config).unwrap();
+        assert!(authorized_partners_by_user
+            .is_authorized(
+                "0x0000000000000000000000000000000000000456",
+                "0x0000000000000000000000000000000000000123",
+            )
+            .await
+            .unwrap());
+    }
+}
@@ -1,16 +1,46 @@
 use crossbeam_channel::unbounded;
+use gateway::ucdp;
+use log::warn;
 
-mod ucdp;
+fn validate(validation: &mut ::ucdp::config::Validation) {
+    ::ucdp::telemetry::validate(validation);
+    ::ucdp::shutdown::validate(validation);
+    ::ucdp::stream::producer::StreamProducerBuilder::validate(validation);
+    ucdp::web::validate(validation);
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
+    let args = ::ucdp::cli::Args::parse();
+    let config = match ::ucdp::cli::init(&args, validate) {
+        Ok(Some(config)) => config,
+        Ok(None) => return Ok(()),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let tracer_provider =
+        ::ucdp::telemetry::init(&config, "gateway").map_err(std::io::Error::other)?;
+
+    let terminate = ::ucdp::shutdown::on_terminate(::ucdp::shutdown::drain_timeout(&config))?;
 
     let (sender, receiver) = unbounded::<::ucdp::stream::events::Events>();
 
     // Start thread that will receive events to send them to the stream
-    ::ucdp::stream::producer::spawn_stream_producer_thread(receiver);
+    let stream_producer_thread =
+        ::ucdp::stream::producer::spawn_stream_producer_thread(&config, receiver)
+            .map_err(std::io::Error::other)?;
+
+    // Start web service, it stops accepting requests on SIGTERM
+    let res = ucdp::web::run_http_server(&config, &args.source(), sender).await;
+
+    // The sender is dropped with the web service, wait for the events left in the channel
+    if !stream_producer_thread.join_until(terminate.deadline()) {
+        warn!("Drain timeout elapsed, events may have been lost");
+    }
 
-    // Start web service
-    ucdp::web::run_http_server(sender).await
+    ::ucdp::telemetry::shutdown(tracer_provider);
+    res
 }
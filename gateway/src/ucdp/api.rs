@@ -13,10 +13,10 @@ pub struct User {
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Event {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<serde_json::Value>,
 }
 
-// TODO add partner in stream event
-// TODO add user in stream event
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Events {
     pub partner: Partner,
@@ -29,6 +29,36 @@ pub struct OkResponse {
     pub token: String,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ActivateSchemaRequest {
+    pub version: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuthorizedPartners {
+    pub partners: Vec<Partner>,
+    // Nonce of the next consent the user signs, when the connector accepts signed consent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+}
+
+// EIP-712 Consent message signed by the user, deadline in seconds since the epoch
+#[derive(Deserialize, Serialize)]
+pub struct ConsentRequest {
+    pub partner: Partner,
+    pub authorized: bool,
+    pub nonce: u64,
+    pub deadline: u64,
+    pub signature: web3::types::Bytes,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ConsentResponse {
+    // Hash of the transaction, when consent is relayed on-chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub error: String,
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use ucdp::config::{Config, Validation};
+use web3::ethabi::{encode, Token};
+use web3::signing::{keccak256, recover};
+use web3::types::{Address, H256, U256};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("config error")]
+    Config(#[from] ucdp::config::Error),
+
+    #[error("invalid signature")]
+    InvalidSignature,
+}
+
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+const CONSENT_TYPE: &str =
+    "Consent(address user,address partner,bool authorized,uint256 nonce,uint256 deadline)";
+
+const LISTING_TYPE: &str = "Listing(address user,uint256 deadline)";
+
+// EIP-712 domain of the consent messages, the one of the Ucdp contract
+#[derive(Clone, Debug)]
+pub struct ConsentDomain {
+    separator: H256,
+}
+
+impl ConsentDomain {
+    pub fn new(chain_id: u64, verifying_contract: Address) -> Self {
+        let separator = keccak256(&encode(&[
+            Token::FixedBytes(keccak256(DOMAIN_TYPE.as_bytes()).to_vec()),
+            Token::FixedBytes(keccak256(b"Ucdp").to_vec()),
+            Token::FixedBytes(keccak256(b"1").to_vec()),
+            Token::Uint(chain_id.into()),
+            Token::Address(verifying_contract),
+        ]));
+        ConsentDomain {
+            separator: H256(separator),
+        }
+    }
+
+    // Section build reads
+    pub const SECTIONS: [&'static str; 1] = ["consent"];
+
+    // Signed consent is accepted once consent.verifying_contract is set
+    pub fn validate(validation: &mut Validation) {
+        if validation
+            .optional("consent.verifying_contract", Config::get_ethereum_address)
+            .is_some()
+        {
+            validation.required("consent.chain_id", Config::get_int);
+        }
+        validation.optional("consent.rate_limit.count", Config::get_int);
+        validation.optional("consent.rate_limit.interval", Config::get_duration);
+    }
+
+    // Hash of a typed message in the domain
+    fn digest(&self, hash: [u8; 32]) -> H256 {
+        let message = [&b"\x19\x01"[..], self.separator.as_bytes(), &hash].concat();
+        H256(keccak256(&message))
+    }
+
+    pub fn build(config: &Config) -> Result<Option<Self>, Error> {
+        let verifying_contract = match config.get_ethereum_address("consent.verifying_contract") {
+            Ok(address) => address,
+            Err(error) if error.is_not_found() => return Ok(None),
+            Err(error) => return Err(Error::Config(error)),
+        };
+        Ok(Some(ConsentDomain::new(
+            config.get_int("consent.chain_id")? as u64,
+            verifying_contract,
+        )))
+    }
+}
+
+// A user grants or revokes the authorization of a partner, until deadline in seconds since the epoch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Consent {
+    pub user: Address,
+    pub partner: Address,
+    pub authorized: bool,
+    pub nonce: u64,
+    pub deadline: u64,
+}
+
+// A consent with the signature of the user, r, s and v
+#[derive(Clone, Debug)]
+pub struct SignedConsent {
+    pub consent: Consent,
+    pub signature: [u8; 65],
+}
+
+impl Consent {
+    // Hash signed by the user, as eth_signTypedData_v4 computes it
+    pub fn digest(&self, domain: &ConsentDomain) -> H256 {
+        let hash = keccak256(&encode(&[
+            Token::FixedBytes(keccak256(CONSENT_TYPE.as_bytes()).to_vec()),
+            Token::Address(self.user),
+            Token::Address(self.partner),
+            Token::Bool(self.authorized),
+            Token::Uint(self.nonce.into()),
+            Token::Uint(self.deadline.into()),
+        ]));
+        domain.digest(hash)
+    }
+
+    // The consent when the user signed it
+    pub fn verify(self, domain: &ConsentDomain, signature: &[u8]) -> Result<SignedConsent, Error> {
+        let signature: [u8; 65] = signature.try_into().map_err(|_| Error::InvalidSignature)?;
+        if signer(self.digest(domain), &signature)? == self.user {
+            Ok(SignedConsent {
+                consent: self,
+                signature,
+            })
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+// Address that signed the digest, v is 27 or 28 as wallets give it or the recovery id itself
+fn signer(digest: H256, signature: &[u8; 65]) -> Result<Address, Error> {
+    let recovery_id = match signature[64] {
+        v @ 27..=28 => v as i32 - 27,
+        v => v as i32,
+    };
+    recover(digest.as_bytes(), &signature[..64], recovery_id).map_err(|_| Error::InvalidSignature)
+}
+
+// A user asks for the partners they have authorized, until deadline in seconds since the epoch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listing {
+    pub user: Address,
+    pub deadline: u64,
+}
+
+impl Listing {
+    // Hash signed by the user, as eth_signTypedData_v4 computes it
+    pub fn digest(&self, domain: &ConsentDomain) -> H256 {
+        let hash = keccak256(&encode(&[
+            Token::FixedBytes(keccak256(LISTING_TYPE.as_bytes()).to_vec()),
+            Token::Address(self.user),
+            Token::Uint(self.deadline.into()),
+        ]));
+        domain.digest(hash)
+    }
+
+    // Ok when the user signed the listing
+    pub fn verify(&self, domain: &ConsentDomain, signature: &[u8]) -> Result<(), Error> {
+        let signature: [u8; 65] = signature.try_into().map_err(|_| Error::InvalidSignature)?;
+        if signer(self.digest(domain), &signature)? == self.user {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+// Signed consent accepted from each user within an interval, relaying it costs gas
+pub struct ConsentRateLimit {
+    count: u64,
+    interval: Duration,
+    // Start of the current interval of each user, with the consent accepted since
+    users: Mutex<HashMap<Address, (Instant, u64)>>,
+}
+
+impl ConsentRateLimit {
+    pub fn build(config: &Config) -> Result<Self, Error> {
+        let count = match config.get_int("consent.rate_limit.count") {
+            Ok(count) => count.max(0) as u64,
+            Err(error) if error.is_not_found() => 10,
+            Err(error) => return Err(Error::Config(error)),
+        };
+        let interval = match config.get_duration("consent.rate_limit.interval") {
+            Ok(interval) => interval,
+            Err(error) if error.is_not_found() => Duration::from_secs(3600),
+            Err(error) => return Err(Error::Config(error)),
+        };
+        Ok(ConsentRateLimit {
+            count,
+            interval,
+            users: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Whether one more consent of the user is accepted, it is counted when it is
+    pub fn acquire(&self, user: Address) -> bool {
+        let now = Instant::now();
+        let mut users = self.users.lock().unwrap();
+        users.retain(|_, (start, _)| now.duration_since(*start) < self.interval);
+        let (_, count) = users.entry(user).or_insert((now, 0));
+        if *count < self.count {
+            *count += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SignedConsent {
+    // Parameters of setAuthorizationBySig
+    pub fn tokens(&self) -> Vec<Token> {
+        let v = match self.signature[64] {
+            v @ 27..=28 => v,
+            v => v + 27,
+        };
+        vec![
+            Token::Address(self.consent.user),
+            Token::Address(self.consent.partner),
+            Token::Bool(self.consent.authorized),
+            Token::Uint(U256::from(self.consent.deadline)),
+            Token::Uint(U256::from(v)),
+            Token::FixedBytes(self.signature[..32].to_vec()),
+            Token::FixedBytes(self.signature[32..64].to_vec()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Consent, ConsentDomain, ConsentRateLimit, Error, Listing};
+    use secp256k1::SecretKey;
+    use std::str::FromStr;
+    use web3::signing::{Key, SecretKeyRef};
+    use web3::types::Address;
+
+    #[test]
+    fn consent_verify() {
+        let key =
+            SecretKey::from_str("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+                .unwrap();
+        let key = SecretKeyRef::new(&key);
+        let domain = ConsentDomain::new(1337, Address::from_low_u64_be(0xc0));
+        let consent = Consent {
+            user: key.address(),
+            partner: Address::from_low_u64_be(0x123),
+            authorized: true,
+            nonce: 0,
+            deadline: 1_700_000_000,
+        };
+
+        let signature = key.sign(consent.digest(&domain).as_bytes(), None).unwrap();
+        let mut bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+        bytes.push(signature.v as u8);
+        let signed = consent.clone().verify(&domain, &bytes).unwrap();
+        assert_eq!(signed.tokens().len(), 7);
+
+        // Signed for another nonce, another domain, or by someone else
+        let replayed = Consent {
+            nonce: 1,
+            ..consent.clone()
+        };
+        for res in [
+            replayed.verify(&domain, &bytes),
+            consent.clone().verify(
+                &ConsentDomain::new(1, Address::from_low_u64_be(0xc0)),
+                &bytes,
+            ),
+            Consent {
+                user: Address::from_low_u64_be(0x456),
+                ..consent.clone()
+            }
+            .verify(&domain, &bytes),
+            consent.verify(&domain, &bytes[..64]),
+        ] {
+            match res {
+                Err(Error::InvalidSignature) => (),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn consent_listing_verify() {
+        let key =
+            SecretKey::from_str("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")
+                .unwrap();
+        let key = SecretKeyRef::new(&key);
+        let domain = ConsentDomain::new(1337, Address::from_low_u64_be(0xc0));
+        let listing = Listing {
+            user: key.address(),
+            deadline: 1_700_000_000,
+        };
+        let signature = key.sign(listing.digest(&domain).as_bytes(), None).unwrap();
+        let mut bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+        bytes.push(signature.v as u8);
+        assert!(listing.verify(&domain, &bytes).is_ok());
+
+        // The partners of another user
+        let other = Listing {
+            user: Address::from_low_u64_be(0x456),
+            ..listing
+        };
+        match other.verify(&domain, &bytes) {
+            Err(Error::InvalidSignature) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn consent_rate_limit() {
+        let mut config = config::Config::default();
+        let _ = config.set("consent.rate_limit.count", 2);
+        let _ = config.set("consent.rate_limit.interval", "100ms");
+        let limit = ConsentRateLimit::build(&ucdp::config::Config::from(config)).unwrap();
+        let (user, other) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+
+        assert!(limit.acquire(user));
+        assert!(limit.acquire(user));
+        assert!(!limit.acquire(user));
+        assert!(limit.acquire(other));
+
+        // Counted again once the interval has elapsed
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(limit.acquire(user));
+    }
+}
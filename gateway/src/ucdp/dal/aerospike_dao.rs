@@ -1,7 +1,8 @@
 use async_trait::async_trait;
 use log::trace;
+use serde::Deserialize;
 use thiserror::Error;
-use ucdp::config::Config;
+use ucdp::config::{Config, Validation};
 
 #[derive(Error, Debug)]
 pub enum AerospikeDaoError {
@@ -16,17 +17,50 @@ pub enum AerospikeDaoError {
 
     #[error("config error")]
     Config(#[from] ucdp::config::Error),
+
+    #[error("record changed concurrently")]
+    Conflict,
 }
 
 pub struct AerospikeDaoResult {
     pub value: Option<Vec<u8>>,
+    #[allow(dead_code)]
     pub ttl: Option<std::time::Duration>,
+    // Generation of the record, 0 when it does not exist
+    pub generation: u32,
+}
+
+// Condition of a write, so that concurrent writers cannot overwrite each other
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expect {
+    // The record must not exist
+    Absent,
+    // The record must still be at the generation it was read at
+    Generation(u32),
+}
+
+impl Expect {
+    // Condition for replacing a record read at the given generation
+    pub fn generation(generation: u32) -> Self {
+        match generation {
+            0 => Expect::Absent,
+            generation => Expect::Generation(generation),
+        }
+    }
 }
 
 #[async_trait]
 pub trait AerospikeDao: Send + Sync {
     async fn get(&self, key: &str) -> Result<AerospikeDaoResult, AerospikeDaoError>;
+    // Best effort, for caches
     async fn put(&self, key: &str, value: Vec<u8>);
+    // Write that fails with Conflict when the condition does not hold
+    async fn put_if(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        expect: Expect,
+    ) -> Result<(), AerospikeDaoError>;
 }
 
 pub struct AerospikeDaoImpl {
@@ -55,6 +89,7 @@ impl AerospikeDao for AerospikeDaoImpl {
                     aerospike::Value::Blob(bytes) => Ok(AerospikeDaoResult {
                         value: Some(bytes.to_vec()),
                         ttl: record.time_to_live(),
+                        generation: record.generation,
                     }),
                     v => Err(AerospikeDaoError::InvalidType(v.to_string())),
                 }
@@ -66,6 +101,7 @@ impl AerospikeDao for AerospikeDaoImpl {
             )) => Ok(AerospikeDaoResult {
                 value: None,
                 ttl: None,
+                generation: 0,
             }),
             // Other errors
             Err(e) => Err(AerospikeDaoError::Aerospike(e)),
@@ -79,22 +115,65 @@ impl AerospikeDao for AerospikeDaoImpl {
         let bin = aerospike::as_bin!("0", bytes);
         let _ = self.client.put(&self.write_policy, &key, &[bin]);
     }
+
+    async fn put_if(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        expect: Expect,
+    ) -> Result<(), AerospikeDaoError> {
+        trace!("put {:?} if {:?}", key, expect);
+        let key = aerospike::as_key!("ucdp", self.set_name.as_str(), key);
+        let bytes: aerospike::Value = value.into();
+        let bin = aerospike::as_bin!("0", bytes);
+        let mut write_policy = self.write_policy.clone();
+        match expect {
+            Expect::Absent => {
+                write_policy.record_exists_action = aerospike::RecordExistsAction::CreateOnly
+            }
+            Expect::Generation(generation) => {
+                write_policy.generation_policy = aerospike::GenerationPolicy::ExpectGenEqual;
+                write_policy.generation = generation;
+            }
+        }
+        match self.client.put(&write_policy, &key, &[bin]) {
+            Ok(()) => Ok(()),
+            Err(aerospike::Error(
+                aerospike::ErrorKind::ServerError(aerospike::ResultCode::KeyExistsError),
+                _,
+            ))
+            | Err(aerospike::Error(
+                aerospike::ErrorKind::ServerError(aerospike::ResultCode::GenerationError),
+                _,
+            )) => Err(AerospikeDaoError::Conflict),
+            Err(e) => Err(AerospikeDaoError::Aerospike(e)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AerospikeConfig {
+    set: String,
+    host: String,
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.required("aerospike", Config::get::<AerospikeConfig>);
 }
 
 pub struct AerospikeDaoBuilder {}
 
 impl AerospikeDaoBuilder {
     pub fn build(config: &Config) -> Result<Box<dyn AerospikeDao>, AerospikeDaoError> {
-        let set_name = config.get_str("aerospike.set")?;
-        let host = config.get_str("aerospike.host")?;
+        let aerospike_config = config.get::<AerospikeConfig>("aerospike")?;
 
         let mut client_policy = aerospike::ClientPolicy::default().clone();
         client_policy.fail_if_not_connected = false; // it makes testing easier
-        let client = aerospike::Client::new(&client_policy, &host)?;
+        let client = aerospike::Client::new(&client_policy, &aerospike_config.host)?;
 
         Ok(Box::new(AerospikeDaoImpl {
             client,
-            set_name,
+            set_name: aerospike_config.set,
             read_policy: aerospike::ReadPolicy::default(),
             write_policy: aerospike::WritePolicy::default(),
         }))
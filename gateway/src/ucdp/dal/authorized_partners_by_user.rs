@@ -1,9 +1,27 @@
-use crate::ucdp::dal::ethereum_dao::{EthereumDao, EthereumDaoBuilder, EthereumDaoError};
+use crate::ucdp::consent::SignedConsent;
+use crate::ucdp::dal::consent_log::{self, ConsentLog, ConsentLogBuilder, ConsentLogError};
+use crate::ucdp::dal::ethereum_dao::{
+    self, EthereumBlocks, EthereumBlocksBuilder, EthereumDao, EthereumDaoBuilder, EthereumDaoError,
+    EthereumEvents, EthereumEventsBuilder, EthereumRelay, EthereumRelayBuilder,
+};
+use crate::ucdp::dal::in_memory_dao::{self, InMemoryDao, InMemoryDaoBuilder, InMemoryDaoError};
+use crate::ucdp::metrics;
 use async_trait::async_trait;
+use futures::future::join_all;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt};
+use opentelemetry::{Context, KeyValue};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
 use thiserror::Error;
-use ucdp::config::Config;
+use ucdp::config::{Config, Validation};
+use ucdp::stream::events::ConsentBlock;
+use ucdp::telemetry;
+use web3::ethabi::Token;
+use web3::types::{Address, BlockId, BlockNumber, H256, U256};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -16,60 +34,663 @@ pub enum Error {
     #[error("config error")]
     Config(#[from] ucdp::config::Error),
 
+    #[error("in memory dao error")]
+    InMemoryDao(#[from] InMemoryDaoError),
+
+    #[error("consent log error: {0}")]
+    ConsentLog(#[from] ConsentLogError),
+
+    #[error("connector does not support {0}")]
+    Unsupported(String),
+
+    #[error("nonce must be {0}")]
+    Nonce(u64),
+
+    #[error("{0} is not registered")]
+    NotRegistered(String),
+
     #[error("unknown connector: {0}")]
     UnknownConnector(String),
 }
 
+// Consent of a user, and the block of the chain it was checked at if any
+pub struct Authorization {
+    pub authorized: bool,
+    pub block: Option<ConsentBlock>,
+}
+
 #[async_trait]
 pub trait AuthorizedPartnersByUserDao: Send + Sync {
     async fn is_authorized(&self, user_id: &str, partner_id: &str) -> Result<bool, Error>;
+
+    // Connectors without chain state have no block to record
+    async fn authorization(&self, user_id: &str, partner_id: &str) -> Result<Authorization, Error> {
+        Ok(Authorization {
+            authorized: self.is_authorized(user_id, partner_id).await?,
+            block: None,
+        })
+    }
+
+    // Record that a user grants or revokes consent to a partner
+    async fn set_authorized(
+        &self,
+        _user_id: &str,
+        _partner_id: &str,
+        _authorized: bool,
+    ) -> Result<(), Error> {
+        Err(Error::Unsupported("updates".into()))
+    }
+
+    // Partners the user has authorized
+    async fn authorized_partners(&self, _user_id: &str) -> Result<Vec<String>, Error> {
+        Err(Error::Unsupported("listing".into()))
+    }
+
+    // Nonce the next signed consent of the user must have
+    async fn nonce(&self, _user_id: &str) -> Result<u64, Error> {
+        Err(Error::Unsupported("signed consent".into()))
+    }
+
+    // Apply a consent the user has signed, with the hash of the transaction when it is relayed on-chain.
+    // The nonce is checked and taken in one step, a replay running alongside must fail.
+    async fn apply_consent(&self, _signed: &SignedConsent) -> Result<Option<H256>, Error> {
+        Err(Error::Unsupported("signed consent".into()))
+    }
+
+    // Consent of a user to each partner, to warm caches with
+    async fn authorizations(
+        &self,
+        user_id: &str,
+        partner_ids: &[&str],
+    ) -> Result<Vec<Authorization>, Error> {
+        join_all(
+            partner_ids
+                .iter()
+                .map(|partner_id| self.authorization(user_id, partner_id)),
+        )
+        .await
+        .into_iter()
+        .collect()
+    }
 }
 
 struct EthereumAuthorizedPartnersByUserDao<'a> {
     ethereum_dao: Box<dyn EthereumDao<'a, (web3::types::Address, web3::types::Address), bool>>,
+    // Consent is read at the latest block, possibly reorged, when None
+    confirmed_blocks: Option<(Box<dyn EthereumBlocks>, u64)>,
+    // Contracts without the Authorization event and signed consent are read-only
+    management: Option<EthereumConsentManagement<'a>>,
+}
+
+struct EthereumConsentManagement<'a> {
+    events: Box<dyn EthereumEvents>,
+    nonces: Box<dyn EthereumDao<'a, Address, U256>>,
+    // Signed consent is not relayed without a key to pay for the transactions
+    relay: Option<EthereumConsentRelay<'a>>,
+}
+
+struct EthereumConsentRelay<'a> {
+    relay: Box<dyn EthereumRelay>,
+    // setAuthorizationBySig reverts for users and partners that are not registered
+    users: Box<dyn EthereumDao<'a, (Address,), (Vec<u8>, bool)>>,
+    partners: Box<dyn EthereumDao<'a, (Address,), (Vec<u8>, bool, bool)>>,
+    // Users and nonces of the consents being relayed, only one of them would not revert
+    relaying: Mutex<HashSet<(Address, u64)>>,
+}
+
+// Releases the nonce of the user once relayed, or once relaying failed
+struct Relaying<'r> {
+    relaying: &'r Mutex<HashSet<(Address, u64)>>,
+    key: (Address, u64),
+}
+
+impl Drop for Relaying<'_> {
+    fn drop(&mut self) {
+        self.relaying.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl<'a> EthereumAuthorizedPartnersByUserDao<'a> {
+    fn management(&self) -> Result<&EthereumConsentManagement<'a>, Error> {
+        self.management
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("consent management".into()))
+    }
+
+    async fn confirmed_block(&self) -> Result<Option<ConsentBlock>, Error> {
+        match &self.confirmed_blocks {
+            Some((blocks, confirmations)) => Ok(Some(blocks.confirmed(*confirmations).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Read by hash (EIP-1898), a reorganization cannot swap the state behind the recorded block
+fn block_id(block: &ConsentBlock) -> Result<BlockId, Error> {
+    H256::from_str(&block.hash)
+        .map(BlockId::Hash)
+        .map_err(|_| Error::Parameter("block hash".into()))
 }
 
 #[async_trait]
 impl AuthorizedPartnersByUserDao for EthereumAuthorizedPartnersByUserDao<'_> {
     async fn is_authorized(&self, user_id: &str, partner_id: &str) -> Result<bool, Error> {
+        Ok(self.authorization(user_id, partner_id).await?.authorized)
+    }
+
+    async fn authorization(&self, user_id: &str, partner_id: &str) -> Result<Authorization, Error> {
+        let mut authorizations = self.authorizations(user_id, &[partner_id]).await?;
+        Ok(authorizations.remove(0))
+    }
+
+    // Every partner is read at the same block, in one JSON-RPC batch when batching is enabled
+    async fn authorizations(
+        &self,
+        user_id: &str,
+        partner_ids: &[&str],
+    ) -> Result<Vec<Authorization>, Error> {
         let user_adress = web3::types::Address::from_str(user_id)
             .map_err(|_| Error::Parameter("user_id".into()))?;
-        let partner_adress = web3::types::Address::from_str(partner_id)
-            .map_err(|_| Error::Parameter("partner_id".into()))?;
+        let keys = partner_ids
+            .iter()
+            .map(|partner_id| {
+                web3::types::Address::from_str(partner_id)
+                    .map(|partner_adress| (user_adress, partner_adress))
+                    .map_err(|_| Error::Parameter("partner_id".into()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
+        let block = self.confirmed_block().await?;
+        let block_id = block.as_ref().map(block_id).transpose()?;
         self.ethereum_dao
-            .get((user_adress, partner_adress))
+            .get_many(keys, block_id)
+            .await
+            .into_iter()
+            .map(|authorized| {
+                Ok(Authorization {
+                    authorized: authorized.map_err(Error::Contract)?,
+                    block: block.clone(),
+                })
+            })
+            .collect()
+    }
+
+    // Replay of the Authorization events of the user, up to the confirmed block
+    async fn authorized_partners(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        let user_adress =
+            Address::from_str(user_id).map_err(|_| Error::Parameter("user_id".into()))?;
+        let block_id = self
+            .confirmed_block()
+            .await?
+            .as_ref()
+            .map(block_id)
+            .transpose()?;
+        let logs = self
+            .management()?
+            .events
+            .logs(user_adress, block_id)
+            .await?;
+
+        let mut partners: Vec<Address> = vec![];
+        for log in logs {
+            let param = |name: &str| {
+                log.params
+                    .iter()
+                    .find(|param| param.name == name)
+                    .map(|param| param.value.clone())
+            };
+            match (param("partner"), param("authorized")) {
+                (Some(Token::Address(partner)), Some(Token::Bool(authorized))) => {
+                    partners.retain(|address| *address != partner);
+                    if authorized {
+                        partners.push(partner);
+                    }
+                }
+                _ => return Err(Error::Parameter("Authorization event".into())),
+            }
+        }
+        Ok(partners
+            .iter()
+            .map(|partner| format!("{:?}", partner))
+            .collect())
+    }
+
+    async fn nonce(&self, user_id: &str) -> Result<u64, Error> {
+        let user_adress =
+            Address::from_str(user_id).map_err(|_| Error::Parameter("user_id".into()))?;
+        // The relayed transaction checks the nonce against the latest state
+        let nonce = self.management()?.nonces.get(user_adress, None).await?;
+        Ok(nonce.low_u64())
+    }
+
+    async fn apply_consent(&self, signed: &SignedConsent) -> Result<Option<H256>, Error> {
+        let relay = self
+            .management()?
+            .relay
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("relaying".into()))?;
+        // Fail early rather than paying for a transaction that reverts
+        let consent = &signed.consent;
+        let key = (consent.user, consent.nonce);
+        if !relay.relaying.lock().unwrap().insert(key) {
+            return Err(Error::Nonce(consent.nonce + 1));
+        }
+        let _relaying = Relaying {
+            relaying: &relay.relaying,
+            key,
+        };
+        // Transactions relayed before and not mined yet have taken their nonces
+        let pending = BlockId::Number(BlockNumber::Pending);
+        let (nonce, user, partner) = futures::join!(
+            self.management()?.nonces.get(consent.user, Some(pending)),
+            relay.users.get((consent.user,), None),
+            relay.partners.get((consent.partner,), None)
+        );
+        let nonce = nonce?.low_u64();
+        if consent.nonce != nonce {
+            return Err(Error::Nonce(nonce));
+        }
+        if !user?.1 {
+            return Err(Error::NotRegistered("user".into()));
+        }
+        if !partner?.2 {
+            return Err(Error::NotRegistered("partner".into()));
+        }
+        Ok(Some(relay.relay.send(signed.tokens()).await?))
+    }
+}
+
+// Partners authorized by each user, addresses are compared case insensitively.
+// Signed consent is refused, nonces kept in memory would start over at 0 with the process and
+// let signed consent be replayed.
+struct InMemoryAuthorizedPartnersByUserDao {
+    in_memory_dao: Box<dyn InMemoryDao<String, Vec<String>>>,
+}
+
+#[async_trait]
+impl AuthorizedPartnersByUserDao for InMemoryAuthorizedPartnersByUserDao {
+    async fn is_authorized(&self, user_id: &str, partner_id: &str) -> Result<bool, Error> {
+        match self.in_memory_dao.get(&user_id.to_lowercase()) {
+            Ok(res) => Ok(res.value.contains(&partner_id.to_lowercase())),
+            Err(InMemoryDaoError::ItemNotFound) => Ok(false),
+            Err(error) => Err(Error::InMemoryDao(error)),
+        }
+    }
+
+    async fn set_authorized(
+        &self,
+        user_id: &str,
+        partner_id: &str,
+        authorized: bool,
+    ) -> Result<(), Error> {
+        let user_id = user_id.to_lowercase();
+        let partner_id = partner_id.to_lowercase();
+        let mut partner_ids = match self.in_memory_dao.get(&user_id) {
+            Ok(res) => res.value,
+            Err(InMemoryDaoError::ItemNotFound) | Err(InMemoryDaoError::Expired) => vec![],
+            Err(error) => return Err(Error::InMemoryDao(error)),
+        };
+        partner_ids.retain(|id| *id != partner_id);
+        if authorized {
+            partner_ids.push(partner_id);
+        }
+        self.in_memory_dao.put(user_id, partner_ids);
+        Ok(())
+    }
+
+    async fn authorized_partners(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        match self.in_memory_dao.get(&user_id.to_lowercase()) {
+            Ok(res) => Ok(res.value),
+            Err(InMemoryDaoError::ItemNotFound) => Ok(vec![]),
+            Err(error) => Err(Error::InMemoryDao(error)),
+        }
+    }
+}
+
+// Consent kept off-chain in a signed log, addresses are compared case insensitively
+struct ConsentLogAuthorizedPartnersByUserDao {
+    consent_log: ConsentLog,
+}
+
+#[async_trait]
+impl AuthorizedPartnersByUserDao for ConsentLogAuthorizedPartnersByUserDao {
+    async fn is_authorized(&self, user_id: &str, partner_id: &str) -> Result<bool, Error> {
+        let partner_ids = self
+            .consent_log
+            .authorized_partners(&user_id.to_lowercase())
+            .await?;
+        Ok(partner_ids.contains(&partner_id.to_lowercase()))
+    }
+
+    async fn set_authorized(
+        &self,
+        user_id: &str,
+        partner_id: &str,
+        authorized: bool,
+    ) -> Result<(), Error> {
+        self.consent_log
+            .append(
+                &user_id.to_lowercase(),
+                &partner_id.to_lowercase(),
+                authorized,
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn authorized_partners(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .consent_log
+            .authorized_partners(&user_id.to_lowercase())
+            .await?)
+    }
+
+    // Entries are numbered per user, the next one is the nonce of the next signed consent
+    async fn nonce(&self, user_id: &str) -> Result<u64, Error> {
+        Ok(self.consent_log.next_seq(&user_id.to_lowercase()).await?)
+    }
+
+    async fn apply_consent(&self, signed: &SignedConsent) -> Result<Option<H256>, Error> {
+        let consent = &signed.consent;
+        let res = self
+            .consent_log
+            .append(
+                &format!("{:?}", consent.user),
+                &format!("{:?}", consent.partner),
+                consent.authorized,
+                Some(consent.nonce),
+            )
+            .await;
+        match res {
+            Ok(_) => Ok(None),
+            Err(ConsentLogError::Seq(seq)) => Err(Error::Nonce(seq)),
+            Err(error) => Err(Error::ConsentLog(error)),
+        }
+    }
+}
+
+// Time and trace the lookups of the connector
+struct MeteredAuthorizedPartnersByUserDao {
+    connector: String,
+    dao: Box<dyn AuthorizedPartnersByUserDao>,
+}
+
+impl MeteredAuthorizedPartnersByUserDao {
+    async fn observe<T>(
+        &self,
+        name: &'static str,
+        operation: impl std::future::Future<Output = Result<T, Error>> + Send,
+    ) -> Result<T, Error> {
+        let cx = telemetry::span(name, SpanKind::Internal, &Context::current());
+        cx.span()
+            .set_attribute(KeyValue::new("dao.connector", self.connector.clone()));
+
+        let start = Instant::now();
+        let res = operation.with_context(cx.clone()).await;
+        metrics::observe_dao("authorized_partners_by_user", &self.connector, start);
+        if let Err(error) = &res {
+            cx.span().set_status(Status::error(error.to_string()));
+        }
+        res
+    }
+}
+
+#[async_trait]
+impl AuthorizedPartnersByUserDao for MeteredAuthorizedPartnersByUserDao {
+    async fn is_authorized(&self, user_id: &str, partner_id: &str) -> Result<bool, Error> {
+        Ok(self.authorization(user_id, partner_id).await?.authorized)
+    }
+
+    async fn authorization(&self, user_id: &str, partner_id: &str) -> Result<Authorization, Error> {
+        let cx = telemetry::span(
+            "authorized_partners_by_user lookup",
+            SpanKind::Internal,
+            &Context::current(),
+        );
+        cx.span()
+            .set_attribute(KeyValue::new("dao.connector", self.connector.clone()));
+
+        let start = Instant::now();
+        let res = self
+            .dao
+            .authorization(user_id, partner_id)
+            .with_context(cx.clone())
+            .await;
+        metrics::observe_dao("authorized_partners_by_user", &self.connector, start);
+        match &res {
+            Ok(Authorization {
+                block: Some(block), ..
+            }) => cx
+                .span()
+                .set_attribute(KeyValue::new("ethereum.block", block.number as i64)),
+            Err(error) => cx.span().set_status(Status::error(error.to_string())),
+            _ => (),
+        }
+        res
+    }
+
+    async fn set_authorized(
+        &self,
+        user_id: &str,
+        partner_id: &str,
+        authorized: bool,
+    ) -> Result<(), Error> {
+        self.observe(
+            "authorized_partners_by_user update",
+            self.dao.set_authorized(user_id, partner_id, authorized),
+        )
+        .await
+    }
+
+    async fn authorized_partners(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        self.observe(
+            "authorized_partners_by_user listing",
+            self.dao.authorized_partners(user_id),
+        )
+        .await
+    }
+
+    async fn nonce(&self, user_id: &str) -> Result<u64, Error> {
+        self.observe("authorized_partners_by_user nonce", self.dao.nonce(user_id))
             .await
-            .map_err(Error::Contract)
+    }
+
+    async fn apply_consent(&self, signed: &SignedConsent) -> Result<Option<H256>, Error> {
+        self.observe(
+            "authorized_partners_by_user signed update",
+            self.dao.apply_consent(signed),
+        )
+        .await
+    }
+
+    async fn authorizations(
+        &self,
+        user_id: &str,
+        partner_ids: &[&str],
+    ) -> Result<Vec<Authorization>, Error> {
+        let cx = telemetry::span(
+            "authorized_partners_by_user lookups",
+            SpanKind::Internal,
+            &Context::current(),
+        );
+        cx.span()
+            .set_attribute(KeyValue::new("dao.connector", self.connector.clone()));
+        cx.span()
+            .set_attribute(KeyValue::new("dao.keys", partner_ids.len() as i64));
+
+        let start = Instant::now();
+        let res = self
+            .dao
+            .authorizations(user_id, partner_ids)
+            .with_context(cx.clone())
+            .await;
+        metrics::observe_dao("authorized_partners_by_user", &self.connector, start);
+        if let Err(error) = &res {
+            cx.span().set_status(Status::error(error.to_string()));
+        }
+        res
     }
 }
 
+// Blocks on top of the one consent is read at, consent is read at the latest block when not set
+fn get_confirmations(config: &Config, key: &str) -> Result<u64, ucdp::config::Error> {
+    u64::try_from(config.get_int(key)?)
+        .map_err(|_| ucdp::config::Error::Invalid(key.into(), "negative confirmations".into()))
+}
+
 pub struct AuthorizedPartnersByUserBuilder {}
 
 impl AuthorizedPartnersByUserBuilder {
-    pub fn build(config: &Config) -> Result<Box<dyn AuthorizedPartnersByUserDao>, Error> {
-        match config
-            .get_str("data.authorized_partners_by_user.connector")?
-            .as_str()
+    // Sections build reads, the DAO is kept on reload while they do not change
+    pub const SECTIONS: [&'static str; 5] = [
+        "data.authorized_partners_by_user",
+        "ethereum",
+        "aerospike",
+        "in_memory",
+        "consent_log",
+    ];
+
+    pub fn validate(validation: &mut Validation) {
+        match validation
+            .one_of(
+                "data.authorized_partners_by_user.connector",
+                &["ethereum", "in-memory", "file", "aerospike"],
+                None,
+            )
+            .as_deref()
         {
+            Some("ethereum") => {
+                ethereum_dao::validate(
+                    validation,
+                    "data.authorized_partners_by_user.contract",
+                    "authorizedPartnersByUser",
+                );
+                validation.optional(
+                    "data.authorized_partners_by_user.confirmations",
+                    get_confirmations,
+                );
+                ethereum_dao::validate_events(
+                    validation,
+                    "data.authorized_partners_by_user.contract",
+                    "Authorization",
+                );
+                EthereumRelayBuilder::validate(validation);
+            }
+            Some("in-memory") => in_memory_dao::validate(validation),
+            Some(store) => consent_log::validate(validation, store),
+            _ => (),
+        }
+    }
+
+    pub fn build(config: &Config) -> Result<Box<dyn AuthorizedPartnersByUserDao>, Error> {
+        let connector = config.get_str("data.authorized_partners_by_user.connector")?;
+        let dao = AuthorizedPartnersByUserBuilder::build_connector_dao(&connector, config)?;
+        Ok(Box::new(MeteredAuthorizedPartnersByUserDao {
+            connector,
+            dao,
+        }))
+    }
+
+    fn build_connector_dao(
+        connector: &str,
+        config: &Config,
+    ) -> Result<Box<dyn AuthorizedPartnersByUserDao>, Error> {
+        match connector {
             "ethereum" => {
-                let ethereum_dao = EthereumDaoBuilder::build(config, "authorizedPartnersByUser")?;
-                let dao = EthereumAuthorizedPartnersByUserDao { ethereum_dao };
+                let ethereum_dao = EthereumDaoBuilder::build(
+                    config,
+                    "data.authorized_partners_by_user.contract",
+                    "authorizedPartnersByUser",
+                )?;
+                let confirmed_blocks = match get_confirmations(
+                    config,
+                    "data.authorized_partners_by_user.confirmations",
+                ) {
+                    Ok(confirmations) => {
+                        Some((EthereumBlocksBuilder::build(config)?, confirmations))
+                    }
+                    Err(error) if error.is_not_found() => None,
+                    Err(error) => return Err(Error::Config(error)),
+                };
+                let dao = EthereumAuthorizedPartnersByUserDao {
+                    ethereum_dao,
+                    confirmed_blocks,
+                    management: AuthorizedPartnersByUserBuilder::build_management(config)?,
+                };
+                Ok(Box::new(dao))
+            }
+            "in-memory" => {
+                let in_memory_dao = InMemoryDaoBuilder::build(config)?;
+                let authorized_partners_by_user: HashMap<String, Vec<String>> =
+                    InMemoryDaoBuilder::<String, Vec<String>>::seed(
+                        config,
+                        "authorized_partners_by_user",
+                    )?;
+                for (user_id, partner_ids) in authorized_partners_by_user {
+                    let partner_ids = partner_ids.iter().map(|id| id.to_lowercase()).collect();
+                    in_memory_dao.put(user_id.to_lowercase(), partner_ids);
+                }
+                let dao = InMemoryAuthorizedPartnersByUserDao { in_memory_dao };
+                Ok(Box::new(dao))
+            }
+            "file" | "aerospike" => {
+                let consent_log = ConsentLogBuilder::build(config, connector)?;
+                let dao = ConsentLogAuthorizedPartnersByUserDao { consent_log };
                 Ok(Box::new(dao))
             }
             unknown_connector => Err(Error::UnknownConnector(unknown_connector.into())),
         }
     }
+
+    // Listing and signed consent, when the ABI of the contract has them
+    fn build_management<'a>(
+        config: &Config,
+    ) -> Result<Option<EthereumConsentManagement<'a>>, Error> {
+        let contract_key = "data.authorized_partners_by_user.contract";
+        let events = EthereumEventsBuilder::build(config, contract_key, "Authorization");
+        let nonces = EthereumDaoBuilder::build(config, contract_key, "nonces");
+        let (events, nonces) = match (events, nonces) {
+            (Ok(events), Ok(nonces)) => (events, nonces),
+            (Err(EthereumDaoError::Creation(_)), _) | (_, Err(EthereumDaoError::Creation(_))) => {
+                return Ok(None)
+            }
+            (Err(error), _) | (_, Err(error)) => return Err(Error::Contract(error)),
+        };
+        let relay = match config.get_str("ethereum.relayer.key") {
+            Ok(_) => Some(EthereumConsentRelay {
+                relay: EthereumRelayBuilder::build(config, contract_key, "setAuthorizationBySig")?,
+                users: EthereumDaoBuilder::build(config, contract_key, "users")?,
+                partners: EthereumDaoBuilder::build(config, contract_key, "partners")?,
+                relaying: Mutex::new(HashSet::new()),
+            }),
+            Err(error) if error.is_not_found() => None,
+            Err(error) => return Err(Error::Config(error)),
+        };
+        Ok(Some(EthereumConsentManagement {
+            events,
+            nonces,
+            relay,
+        }))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Error;
-    use crate::ucdp::dal::authorized_partners_by_user::EthereumAuthorizedPartnersByUserDao;
-    use crate::ucdp::dal::ethereum_dao::{EthereumDao, EthereumDaoError};
+    use crate::ucdp::consent::{Consent, SignedConsent};
+    use crate::ucdp::dal::authorized_partners_by_user::{
+        EthereumAuthorizedPartnersByUserDao, EthereumConsentManagement, EthereumConsentRelay,
+    };
+    use crate::ucdp::dal::ethereum_dao::{
+        EthereumBlocks, EthereumDao, EthereumDaoError, EthereumEvents, EthereumRelay,
+    };
     use crate::ucdp::dal::{AuthorizedPartnersByUserBuilder, AuthorizedPartnersByUserDao};
     use async_trait::async_trait;
     use ucdp::config::Config;
+    use ucdp::stream::events::ConsentBlock;
+    use web3::ethabi::{Log, LogParam, Token};
+    use web3::types::{Address, BlockId, H256, U256};
 
     #[test]
     fn authorized_partners_by_user_builder_build_ok() {
@@ -80,12 +701,112 @@ mod tests {
             "ethereum.contract",
             "0x0000000000000000000000000000000000000000",
         );
+        let _ = config.set("ethereum.from_block", 0);
         let config = Config::from(config);
 
         let res = AuthorizedPartnersByUserBuilder::build(&config);
         assert!(res.is_ok())
     }
 
+    // Authorization events are not replayed from the genesis block
+    #[test]
+    fn authorized_partners_by_user_builder_err_from_block() {
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "ethereum");
+        let _ = config.set("ethereum.network", "http://ethereum");
+        let _ = config.set(
+            "ethereum.contract",
+            "0x0000000000000000000000000000000000000000",
+        );
+        let config = Config::from(config);
+
+        let mut validation = ucdp::config::Validation::new(&config);
+        AuthorizedPartnersByUserBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => {
+                assert_eq!(problems, vec!["ethereum.from_block: missing"])
+            }
+            _ => unreachable!(),
+        }
+        match AuthorizedPartnersByUserBuilder::build(&config) {
+            Err(Error::Contract(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn authorized_partners_by_user_builder_validate_confirmations() {
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "ethereum");
+        let _ = config.set("data.authorized_partners_by_user.confirmations", -1);
+        let _ = config.set("ethereum.network", "http://ethereum");
+        let _ = config.set(
+            "ethereum.contract",
+            "0x0000000000000000000000000000000000000000",
+        );
+        let _ = config.set("ethereum.from_block", 0);
+        let config = Config::from(config);
+
+        let mut validation = ucdp::config::Validation::new(&config);
+        AuthorizedPartnersByUserBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => assert_eq!(
+                problems,
+                vec!["data.authorized_partners_by_user.confirmations: negative confirmations"]
+            ),
+            _ => unreachable!(),
+        }
+        match AuthorizedPartnersByUserBuilder::build(&config) {
+            Err(Error::Config(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_consent_log() {
+        let path = std::env::temp_dir().join(format!("{}.log", uuid::Uuid::new_v4()));
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "file");
+        let _ = config.set(
+            "consent_log.key",
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        );
+        let _ = config.set("consent_log.path", path.to_str().unwrap());
+        let config = Config::from(config);
+
+        let mut validation = ucdp::config::Validation::new(&config);
+        AuthorizedPartnersByUserBuilder::validate(&mut validation);
+        assert!(validation.finish().is_ok());
+
+        let dao = AuthorizedPartnersByUserBuilder::build(&config).unwrap();
+        dao.set_authorized("0xABC", "0xDEF", true).await.unwrap();
+        assert!(dao.is_authorized("0xabc", "0xdef").await.unwrap());
+        dao.set_authorized("0xabc", "0xdef", false).await.unwrap();
+        assert!(!dao.is_authorized("0xABC", "0xDEF").await.unwrap());
+        let _ = std::fs::remove_file(format!("{}.head", path.to_str().unwrap()));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_unsupported() {
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(OptionTestEthereumDao { value: Some(true) }),
+            confirmed_blocks: None,
+            management: None,
+        };
+        match dao.set_authorized("0x123", "0x456", true).await {
+            Err(Error::Unsupported(_)) => (),
+            _ => unreachable!(),
+        }
+        match dao
+            .authorized_partners("0x0000000000000000000000000000000000000123")
+            .await
+        {
+            Err(Error::Unsupported(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn authorized_partners_by_user_builder_build_err_missing_connector() {
         let config = config::Config::default();
@@ -123,7 +844,12 @@ mod tests {
         async fn get(
             &self,
             _: (web3::types::Address, web3::types::Address),
+            block: Option<web3::types::BlockId>,
         ) -> Result<bool, EthereumDaoError> {
+            // Authorized at block 42 only
+            if let Some(block) = block {
+                return Ok(block == BlockId::Hash(H256::from_low_u64_be(42)));
+            }
             self.value.ok_or_else(|| {
                 EthereumDaoError::Execution(web3::contract::Error::InvalidOutputType(
                     "error".into(),
@@ -137,6 +863,8 @@ mod tests {
         let ethereum_dao = OptionTestEthereumDao { value: Some(true) };
         let dao = EthereumAuthorizedPartnersByUserDao {
             ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: None,
+            management: None,
         };
         let authorized_partners_by_user = Box::new(dao);
 
@@ -149,11 +877,89 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    // Latest block is 50
+    struct TestEthereumBlocks {}
+
+    #[async_trait]
+    impl EthereumBlocks for TestEthereumBlocks {
+        async fn confirmed(&self, confirmations: u64) -> Result<ConsentBlock, EthereumDaoError> {
+            Ok(ConsentBlock {
+                number: 50 - confirmations,
+                hash: format!("{:?}", H256::from_low_u64_be(50 - confirmations)),
+            })
+        }
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_authorization_confirmed_block() {
+        let ethereum_dao = OptionTestEthereumDao { value: Some(false) };
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: Some((Box::new(TestEthereumBlocks {}), 8)),
+            management: None,
+        };
+
+        let authorization = dao
+            .authorization(
+                "0x0000000000000000000000000000000000000123",
+                "0x0000000000000000000000000000000000000456",
+            )
+            .await
+            .unwrap();
+        assert!(authorization.authorized);
+        assert_eq!(
+            authorization.block,
+            Some(ConsentBlock {
+                number: 42,
+                hash: format!("{:?}", H256::from_low_u64_be(42))
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_authorizations() {
+        let ethereum_dao = OptionTestEthereumDao { value: Some(false) };
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: Some((Box::new(TestEthereumBlocks {}), 8)),
+            management: None,
+        };
+
+        let authorizations = dao
+            .authorizations(
+                "0x0000000000000000000000000000000000000123",
+                &[
+                    "0x0000000000000000000000000000000000000456",
+                    "0x0000000000000000000000000000000000000789",
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(authorizations.len(), 2);
+        assert!(authorizations
+            .iter()
+            .all(|authorization| authorization.authorized
+                && authorization.block.as_ref().map(|block| block.number) == Some(42)));
+
+        match dao
+            .authorizations(
+                "0x0000000000000000000000000000000000000123",
+                &["0x0000000000000000000000000000000000000456", "0x789"],
+            )
+            .await
+        {
+            Err(Error::Parameter(parameter)) => assert_eq!(parameter, "partner_id"),
+            _ => unreachable!(),
+        }
+    }
+
     #[actix_rt::test]
     async fn authorized_partners_by_user_is_authorized_err_contract() {
         let ethereum_dao = OptionTestEthereumDao { value: None };
         let dao = EthereumAuthorizedPartnersByUserDao {
             ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: None,
+            management: None,
         };
         let authorized_partners_by_user = Box::new(dao);
 
@@ -174,6 +980,8 @@ mod tests {
         let ethereum_dao = OptionTestEthereumDao { value: Some(true) };
         let dao = EthereumAuthorizedPartnersByUserDao {
             ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: None,
+            management: None,
         };
         let authorized_partners_by_user = Box::new(dao);
 
@@ -195,6 +1003,8 @@ mod tests {
         let ethereum_dao = OptionTestEthereumDao { value: Some(true) };
         let dao = EthereumAuthorizedPartnersByUserDao {
             ethereum_dao: Box::new(ethereum_dao),
+            confirmed_blocks: None,
+            management: None,
         };
         let authorized_partners_by_user = Box::new(dao);
 
@@ -210,4 +1020,222 @@ mod tests {
             unreachable!();
         }
     }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_in_memory_seeded() {
+        let path = std::env::temp_dir().join("ucdp-authorized-partners-by-user-seed.json");
+        std::fs::write(
+            &path,
+            r#"{"authorized_partners_by_user": {"0xABC": ["0xDEF"]}}"#,
+        )
+        .unwrap();
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "in-memory");
+        let _ = config.set("in_memory.seed", path.to_str().unwrap());
+        let _ = config.set("in_memory.ttl", 0);
+        let config = Config::from(config);
+
+        let dao = AuthorizedPartnersByUserBuilder::build(&config).unwrap();
+        assert!(dao.is_authorized("0xabc", "0xdef").await.unwrap());
+        assert!(!dao.is_authorized("0xabc", "0x123").await.unwrap());
+        assert!(!dao.is_authorized("0x123", "0xdef").await.unwrap());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    fn signed_consent(partner: u64, authorized: bool, nonce: u64) -> SignedConsent {
+        SignedConsent {
+            consent: Consent {
+                user: Address::from_low_u64_be(0xabc),
+                partner: Address::from_low_u64_be(partner),
+                authorized,
+                nonce,
+                deadline: 0,
+            },
+            signature: [0; 65],
+        }
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_in_memory_apply_consent() {
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "in-memory");
+        let _ = config.set("in_memory.ttl", 0);
+        let config = Config::from(config);
+        let dao = AuthorizedPartnersByUserBuilder::build(&config).unwrap();
+        let user_id = "0x0000000000000000000000000000000000000ABC";
+
+        // Nonces would not survive a restart
+        match dao.nonce(user_id).await {
+            Err(Error::Unsupported(feature)) => assert_eq!(feature, "signed consent"),
+            _ => unreachable!(),
+        }
+        match dao.apply_consent(&signed_consent(0xdef, true, 0)).await {
+            Err(Error::Unsupported(feature)) => assert_eq!(feature, "signed consent"),
+            _ => unreachable!(),
+        }
+        assert!(dao.authorized_partners(user_id).await.unwrap().is_empty());
+    }
+
+    // The user authorizes 0x456 and 0x789, then revokes 0x456, up to the requested block
+    struct TestEthereumEvents {}
+
+    #[async_trait]
+    impl EthereumEvents for TestEthereumEvents {
+        async fn logs(
+            &self,
+            _: Address,
+            block: Option<BlockId>,
+        ) -> Result<Vec<Log>, EthereumDaoError> {
+            let log = |partner: u64, authorized: bool| Log {
+                params: vec![
+                    LogParam {
+                        name: "partner".into(),
+                        value: Token::Address(Address::from_low_u64_be(partner)),
+                    },
+                    LogParam {
+                        name: "authorized".into(),
+                        value: Token::Bool(authorized),
+                    },
+                ],
+            };
+            let mut logs = vec![log(0x456, true), log(0x789, true)];
+            if block.is_none() {
+                logs.push(log(0x456, false));
+            }
+            Ok(logs)
+        }
+    }
+
+    struct TestNonces {}
+
+    #[async_trait]
+    impl<'a> EthereumDao<'a, Address, U256> for TestNonces {
+        async fn get(&self, _: Address, _: Option<BlockId>) -> Result<U256, EthereumDaoError> {
+            Ok(3.into())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_ethereum_management() {
+        let management = || {
+            Some(EthereumConsentManagement {
+                events: Box::new(TestEthereumEvents {}),
+                nonces: Box::new(TestNonces {}),
+                relay: None,
+            })
+        };
+        let user_id = "0x0000000000000000000000000000000000000123";
+
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(OptionTestEthereumDao { value: Some(true) }),
+            confirmed_blocks: None,
+            management: management(),
+        };
+        assert_eq!(
+            dao.authorized_partners(user_id).await.unwrap(),
+            vec!["0x0000000000000000000000000000000000000789"]
+        );
+        assert_eq!(dao.nonce(user_id).await.unwrap(), 3);
+        // Not relayed without a key to pay for the transaction
+        match dao.apply_consent(&signed_consent(0x456, true, 3)).await {
+            Err(Error::Unsupported(_)) => (),
+            _ => unreachable!(),
+        }
+
+        // Revocation is not confirmed yet
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(OptionTestEthereumDao { value: Some(true) }),
+            confirmed_blocks: Some((Box::new(TestEthereumBlocks {}), 8)),
+            management: management(),
+        };
+        assert_eq!(dao.authorized_partners(user_id).await.unwrap().len(), 2);
+    }
+
+    struct TestRelay {}
+
+    #[async_trait]
+    impl EthereumRelay for TestRelay {
+        async fn send(&self, _: Vec<Token>) -> Result<H256, EthereumDaoError> {
+            actix_rt::time::sleep(std::time::Duration::from_millis(10)).await;
+            Ok(H256::from_low_u64_be(1))
+        }
+    }
+
+    // Registered below 0x1000
+    struct TestRegistrations {}
+
+    #[async_trait]
+    impl<'a> EthereumDao<'a, (Address,), (Vec<u8>, bool)> for TestRegistrations {
+        async fn get(
+            &self,
+            (user,): (Address,),
+            _: Option<BlockId>,
+        ) -> Result<(Vec<u8>, bool), EthereumDaoError> {
+            Ok((vec![], user.to_low_u64_be() < 0x1000))
+        }
+    }
+
+    #[async_trait]
+    impl<'a> EthereumDao<'a, (Address,), (Vec<u8>, bool, bool)> for TestRegistrations {
+        async fn get(
+            &self,
+            (partner,): (Address,),
+            _: Option<BlockId>,
+        ) -> Result<(Vec<u8>, bool, bool), EthereumDaoError> {
+            Ok((vec![], true, partner.to_low_u64_be() < 0x1000))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn authorized_partners_by_user_ethereum_relay() {
+        let dao = EthereumAuthorizedPartnersByUserDao {
+            ethereum_dao: Box::new(OptionTestEthereumDao { value: Some(true) }),
+            confirmed_blocks: None,
+            management: Some(EthereumConsentManagement {
+                events: Box::new(TestEthereumEvents {}),
+                nonces: Box::new(TestNonces {}),
+                relay: Some(EthereumConsentRelay {
+                    relay: Box::new(TestRelay {}),
+                    users: Box::new(TestRegistrations {}),
+                    partners: Box::new(TestRegistrations {}),
+                    relaying: Default::default(),
+                }),
+            }),
+        };
+        assert_eq!(
+            dao.apply_consent(&signed_consent(0x456, true, 3))
+                .await
+                .unwrap(),
+            Some(H256::from_low_u64_be(1))
+        );
+
+        // Not paid for, the transaction would revert
+        match dao.apply_consent(&signed_consent(0x4560, true, 3)).await {
+            Err(Error::NotRegistered(who)) => assert_eq!(who, "partner"),
+            _ => unreachable!(),
+        }
+        let mut signed = signed_consent(0x456, true, 3);
+        signed.consent.user = Address::from_low_u64_be(0xabc0);
+        match dao.apply_consent(&signed).await {
+            Err(Error::NotRegistered(who)) => assert_eq!(who, "user"),
+            _ => unreachable!(),
+        }
+        match dao.apply_consent(&signed_consent(0x456, true, 2)).await {
+            Err(Error::Nonce(nonce)) => assert_eq!(nonce, 3),
+            _ => unreachable!(),
+        }
+
+        // The same nonce relayed once at a time
+        let (grant, revoke) = (
+            signed_consent(0x456, true, 3),
+            signed_consent(0x789, false, 3),
+        );
+        let (first, second) = futures::join!(dao.apply_consent(&grant), dao.apply_consent(&revoke));
+        assert!(first.is_ok());
+        match second {
+            Err(Error::Nonce(nonce)) => assert_eq!(nonce, 4),
+            _ => unreachable!(),
+        }
+    }
 }
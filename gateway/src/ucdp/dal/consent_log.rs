@@ -0,0 +1,837 @@
+use crate::ucdp::dal::aerospike_dao::{
+    self, AerospikeDao, AerospikeDaoBuilder, AerospikeDaoError, Expect,
+};
+use crate::ucdp::dal::ethereum_dao::get_secret_key;
+use async_trait::async_trait;
+use futures::lock::Mutex as AsyncMutex;
+use log::{info, trace, warn};
+use secp256k1::SecretKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use thiserror::Error;
+use ucdp::config::{Config, Validation};
+use web3::signing::{keccak256, recover, Key, SecretKeyRef};
+use web3::types::{Address, Bytes, H256};
+
+#[derive(Error, Debug)]
+pub enum ConsentLogError {
+    #[error("config error")]
+    Config(#[from] ucdp::config::Error),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("serialization error")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("aerospike error")]
+    Aerospike(#[from] AerospikeDaoError),
+
+    #[error("signing error: {0}")]
+    Signing(String),
+
+    #[error("consent log of {0} has been tampered with: {1}")]
+    Tampered(String, String),
+
+    #[error("next entry is {0}")]
+    Seq(u64),
+
+    #[error("unknown store: {0}")]
+    UnknownStore(String),
+}
+
+// A change of consent of a user, chained to the previous change by its hash and signed
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConsentEntry {
+    pub user_id: String,
+    // 0 for the first entry of the user
+    pub seq: u64,
+    pub partner_id: String,
+    pub authorized: bool,
+    // Milliseconds since the epoch
+    pub timestamp: u64,
+    // Partners authorized by the user once the change is applied
+    pub partners: Vec<String>,
+    // Zero for the first entry of the user
+    pub previous: H256,
+    pub hash: H256,
+    pub signature: Bytes,
+}
+
+impl ConsentEntry {
+    fn digest(&self) -> H256 {
+        let content = (
+            &self.user_id,
+            self.seq,
+            &self.partner_id,
+            self.authorized,
+            self.timestamp,
+            &self.partners,
+            &self.previous,
+        );
+        // Tuples serialize as arrays, the same content gives the same bytes
+        H256(keccak256(&serde_json::to_vec(&content).unwrap_or_default()))
+    }
+}
+
+// Where the entries are kept, appended to and never changed
+#[async_trait]
+pub trait ConsentStore: Send + Sync {
+    async fn head(&self, user_id: &str) -> Result<Option<ConsentEntry>, ConsentLogError>;
+    async fn entry(&self, user_id: &str, seq: u64)
+        -> Result<Option<ConsentEntry>, ConsentLogError>;
+    async fn append(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError>;
+    // Make the entry the head when it follows the current head, for an append cut short
+    async fn advance_head(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError>;
+}
+
+// Sequence number of the entry that follows the head
+fn next_seq(head: Option<&ConsentEntry>) -> u64 {
+    head.map(|head| head.seq + 1).unwrap_or(0)
+}
+
+// Entries as JSON lines of a file, indexed in memory. The latest entry of each user is also
+// appended to a second file, the last one of the user wins, so that lines cut off the log are noticed.
+struct FileConsentStore {
+    file: Arc<Mutex<std::fs::File>>,
+    head_file: Arc<Mutex<std::fs::File>>,
+    entries: Mutex<HashMap<String, Vec<ConsentEntry>>>,
+    heads: Mutex<HashMap<String, ConsentEntry>>,
+}
+
+impl FileConsentStore {
+    fn open(path: &str) -> Result<Self, ConsentLogError> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)?;
+        let mut entries: HashMap<String, Vec<ConsentEntry>> = HashMap::new();
+        for line in BufReader::new(&file).lines() {
+            let entry: ConsentEntry = serde_json::from_str(&line?)?;
+            entries
+                .entry(entry.user_id.clone())
+                .or_default()
+                .push(entry);
+        }
+        // No heads at all for a log that has entries is told by the reads
+        let mut head_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(format!("{}.head", path))?;
+        let mut bytes = vec![];
+        head_file.read_to_end(&mut bytes)?;
+        // A head cut short is dropped, the reads move the head to its entry again
+        let complete = bytes
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map(|position| position + 1)
+            .unwrap_or(0);
+        if complete < bytes.len() {
+            warn!("Dropping the last head of {}, cut short", path);
+            head_file.set_len(complete as u64)?;
+        }
+        let mut heads = HashMap::new();
+        for line in bytes[..complete].split(|byte| *byte == b'\n') {
+            if !line.is_empty() {
+                let head: ConsentEntry = serde_json::from_slice(line)?;
+                heads.insert(head.user_id.clone(), head);
+            }
+        }
+        Ok(FileConsentStore {
+            file: Arc::new(Mutex::new(file)),
+            head_file: Arc::new(Mutex::new(head_file)),
+            entries: Mutex::new(entries),
+            heads: Mutex::new(heads),
+        })
+    }
+}
+
+// Append the line and sync it, away from the executor
+async fn append_line(
+    file: &Arc<Mutex<std::fs::File>>,
+    entry: &ConsentEntry,
+) -> Result<(), ConsentLogError> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let file = file.clone();
+    actix_web::web::block(move || {
+        let mut file = file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()
+    })
+    .await
+    .map_err(|error| std::io::Error::other(error.to_string()))??;
+    Ok(())
+}
+
+#[async_trait]
+impl ConsentStore for FileConsentStore {
+    async fn head(&self, user_id: &str) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        Ok(self.heads.lock().unwrap().get(user_id).cloned())
+    }
+
+    async fn entry(
+        &self,
+        user_id: &str,
+        seq: u64,
+    ) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries
+            .get(user_id)
+            .and_then(|entries| entries.get(seq as usize).cloned()))
+    }
+
+    async fn append(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError> {
+        append_line(&self.file, entry).await?;
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(entry.user_id.clone())
+            .or_default()
+            .push(entry.clone());
+
+        // The head is replaced once the entry is in the log
+        self.advance_head(entry).await
+    }
+
+    async fn advance_head(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError> {
+        {
+            let heads = self.heads.lock().unwrap();
+            let head = heads.get(&entry.user_id);
+            if head == Some(entry) {
+                return Ok(());
+            }
+            if entry.seq != next_seq(head) {
+                return Err(ConsentLogError::Seq(next_seq(head)));
+            }
+        }
+        append_line(&self.head_file, entry).await?;
+        self.heads
+            .lock()
+            .unwrap()
+            .insert(entry.user_id.clone(), entry.clone());
+        Ok(())
+    }
+}
+
+// Entries as records of Aerospike, the latest one of a user is also kept as its head
+struct AerospikeConsentStore {
+    aerospike_dao: Box<dyn AerospikeDao>,
+}
+
+impl AerospikeConsentStore {
+    async fn get(&self, key: &str) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        match self.aerospike_dao.get(key).await?.value {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ConsentStore for AerospikeConsentStore {
+    async fn head(&self, user_id: &str) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        self.get(&format!("consent/{}", user_id)).await
+    }
+
+    async fn entry(
+        &self,
+        user_id: &str,
+        seq: u64,
+    ) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        self.get(&format!("consent/{}/{}", user_id, seq)).await
+    }
+
+    async fn append(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError> {
+        let next_seq = next_seq(self.head(&entry.user_id).await?.as_ref());
+        if entry.seq != next_seq {
+            return Err(ConsentLogError::Seq(next_seq));
+        }
+
+        // Another gateway has appended the same entry first
+        self.aerospike_dao
+            .put_if(
+                &format!("consent/{}/{}", entry.user_id, entry.seq),
+                serde_json::to_vec(entry)?,
+                Expect::Absent,
+            )
+            .await
+            .map_err(|error| match error {
+                AerospikeDaoError::Conflict => ConsentLogError::Seq(entry.seq + 1),
+                error => ConsentLogError::Aerospike(error),
+            })?;
+        self.advance_head(entry).await
+    }
+
+    async fn advance_head(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError> {
+        let head_key = format!("consent/{}", entry.user_id);
+        let record = self.aerospike_dao.get(&head_key).await?;
+        let head = match &record.value {
+            Some(bytes) => Some(serde_json::from_slice::<ConsentEntry>(bytes)?),
+            None => None,
+        };
+        if head.as_ref() == Some(entry) {
+            return Ok(());
+        }
+        if entry.seq != next_seq(head.as_ref()) {
+            return Err(ConsentLogError::Seq(next_seq(head.as_ref())));
+        }
+        let res = self
+            .aerospike_dao
+            .put_if(
+                &head_key,
+                serde_json::to_vec(entry)?,
+                Expect::generation(record.generation),
+            )
+            .await;
+        match res {
+            // Moved by a read of another gateway in the meantime
+            Err(AerospikeDaoError::Conflict)
+                if self.head(&entry.user_id).await?.as_ref() == Some(entry) =>
+            {
+                Ok(())
+            }
+            Err(AerospikeDaoError::Conflict) => Err(ConsentLogError::Seq(entry.seq + 1)),
+            res => Ok(res?),
+        }
+    }
+}
+
+// Consent of users as a log of signed entries per user.
+// An entry changed without the key no longer matches its signature, an entry removed or
+// replaced breaks the chain of hashes that verify walks, and a head that is not the latest
+// entry of the chain is refused by the reads.
+pub struct ConsentLog {
+    store: Box<dyn ConsentStore>,
+    key: SecretKey,
+    signer: Address,
+    // Entries of a user are appended one at a time
+    appending: AsyncMutex<()>,
+}
+
+impl ConsentLog {
+    // Partners authorized by the user according to the latest entry
+    pub async fn authorized_partners(&self, user_id: &str) -> Result<Vec<String>, ConsentLogError> {
+        Ok(self
+            .head(user_id)
+            .await?
+            .map(|head| head.partners)
+            .unwrap_or_default())
+    }
+
+    // Sequence number of the next entry of the user
+    pub async fn next_seq(&self, user_id: &str) -> Result<u64, ConsentLogError> {
+        Ok(self
+            .head(user_id)
+            .await?
+            .map(|head| head.seq + 1)
+            .unwrap_or(0))
+    }
+
+    // Head of the user, when it is the latest entry of the chain. A head rolled back to an
+    // older entry, or entries cut off after it, would undo the latest changes.
+    // The one entry written by an append cut short before the head becomes the head.
+    async fn head(&self, user_id: &str) -> Result<Option<ConsentEntry>, ConsentLogError> {
+        let tampered = |reason: &str| ConsentLogError::Tampered(user_id.into(), reason.into());
+        let head = self.store.head(user_id).await?;
+        let next_seq = match &head {
+            Some(head) => {
+                self.check(head)?;
+                match self.store.entry(user_id, head.seq).await? {
+                    Some(entry) => {
+                        self.check(&entry)?;
+                        if entry != *head {
+                            return Err(tampered("head is not in the chain"));
+                        }
+                    }
+                    None => return Err(tampered("head is not in the chain")),
+                }
+                head.seq + 1
+            }
+            None => 0,
+        };
+        let next = match self.store.entry(user_id, next_seq).await? {
+            Some(next) => next,
+            None => return Ok(head),
+        };
+        self.check(&next)?;
+        let previous = head.map(|head| head.hash).unwrap_or_default();
+        if next.seq != next_seq
+            || next.previous != previous
+            || self.store.entry(user_id, next_seq + 1).await?.is_some()
+        {
+            return Err(tampered("head is not the latest entry"));
+        }
+        warn!(
+            "Head of the consent log of {} moved to entry {}, appending stopped halfway",
+            user_id, next_seq
+        );
+        self.store.advance_head(&next).await?;
+        Ok(Some(next))
+    }
+
+    // Append the entry, as entry seq when given
+    pub async fn append(
+        &self,
+        user_id: &str,
+        partner_id: &str,
+        authorized: bool,
+        seq: Option<u64>,
+    ) -> Result<ConsentEntry, ConsentLogError> {
+        let _appending = self.appending.lock().await;
+        // A head that is not the latest entry is not extended, that would hide it.
+        // The new entry is chained to the head, the chain before it is not walked again.
+        let head = self.head(user_id).await?;
+        let next_seq = next_seq(head.as_ref());
+        if seq.unwrap_or(next_seq) != next_seq {
+            return Err(ConsentLogError::Seq(next_seq));
+        }
+
+        let mut partners = head
+            .as_ref()
+            .map(|head| head.partners.clone())
+            .unwrap_or_default();
+        partners.retain(|id| id != partner_id);
+        if authorized {
+            partners.push(partner_id.into());
+            partners.sort();
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        let mut entry = ConsentEntry {
+            user_id: user_id.into(),
+            seq: next_seq,
+            partner_id: partner_id.into(),
+            authorized,
+            timestamp,
+            partners,
+            previous: head.map(|head| head.hash).unwrap_or_default(),
+            hash: H256::zero(),
+            signature: Bytes::default(),
+        };
+        entry.hash = entry.digest();
+        let signature = SecretKeyRef::new(&self.key)
+            .sign(entry.hash.as_bytes(), None)
+            .map_err(|error| ConsentLogError::Signing(error.to_string()))?;
+        let mut bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+        bytes.push(signature.v as u8);
+        entry.signature = Bytes(bytes);
+
+        trace!("append {:?}", entry);
+        self.store.append(&entry).await?;
+        Ok(entry)
+    }
+
+    // Check the whole log of the user, the number of entries when it is intact
+    #[cfg(test)]
+    pub async fn verify(&self, user_id: &str) -> Result<u64, ConsentLogError> {
+        let tampered = |reason: String| ConsentLogError::Tampered(user_id.into(), reason);
+        let mut previous: Option<ConsentEntry> = None;
+        let mut seq = 0;
+        while let Some(entry) = self.store.entry(user_id, seq).await? {
+            self.check(&entry)?;
+            let expected = previous.as_ref().map(|previous| previous.hash);
+            if entry.seq != seq || entry.previous != expected.unwrap_or_default() {
+                return Err(tampered(format!("entry {} is out of the chain", seq)));
+            }
+            previous = Some(entry);
+            seq += 1;
+        }
+        if self.store.head(user_id).await? != previous {
+            return Err(tampered("latest entry is not the head".into()));
+        }
+        Ok(seq)
+    }
+
+    // The entry is the one that was signed
+    fn check(&self, entry: &ConsentEntry) -> Result<(), ConsentLogError> {
+        let tampered = |reason: String| ConsentLogError::Tampered(entry.user_id.clone(), reason);
+        if entry.digest() != entry.hash {
+            return Err(tampered(format!(
+                "entry {} does not match its hash",
+                entry.seq
+            )));
+        }
+        let signature = &entry.signature.0;
+        let signer = match signature.len() {
+            65 => recover(
+                entry.hash.as_bytes(),
+                &signature[..64],
+                signature[64] as i32 - 27,
+            )
+            .ok(),
+            _ => None,
+        };
+        if signer != Some(self.signer) {
+            return Err(tampered(format!(
+                "entry {} is not signed by {:?}",
+                entry.seq, self.signer
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn validate(validation: &mut Validation, store: &str) {
+    validation.required("consent_log.key", get_secret_key);
+    match store {
+        "file" => {
+            validation.required("consent_log.path", Config::get_str);
+        }
+        "aerospike" => aerospike_dao::validate(validation),
+        _ => (),
+    }
+}
+
+pub struct ConsentLogBuilder {}
+
+impl ConsentLogBuilder {
+    pub fn build(config: &Config, store: &str) -> Result<ConsentLog, ConsentLogError> {
+        let key = get_secret_key(config, "consent_log.key")?;
+        let store: Box<dyn ConsentStore> = match store {
+            "file" => Box::new(FileConsentStore::open(
+                &config.get_str("consent_log.path")?,
+            )?),
+            "aerospike" => Box::new(AerospikeConsentStore {
+                aerospike_dao: AerospikeDaoBuilder::build(config)?,
+            }),
+            unknown_store => return Err(ConsentLogError::UnknownStore(unknown_store.into())),
+        };
+        let signer = SecretKeyRef::new(&key).address();
+        info!("Consent log entries are signed by {:?}", signer);
+        Ok(ConsentLog {
+            store,
+            signer,
+            key,
+            appending: AsyncMutex::new(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AerospikeConsentStore, ConsentLog, ConsentLogBuilder, ConsentLogError};
+    use crate::ucdp::dal::aerospike_dao::{
+        AerospikeDao, AerospikeDaoError, AerospikeDaoResult, Expect,
+    };
+    use async_trait::async_trait;
+    use futures::lock::Mutex as AsyncMutex;
+    use secp256k1::SecretKey;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use ucdp::config::{Config, Validation};
+    use web3::signing::{Key, SecretKeyRef};
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn config(path: &std::path::Path, key: &str) -> Config {
+        let mut config = config::Config::default();
+        let _ = config.set("consent_log.key", key);
+        let _ = config.set("consent_log.path", path.to_str().unwrap());
+        Config::from(config)
+    }
+
+    #[actix_rt::test]
+    async fn consent_log_file() {
+        let path = std::env::temp_dir().join(format!("{}.log", uuid::Uuid::new_v4()));
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        assert_eq!(
+            format!("{:?}", log.signer),
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+        );
+
+        log.append("0x123", "0x456", true, None).await.unwrap();
+        log.append("0x123", "0x789", true, Some(1)).await.unwrap();
+        log.append("0x123", "0x456", false, None).await.unwrap();
+        assert_eq!(
+            log.authorized_partners("0x123").await.unwrap(),
+            vec!["0x789"]
+        );
+        assert_eq!(log.next_seq("0x123").await.unwrap(), 3);
+        match log.append("0x123", "0x456", true, Some(1)).await {
+            Err(ConsentLogError::Seq(seq)) => assert_eq!(seq, 3),
+            _ => unreachable!(),
+        }
+        assert!(log.authorized_partners("0xabc").await.unwrap().is_empty());
+        assert_eq!(log.verify("0x123").await.unwrap(), 3);
+
+        // Read back from the file
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        assert_eq!(
+            log.authorized_partners("0x123").await.unwrap(),
+            vec!["0x789"]
+        );
+        assert_eq!(log.verify("0x123").await.unwrap(), 3);
+
+        // Stopped between the entry and the head
+        let head_path = format!("{}.head", path.to_str().unwrap());
+        let heads = std::fs::read(&head_path).unwrap();
+        log.append("0x123", "0xabc", true, None).await.unwrap();
+        std::fs::write(&head_path, heads).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        assert_eq!(
+            log.authorized_partners("0x123").await.unwrap(),
+            vec!["0x789", "0xabc"]
+        );
+        assert_eq!(log.verify("0x123").await.unwrap(), 4);
+
+        // Stopped while writing the head
+        let mut heads = std::fs::read(&head_path).unwrap();
+        heads.extend_from_slice(br#"{"user_id":"0x1"#);
+        std::fs::write(&head_path, heads).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        log.append("0x123", "0xabc", false, None).await.unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        assert_eq!(log.verify("0x123").await.unwrap(), 5);
+        remove(&path);
+    }
+
+    #[actix_rt::test]
+    async fn consent_log_tampered() {
+        let path = std::env::temp_dir().join(format!("{}.log", uuid::Uuid::new_v4()));
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        log.append("0x123", "0x456", true, None).await.unwrap();
+        log.append("0x123", "0x456", false, None).await.unwrap();
+        log.append("0x123", "0x789", true, None).await.unwrap();
+
+        // Authorization granted behind the back of the log
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        let forged = lines[2].replace(r#""partners":["0x789"]"#, r#""partners":["0x456","0x789"]"#);
+        std::fs::write(&path, format!("{}\n{}\n{}\n", lines[0], lines[1], forged)).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        match log.authorized_partners("0x123").await {
+            Err(ConsentLogError::Tampered(user_id, reason)) => {
+                assert_eq!(user_id, "0x123");
+                assert_eq!(reason, "entry 2 does not match its hash");
+            }
+            _ => unreachable!(),
+        }
+
+        // Revocation removed
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        match log.authorized_partners("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "head is not in the chain")
+            }
+            _ => unreachable!(),
+        }
+        match log.verify("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "entry 1 is out of the chain")
+            }
+            _ => unreachable!(),
+        }
+
+        // Entries signed by another key
+        let other = "0x0000000000000000000000000000000000000000000000000000000000000001";
+        let log = ConsentLogBuilder::build(&config(&path, other), "file").unwrap();
+        match log.verify("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert!(reason.starts_with("entry 0 is not signed by"))
+            }
+            _ => unreachable!(),
+        }
+        remove(&path);
+    }
+
+    #[actix_rt::test]
+    async fn consent_log_truncated() {
+        let path = std::env::temp_dir().join(format!("{}.log", uuid::Uuid::new_v4()));
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        log.append("0x123", "0x456", true, None).await.unwrap();
+        log.append("0x123", "0x789", true, None).await.unwrap();
+        log.append("0x123", "0x456", false, None).await.unwrap();
+
+        // Revocation cut off the end of the log
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        match log.authorized_partners("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "head is not in the chain")
+            }
+            _ => unreachable!(),
+        }
+        match log.verify("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "latest entry is not the head")
+            }
+            _ => unreachable!(),
+        }
+        match log.append("0x123", "0x456", true, None).await {
+            Err(ConsentLogError::Tampered(_, _)) => (),
+            _ => unreachable!(),
+        }
+
+        // Heads removed along with the log
+        std::fs::write(&path, content).unwrap();
+        std::fs::remove_file(format!("{}.head", path.to_str().unwrap())).unwrap();
+        let log = ConsentLogBuilder::build(&config(&path, KEY), "file").unwrap();
+        match log.authorized_partners("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "head is not the latest entry")
+            }
+            _ => unreachable!(),
+        }
+        remove(&path);
+    }
+
+    // Values and generations of the records by key
+    type Records = Arc<Mutex<HashMap<String, (Vec<u8>, u32)>>>;
+
+    #[derive(Default)]
+    struct TestAerospikeDao {
+        records: Records,
+    }
+
+    #[async_trait]
+    impl AerospikeDao for TestAerospikeDao {
+        async fn get(&self, key: &str) -> Result<AerospikeDaoResult, AerospikeDaoError> {
+            let records = self.records.lock().unwrap();
+            Ok(AerospikeDaoResult {
+                value: records.get(key).map(|(value, _)| value.clone()),
+                ttl: None,
+                generation: records
+                    .get(key)
+                    .map(|(_, generation)| *generation)
+                    .unwrap_or(0),
+            })
+        }
+
+        async fn put(&self, _: &str, _: Vec<u8>) {
+            unreachable!()
+        }
+
+        async fn put_if(
+            &self,
+            key: &str,
+            value: Vec<u8>,
+            expect: Expect,
+        ) -> Result<(), AerospikeDaoError> {
+            let mut records = self.records.lock().unwrap();
+            let generation = records
+                .get(key)
+                .map(|(_, generation)| *generation)
+                .unwrap_or(0);
+            match expect {
+                Expect::Absent if generation != 0 => return Err(AerospikeDaoError::Conflict),
+                Expect::Generation(expected) if expected != generation => {
+                    return Err(AerospikeDaoError::Conflict)
+                }
+                _ => (),
+            }
+            records.insert(key.into(), (value, generation + 1));
+            Ok(())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn consent_log_aerospike() {
+        let aerospike_dao = TestAerospikeDao::default();
+        let records = aerospike_dao.records.clone();
+        let key = SecretKey::from_str(&KEY[2..]).unwrap();
+        let log = ConsentLog {
+            store: Box::new(AerospikeConsentStore {
+                aerospike_dao: Box::new(aerospike_dao),
+            }),
+            signer: SecretKeyRef::new(&key).address(),
+            key,
+            appending: AsyncMutex::new(()),
+        };
+        log.append("0x123", "0x456", true, None).await.unwrap();
+        log.append("0x123", "0x789", true, None).await.unwrap();
+        let revocation = log.append("0x123", "0x456", false, None).await.unwrap();
+        assert_eq!(
+            log.authorized_partners("0x123").await.unwrap(),
+            vec!["0x789"]
+        );
+        assert_eq!(records.lock().unwrap()["consent/0x123"].1, 3);
+
+        // Entry appended by another gateway in the meantime
+        match log.store.append(&revocation).await {
+            Err(ConsentLogError::Seq(seq)) => assert_eq!(seq, 3),
+            _ => unreachable!(),
+        }
+        let mut next = revocation.clone();
+        next.seq = 3;
+        records
+            .lock()
+            .unwrap()
+            .insert("consent/0x123/3".into(), (vec![], 1));
+        match log.store.append(&next).await {
+            Err(ConsentLogError::Seq(seq)) => assert_eq!(seq, 4),
+            _ => unreachable!(),
+        }
+        records.lock().unwrap().remove("consent/0x123/3");
+
+        // Entry written but not the head, appending stopped halfway
+        let head = records.lock().unwrap()["consent/0x123"].0.clone();
+        let older = records.lock().unwrap()["consent/0x123/1"].0.clone();
+        records
+            .lock()
+            .unwrap()
+            .insert("consent/0x123".into(), (older, 4));
+        assert_eq!(
+            log.authorized_partners("0x123").await.unwrap(),
+            vec!["0x789"]
+        );
+        assert_eq!(records.lock().unwrap()["consent/0x123"], (head, 5));
+        log.append("0x123", "0xabc", true, None).await.unwrap();
+        assert_eq!(log.verify("0x123").await.unwrap(), 4);
+
+        // Head rolled back to an older entry
+        let older = records.lock().unwrap()["consent/0x123/1"].0.clone();
+        records
+            .lock()
+            .unwrap()
+            .insert("consent/0x123".into(), (older, 4));
+        match log.authorized_partners("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "head is not the latest entry")
+            }
+            _ => unreachable!(),
+        }
+        match log.verify("0x123").await {
+            Err(ConsentLogError::Tampered(_, reason)) => {
+                assert_eq!(reason, "latest entry is not the head")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn remove(path: &std::path::Path) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(format!("{}.head", path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn consent_log_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set("consent_log.key", "not a key");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        super::validate(&mut validation, "file");
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "consent_log.key: not a secp256k1 private key",
+                    "consent_log.path: missing"
+                ]
+            ),
+            _ => unreachable!(),
+        }
+    }
+}
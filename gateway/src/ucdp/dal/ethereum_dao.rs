@@ -1,9 +1,39 @@
+use crate::ucdp::dal::ethereum_transport::{self, EthereumTransport};
 use async_trait::async_trait;
+use futures::future::join_all;
+use futures::lock::Mutex as AsyncMutex;
 use log::trace;
+use once_cell::sync::Lazy;
+use secp256k1::SecretKey;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use thiserror::Error;
-use ucdp::config::Config;
+use ucdp::config::{Config, Validation};
+use ucdp::stream::events::ConsentBlock;
 use web3::contract::tokens::{Detokenize, Tokenize};
+use web3::ethabi;
+use web3::signing::{Key, SecretKeyRef};
+use web3::types::{
+    Address, BlockId, BlockNumber, Bytes, CallRequest, FilterBuilder, TransactionId,
+    TransactionParameters, H256, U256,
+};
+
+// ABI of the contracts that do not set one
+static UCDP_ABI: Lazy<Arc<ethabi::Contract>> = Lazy::new(|| {
+    Arc::new(
+        ethabi::Contract::load(&include_bytes!("../../../res/Ucdp.abi.json")[..])
+            .expect("res/Ucdp.abi.json is a valid ABI"),
+    )
+});
+
+// ABI read from a file, with the modification time of the file
+type Abi = (Option<SystemTime>, Arc<ethabi::Contract>);
+
+// ABIs by path, parsed once for all the DAOs, parsed again when the file changes
+static ABIS: Lazy<Mutex<HashMap<String, Abi>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
 #[derive(Error, Debug)]
 pub enum EthereumDaoError {
@@ -21,18 +51,39 @@ pub enum EthereumDaoError {
 
     #[error("network error")]
     Network(#[from] web3::Error),
+
+    #[error("abi error: {0}")]
+    Abi(String),
 }
 
 #[async_trait]
 pub trait EthereumDao<'a, K, R>: Send + Sync {
-    async fn get(&self, key: K) -> Result<R, EthereumDaoError>
+    // Value at the given block, at the latest one when None
+    async fn get(&self, key: K, block: Option<BlockId>) -> Result<R, EthereumDaoError>
     where
         'a: 'async_trait;
+
+    // Values in the order of the keys, in one JSON-RPC batch when ethereum.batch.window is set
+    async fn get_many(
+        &self,
+        keys: Vec<K>,
+        block: Option<BlockId>,
+    ) -> Vec<Result<R, EthereumDaoError>>
+    where
+        K: Send + 'a,
+        R: Send,
+        'a: 'async_trait,
+    {
+        join_all(keys.into_iter().map(|key| self.get(key, block))).await
+    }
 }
 
 // Main implementation of EthereumDao
 struct EthereumDaoImpl {
-    contract: web3::contract::Contract<web3::transports::Http>,
+    eth: web3::api::Eth<EthereumTransport>,
+    address: Address,
+    // Shared with the other DAOs of the same ABI rather than copied into a web3 Contract
+    abi: Arc<ethabi::Contract>,
     function_name: String,
 }
 
@@ -42,21 +93,452 @@ where
     K: Tokenize + Send + 'a + std::fmt::Debug,
     R: Detokenize,
 {
-    async fn get(&self, key: K) -> Result<R, EthereumDaoError>
+    async fn get(&self, key: K, block: Option<BlockId>) -> Result<R, EthereumDaoError>
     where
         'a: 'async_trait,
     {
-        trace!("get {:?}", key);
-        self.contract
-            .query(
-                self.function_name.as_str(),
-                key,
-                None,
-                web3::contract::Options::default(),
-                None,
-            )
+        trace!("get {:?} at {:?}", key, block);
+        // As web3::contract::Contract::query does
+        let function = self
+            .abi
+            .function(&self.function_name)
+            .map_err(web3::contract::Error::Abi)?;
+        let data = function
+            .encode_input(&key.into_tokens())
+            .map_err(web3::contract::Error::Abi)?;
+        let request = CallRequest::builder()
+            .to(self.address)
+            .data(Bytes(data))
+            .build();
+        let output = self
+            .eth
+            .call(request, block)
             .await
-            .map_err(EthereumDaoError::Execution)
+            .map_err(web3::contract::Error::Api)?;
+        let tokens = function
+            .decode_output(&output.0)
+            .map_err(web3::contract::Error::Abi)?;
+        Ok(R::from_tokens(tokens)?)
+    }
+}
+
+// Blocks of the chain the contracts are read at
+#[async_trait]
+pub trait EthereumBlocks: Send + Sync {
+    // The latest block once it has that many confirmations, the latest block with 0
+    async fn confirmed(&self, confirmations: u64) -> Result<ConsentBlock, EthereumDaoError>;
+}
+
+struct EthereumBlocksImpl {
+    web3: web3::Web3<EthereumTransport>,
+}
+
+#[async_trait]
+impl EthereumBlocks for EthereumBlocksImpl {
+    async fn confirmed(&self, confirmations: u64) -> Result<ConsentBlock, EthereumDaoError> {
+        let number = if confirmations == 0 {
+            BlockNumber::Latest
+        } else {
+            let latest = self.web3.eth().block_number().await?.as_u64();
+            BlockNumber::Number(latest.saturating_sub(confirmations).into())
+        };
+        let block = self
+            .web3
+            .eth()
+            .block(BlockId::Number(number))
+            .await?
+            .ok_or_else(|| EthereumDaoError::Parameter(format!("no block {:?}", number)))?;
+        // Pending blocks have neither number nor hash
+        match (block.number, block.hash) {
+            (Some(number), Some(hash)) => Ok(ConsentBlock {
+                number: number.as_u64(),
+                hash: format!("{:?}", hash),
+            }),
+            _ => Err(EthereumDaoError::Parameter(format!(
+                "block {:?} is pending",
+                number
+            ))),
+        }
+    }
+}
+
+pub struct EthereumBlocksBuilder {}
+
+impl EthereumBlocksBuilder {
+    pub fn build(config: &Config) -> Result<Box<dyn EthereumBlocks>, EthereumDaoError> {
+        let web3 = web3::Web3::new(EthereumTransport::shared(config)?);
+        Ok(Box::new(EthereumBlocksImpl { web3 }))
+    }
+}
+
+fn load_abi(path: &str) -> Result<Arc<ethabi::Contract>, EthereumDaoError> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    if let Some((abi_modified, abi)) = ABIS.lock().unwrap().get(path) {
+        if *abi_modified == modified {
+            return Ok(abi.clone());
+        }
+    }
+    // Read without the lock, DAOs loading other ABIs do not wait for this file
+    let file = std::fs::File::open(path)
+        .map_err(|error| EthereumDaoError::Abi(format!("{}: {}", path, error)))?;
+    let abi = Arc::new(ethabi::Contract::load(std::io::BufReader::new(file))?);
+    ABIS.lock()
+        .unwrap()
+        .insert(path.into(), (modified, abi.clone()));
+    Ok(abi)
+}
+
+fn get_abi(config: &Config, key: &str) -> Result<Arc<ethabi::Contract>, ucdp::config::Error> {
+    load_abi(&config.get_str(key)?)
+        .map_err(|error| ucdp::config::Error::Invalid(key.into(), error.to_string()))
+}
+
+// Keys of the contract a DAO reads, either one of ethereum.contracts named by the DAO
+// or ethereum.contract, with the ABI of res/Ucdp.abi.json unless a path is set
+struct ContractKeys {
+    address: String,
+    abi: String,
+    // Block the contract was deployed at, its events are not looked for before it
+    from_block: String,
+}
+
+impl ContractKeys {
+    fn new(name: Option<String>) -> Self {
+        match name {
+            Some(name) => ContractKeys {
+                address: format!("ethereum.contracts.{}.address", name),
+                abi: format!("ethereum.contracts.{}.abi", name),
+                from_block: format!("ethereum.contracts.{}.from_block", name),
+            },
+            None => ContractKeys {
+                address: "ethereum.contract".into(),
+                abi: "ethereum.abi".into(),
+                from_block: "ethereum.from_block".into(),
+            },
+        }
+    }
+
+    fn of(config: &Config, contract_key: &str) -> Self {
+        ContractKeys::new(config.get_str(contract_key).ok())
+    }
+}
+
+// Key of the address of the contract named in contract_key
+pub fn contract_address_key(config: &Config, contract_key: &str) -> String {
+    ContractKeys::of(config, contract_key).address
+}
+
+// Check the contract named in contract_key, and that its ABI has the function the DAO calls
+pub fn validate(validation: &mut Validation, contract_key: &str, function_name: &str) {
+    ethereum_transport::validate(validation);
+    let keys = ContractKeys::new(validation.optional(contract_key, Config::get_str));
+    validation.required(&keys.address, Config::get_ethereum_address);
+    let abi = validation
+        .optional(&keys.abi, get_abi)
+        .unwrap_or_else(|| UCDP_ABI.clone());
+    if abi.function(function_name).is_err() {
+        validation.invalid(&keys.abi, format!("no function {}", function_name));
+    }
+    validation.optional(&keys.from_block, get_block);
+    validation.optional("ethereum.log_range", |config, key| {
+        match config.get_int(key)? {
+            range if range > 0 => Ok(range),
+            _ => Err(ucdp::config::Error::Invalid(
+                key.into(),
+                "empty range".into(),
+            )),
+        }
+    });
+}
+
+// Events are replayed from the block the contract was deployed at, it must be set when the
+// ABI of the contract has the event. The whole chain would be read again otherwise.
+pub fn validate_events(validation: &mut Validation, contract_key: &str, event_name: &str) {
+    let keys = ContractKeys::new(validation.optional(contract_key, Config::get_str));
+    let abi = validation
+        .optional(&keys.abi, get_abi)
+        .unwrap_or_else(|| UCDP_ABI.clone());
+    if abi.event(event_name).is_ok() {
+        validation.required(&keys.from_block, get_block);
+    }
+}
+
+// Block number
+fn get_block(config: &Config, key: &str) -> Result<u64, ucdp::config::Error> {
+    u64::try_from(config.get_int(key)?)
+        .map_err(|_| ucdp::config::Error::Invalid(key.into(), "negative block".into()))
+}
+
+// Address and ABI of the contract named in contract_key
+fn get_contract(
+    config: &Config,
+    contract_key: &str,
+) -> Result<(Address, Arc<ethabi::Contract>), EthereumDaoError> {
+    let keys = ContractKeys::of(config, contract_key);
+    let address = config
+        .get_str(&keys.address)
+        .map(|address| Address::from_str(address.as_str()))?
+        .map_err(|_| EthereumDaoError::Parameter(keys.address.clone()))?;
+    let abi = match config.get_str(&keys.abi) {
+        Ok(path) => load_abi(&path)?,
+        Err(_) => UCDP_ABI.clone(),
+    };
+    Ok((address, abi))
+}
+
+// Secp256k1 private key in hex, preferably a ${file:...} reference
+pub fn get_secret_key(config: &Config, key: &str) -> Result<SecretKey, ucdp::config::Error> {
+    let value = config.get_str(key)?;
+    SecretKey::from_str(value.trim_start_matches("0x"))
+        .map_err(|_| ucdp::config::Error::Invalid(key.into(), "not a secp256k1 private key".into()))
+}
+
+// Events emitted by a contract
+#[async_trait]
+pub trait EthereumEvents: Send + Sync {
+    // Events whose first indexed parameter is topic, oldest first, up to the given block
+    async fn logs(
+        &self,
+        topic: Address,
+        block: Option<BlockId>,
+    ) -> Result<Vec<ethabi::Log>, EthereumDaoError>;
+}
+
+struct EthereumEventsImpl {
+    web3: web3::Web3<EthereumTransport>,
+    address: Address,
+    event: ethabi::Event,
+    from_block: u64,
+    // Blocks of each eth_getLogs call
+    range: u64,
+}
+
+#[async_trait]
+impl EthereumEvents for EthereumEventsImpl {
+    async fn logs(
+        &self,
+        topic: Address,
+        block: Option<BlockId>,
+    ) -> Result<Vec<ethabi::Log>, EthereumDaoError> {
+        trace!("logs {} of {:?} at {:?}", self.event.name, topic, block);
+        // Ranges are given by number, the number of a block read by hash is looked up
+        let (to_block, hash) = match block {
+            Some(BlockId::Hash(hash)) => {
+                let number = self
+                    .web3
+                    .eth()
+                    .block(BlockId::Hash(hash))
+                    .await?
+                    .and_then(|block| block.number)
+                    .ok_or_else(|| EthereumDaoError::Parameter(format!("no block {:?}", hash)))?;
+                (BlockNumber::Number(number), Some(hash))
+            }
+            Some(BlockId::Number(number)) => (number, None),
+            None => (BlockNumber::Latest, None),
+        };
+        let to_block = match to_block {
+            BlockNumber::Number(number) => number,
+            _ => self.web3.eth().block_number().await?,
+        };
+
+        // Providers cap the blocks of a call, the blocks since the deployment are read in ranges
+        let mut logs = vec![];
+        let mut from_block = self.from_block;
+        while from_block <= to_block.as_u64() {
+            let range_to_block = (from_block + self.range - 1).min(to_block.as_u64());
+            let filter = FilterBuilder::default()
+                .address(vec![self.address])
+                .topics(
+                    Some(vec![self.event.signature()]),
+                    Some(vec![H256::from(topic)]),
+                    None,
+                    None,
+                )
+                .from_block(BlockNumber::Number(from_block.into()))
+                .to_block(BlockNumber::Number(range_to_block.into()))
+                .build();
+            logs.extend(self.web3.eth().logs(filter).await?);
+            from_block = range_to_block + 1;
+        }
+        // Logs of the block at that number since a reorganization carry another hash
+        if let Some(hash) = hash {
+            if logs
+                .iter()
+                .any(|log| log.block_number == Some(to_block) && log.block_hash != Some(hash))
+            {
+                return Err(EthereumDaoError::Parameter(format!(
+                    "block {:?} is no longer in the chain",
+                    hash
+                )));
+            }
+        }
+        logs.into_iter()
+            .map(|log| {
+                let log = self.event.parse_log(ethabi::RawLog {
+                    topics: log.topics,
+                    data: log.data.0,
+                })?;
+                Ok(log)
+            })
+            .collect()
+    }
+}
+
+pub struct EthereumEventsBuilder {}
+
+impl EthereumEventsBuilder {
+    pub fn build(
+        config: &Config,
+        contract_key: &str,
+        event_name: &str,
+    ) -> Result<Box<dyn EthereumEvents>, EthereumDaoError> {
+        let (address, abi) = get_contract(config, contract_key)?;
+        let event = abi.event(event_name)?.clone();
+        let from_block = get_block(config, &ContractKeys::of(config, contract_key).from_block)?;
+        let web3 = web3::Web3::new(EthereumTransport::shared(config)?);
+        Ok(Box::new(EthereumEventsImpl {
+            web3,
+            address,
+            event,
+            from_block,
+            range: config
+                .get_int("ethereum.log_range")
+                .map(|range| range.max(1) as u64)
+                .unwrap_or(10_000),
+        }))
+    }
+}
+
+// Transactions to a contract, signed and paid for by the gateway
+#[async_trait]
+pub trait EthereumRelay: Send + Sync {
+    // Hash of the transaction once the node has accepted it
+    async fn send(&self, params: Vec<ethabi::Token>) -> Result<H256, EthereumDaoError>;
+}
+
+struct EthereumRelayImpl {
+    web3: web3::Web3<EthereumTransport>,
+    address: Address,
+    function: ethabi::Function,
+    key: SecretKey,
+    // Estimated by the node for each transaction when not set
+    gas: Option<U256>,
+    // Nonce of the next transaction, read from the pending state of the node when unknown.
+    // Relays take it in turn, concurrent ones would otherwise sign with the same nonce.
+    nonce: AsyncMutex<Option<U256>>,
+}
+
+impl EthereumRelayImpl {
+    // The node already has the transaction, sent again by a retry of the transport
+    async fn is_known(&self, hash: H256, error: &web3::Error) -> bool {
+        match error {
+            web3::Error::Rpc(error) if error.message.contains("already known") => true,
+            _ => matches!(
+                self.web3.eth().transaction(TransactionId::Hash(hash)).await,
+                Ok(Some(_))
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl EthereumRelay for EthereumRelayImpl {
+    async fn send(&self, params: Vec<ethabi::Token>) -> Result<H256, EthereumDaoError> {
+        trace!("send {} {:?}", self.function.name, params);
+        let data = Bytes(self.function.encode_input(&params)?);
+        // A call that would revert fails the estimate, before any gas is paid for
+        let gas = match self.gas {
+            Some(gas) => gas,
+            None => {
+                let call = CallRequest {
+                    from: Some(SecretKeyRef::new(&self.key).address()),
+                    to: Some(self.address),
+                    data: Some(data.clone()),
+                    ..Default::default()
+                };
+                self.web3.eth().estimate_gas(call, None).await?
+            }
+        };
+        let mut transaction = TransactionParameters {
+            to: Some(self.address),
+            data,
+            gas,
+            ..Default::default()
+        };
+
+        let mut nonce = self.nonce.lock().await;
+        let next = match *nonce {
+            Some(next) => next,
+            None => {
+                let address = SecretKeyRef::new(&self.key).address();
+                self.web3
+                    .eth()
+                    .transaction_count(address, Some(BlockNumber::Pending))
+                    .await?
+            }
+        };
+        *nonce = Some(next);
+        transaction.nonce = Some(next);
+        // Gas price and chain id are asked to the node
+        let signed = self
+            .web3
+            .accounts()
+            .sign_transaction(transaction, SecretKeyRef::new(&self.key))
+            .await?;
+        match self
+            .web3
+            .eth()
+            .send_raw_transaction(signed.raw_transaction)
+            .await
+        {
+            Ok(hash) => {
+                *nonce = Some(next + 1);
+                Ok(hash)
+            }
+            Err(error) if self.is_known(signed.transaction_hash, &error).await => {
+                *nonce = Some(next + 1);
+                Ok(signed.transaction_hash)
+            }
+            Err(error) => {
+                // Whether the node took the nonce is not known, it is read again
+                *nonce = None;
+                Err(error.into())
+            }
+        }
+    }
+}
+
+pub struct EthereumRelayBuilder {}
+
+impl EthereumRelayBuilder {
+    // Relaying is enabled by ethereum.relayer.key
+    pub fn validate(validation: &mut Validation) {
+        validation.optional("ethereum.relayer.key", get_secret_key);
+        validation.optional("ethereum.relayer.gas", Config::get_int);
+    }
+
+    pub fn build(
+        config: &Config,
+        contract_key: &str,
+        function_name: &str,
+    ) -> Result<Box<dyn EthereumRelay>, EthereumDaoError> {
+        let key = get_secret_key(config, "ethereum.relayer.key")?;
+        let (address, abi) = get_contract(config, contract_key)?;
+        let function = abi.function(function_name)?.clone();
+        let web3 = web3::Web3::new(EthereumTransport::shared(config)?);
+        Ok(Box::new(EthereumRelayImpl {
+            web3,
+            address,
+            function,
+            key,
+            gas: config
+                .get_int("ethereum.relayer.gas")
+                .ok()
+                .map(|gas| U256::from(gas.max(0))),
+            nonce: AsyncMutex::new(None),
+        }))
     }
 }
 
@@ -72,24 +554,18 @@ where
 {
     pub fn build(
         config: &Config,
+        contract_key: &str,
         function_name: &str,
     ) -> Result<Box<dyn EthereumDao<'a, K, R>>, EthereumDaoError> {
-        let network = config.get_str("ethereum.network")?;
-        let contract_address = config
-            .get_str("ethereum.contract")
-            .map(|address| web3::types::Address::from_str(address.as_str()))?
-            .map_err(|_| EthereumDaoError::Parameter("ethereum.contract".into()))?;
-
-        let http = web3::transports::Http::new(network.as_str())?;
-        let web3 = web3::Web3::new(http);
-        let contract = web3::contract::Contract::from_json(
-            web3.eth(),
-            contract_address,
-            include_bytes!("../../../res/Ucdp.abi.json"),
-        )?;
+        let (address, abi) = get_contract(config, contract_key)?;
+        // Calls to a function missing from the ABI would only fail when made
+        abi.function(function_name)?;
 
+        let web3 = web3::Web3::new(EthereumTransport::shared(config)?);
         let dao = EthereumDaoImpl {
-            contract,
+            eth: web3.eth(),
+            address,
+            abi,
             function_name: function_name.into(),
         };
 
@@ -99,8 +575,17 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::ucdp::dal::ethereum_dao::{EthereumDaoBuilder, EthereumDaoError};
-    use ucdp::config::Config;
+    use crate::ucdp::dal::ethereum_dao::{
+        load_abi, validate, EthereumDaoBuilder, EthereumDaoError, EthereumEventsBuilder,
+        EthereumRelayBuilder,
+    };
+    use crate::ucdp::dal::ethereum_transport::tests::rpc_server_with;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use ucdp::config::{Config, Validation};
+    use web3::ethabi::Token;
+    use web3::signing::keccak256;
+    use web3::types::{Address, Bytes, H256};
 
     fn config(network: Option<&str>, address: Option<&str>) -> Config {
         let mut config = config::Config::default();
@@ -119,14 +604,169 @@ mod tests {
             Some("http://ethereum"),
             Some("0x0000000000000000000000000000000000000000"),
         );
-        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "function_name");
+        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "data.test.contract", "partners");
         assert!(res.is_ok())
     }
 
+    #[actix_rt::test]
+    async fn ethereum_dao_get() {
+        let contract = "0x0000000000000000000000000000000000000c0a";
+        let (url, _) = rpc_server_with(move |request| {
+            // Called on the contract, with the user and partner ABI encoded after the selector
+            let call = &request["params"][0];
+            assert_eq!(call["to"], contract);
+            assert_eq!(call["data"].as_str()?.len(), 2 + 2 * (4 + 32 + 32));
+            Some(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": format!("0x{:064x}", 1),
+            }))
+        });
+        let config = config(Some(&url), Some(contract));
+        let dao = EthereumDaoBuilder::<(Address, Address), bool>::build(
+            &config,
+            "data.test.contract",
+            "authorizedPartnersByUser",
+        )
+        .unwrap();
+        let key = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        assert!(dao.get(key, None).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_events_logs() {
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let (url, _) = rpc_server_with({
+            let ranges = ranges.clone();
+            move |request| {
+                let result = match request["method"].as_str()? {
+                    "eth_blockNumber" => json!("0x19"),
+                    "eth_getLogs" => {
+                        let filter = &request["params"][0];
+                        ranges.lock().unwrap().push((
+                            filter["fromBlock"].as_str()?.to_string(),
+                            filter["toBlock"].as_str()?.to_string(),
+                        ));
+                        json!([])
+                    }
+                    _ => return None,
+                };
+                Some(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+            }
+        });
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", url);
+        let _ = config.set(
+            "ethereum.contract",
+            "0x0000000000000000000000000000000000000c0a",
+        );
+        let _ = config.set("ethereum.from_block", 3);
+        let _ = config.set("ethereum.log_range", 10);
+        let config = Config::from(config);
+        let events =
+            EthereumEventsBuilder::build(&config, "data.test.contract", "Authorization").unwrap();
+
+        // From the deployment to the latest block, 10 blocks at most per call
+        let logs = events
+            .logs(Address::from_low_u64_be(1), None)
+            .await
+            .unwrap();
+        assert!(logs.is_empty());
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![
+                ("0x3".to_string(), "0xc".to_string()),
+                ("0xd".to_string(), "0x16".to_string()),
+                ("0x17".to_string(), "0x19".to_string()),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_relay_send() {
+        let methods = Arc::new(Mutex::new(Vec::<String>::new()));
+        let (url, _) = rpc_server_with({
+            let methods = methods.clone();
+            move |request| {
+                let mut methods = methods.lock().unwrap();
+                methods.push(request["method"].as_str()?.into());
+                let sent = methods
+                    .iter()
+                    .filter(|method| *method == "eth_sendRawTransaction")
+                    .count();
+                let result = match request["method"].as_str()? {
+                    "eth_getTransactionCount" => json!("0x5"),
+                    "eth_gasPrice" => json!("0x1"),
+                    "eth_estimateGas" => json!("0x186a0"),
+                    "eth_chainId" => json!("0x539"),
+                    "eth_getTransactionByHash" => json!(null),
+                    "eth_sendRawTransaction" => {
+                        let error = match sent {
+                            // Sent again by a retry
+                            3 => "already known",
+                            4 => "nonce too low",
+                            _ => {
+                                let raw: Bytes =
+                                    serde_json::from_value(request["params"][0].clone()).ok()?;
+                                return Some(json!({
+                                    "jsonrpc": "2.0",
+                                    "id": request["id"],
+                                    "result": H256(keccak256(&raw.0)),
+                                }));
+                            }
+                        };
+                        return Some(json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "error": { "code": -32000, "message": error },
+                        }));
+                    }
+                    _ => return None,
+                };
+                Some(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+            }
+        });
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", url);
+        let _ = config.set(
+            "ethereum.contract",
+            "0x0000000000000000000000000000000000000c0a",
+        );
+        let _ = config.set(
+            "ethereum.relayer.key",
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        );
+        let config = Config::from(config);
+        let relay =
+            EthereumRelayBuilder::build(&config, "data.test.contract", "authorizePartner").unwrap();
+        let params = || vec![Token::Address(Address::from_low_u64_be(1))];
+        let count = |method: &str| {
+            let methods = methods.lock().unwrap();
+            methods.iter().filter(|m| *m == method).count()
+        };
+
+        // Concurrent relays sign with their own nonces, the same nonce would give the same hash
+        let (first, second) = futures::join!(relay.send(params()), relay.send(params()));
+        assert_ne!(first.unwrap(), second.unwrap());
+        assert_eq!(count("eth_getTransactionCount"), 1);
+
+        assert!(relay.send(params()).await.is_ok());
+        match relay.send(params()).await {
+            Err(EthereumDaoError::Network(_)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(count("eth_getTransactionByHash"), 1);
+
+        // The nonce is read again after a failure
+        assert!(relay.send(params()).await.is_ok());
+        assert_eq!(count("eth_getTransactionCount"), 2);
+        assert_eq!(count("eth_estimateGas"), 5);
+    }
+
     #[test]
     fn ethereum_dao_builder_build_err_config() {
         let config = config(None, None);
-        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "function_name");
+        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "data.test.contract", "partners");
         match res {
             Err(EthereumDaoError::Config(_)) => (),
             _ => unreachable!(),
@@ -136,7 +776,7 @@ mod tests {
     #[test]
     fn ethereum_dao_builder_build_err_parameter() {
         let config = config(Some("http://ethereum"), Some("not an address"));
-        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "function_name");
+        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "data.test.contract", "partners");
         if let Err(EthereumDaoError::Parameter(reason)) = res {
             assert_eq!(reason, "ethereum.contract");
         } else {
@@ -150,10 +790,92 @@ mod tests {
             Some("not a network"),
             Some("0x0000000000000000000000000000000000000000"),
         );
-        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "function_name");
+        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "data.test.contract", "partners");
         match res {
             Err(EthereumDaoError::Network(_)) => (),
             _ => unreachable!(),
         }
     }
+
+    #[test]
+    fn ethereum_dao_builder_build_err_function() {
+        let config = config(
+            Some("http://ethereum"),
+            Some("0x0000000000000000000000000000000000000000"),
+        );
+        let res = EthereumDaoBuilder::<u32, u32>::build(&config, "data.test.contract", "unknown");
+        match res {
+            Err(EthereumDaoError::Creation(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn ethereum_dao_builder_build_named_contract() {
+        let path = std::env::temp_dir().join(format!("{}.abi.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"[{"type":"function","name":"consent","inputs":[],"outputs":[{"name":"","type":"bool"}],"stateMutability":"view"}]"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", "http://ethereum");
+        let _ = config.set("data.test.contract", "consent");
+        let _ = config.set(
+            "ethereum.contracts.consent.address",
+            "0x0000000000000000000000000000000000000001",
+        );
+        let _ = config.set("ethereum.contracts.consent.abi", path);
+        let config = Config::from(config);
+
+        assert!(
+            EthereumDaoBuilder::<u32, bool>::build(&config, "data.test.contract", "consent")
+                .is_ok()
+        );
+        // The default contract is not needed, nor is its function in the ABI
+        match EthereumDaoBuilder::<u32, bool>::build(&config, "data.test.contract", "partners") {
+            Err(EthereumDaoError::Creation(_)) => (),
+            _ => unreachable!(),
+        }
+        // Parsed once
+        assert!(Arc::ptr_eq(
+            &load_abi(path).unwrap(),
+            &load_abi(path).unwrap()
+        ));
+
+        let mut validation = Validation::new(&config);
+        validate(&mut validation, "data.test.contract", "partners");
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => assert_eq!(
+                problems,
+                vec!["ethereum.contracts.consent.abi: no function partners"]
+            ),
+            _ => unreachable!(),
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn ethereum_dao_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", "http://ethereum");
+        let _ = config.set("data.test.contract", "consent");
+        let _ = config.set("ethereum.contracts.consent.abi", "/nonexistent.abi.json");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        validate(&mut validation, "data.test.contract", "partners");
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => {
+                assert_eq!(problems.len(), 2);
+                assert_eq!(problems[0], "ethereum.contracts.consent.address: missing");
+                assert!(problems[1].starts_with(
+                    "ethereum.contracts.consent.abi: abi error: /nonexistent.abi.json"
+                ));
+            }
+            _ => unreachable!(),
+        }
+    }
 }
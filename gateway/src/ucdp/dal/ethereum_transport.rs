@@ -0,0 +1,700 @@
+use crate::ucdp::dal::ethereum_dao::EthereumDaoError;
+use futures::channel::oneshot;
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use jsonrpc_core as rpc;
+use log::warn;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use ucdp::config::{self, Config, Validation};
+use web3::helpers;
+use web3::transports::{Http, Ipc, WebSocket};
+use web3::{BatchTransport, RequestId, Transport};
+
+const SCHEMES: &[&str] = &["http", "https", "ws", "wss", "ipc"];
+
+const SETTINGS: &[&str] = &[
+    "ethereum.network",
+    "ethereum.timeout",
+    "ethereum.retries",
+    "ethereum.cooldown",
+    "ethereum.batch.window",
+    "ethereum.batch.size",
+];
+
+// Transports in use by their settings
+static SHARED: Lazy<Mutex<HashMap<String, Weak<Inner>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// A connection to one endpoint
+#[derive(Clone, Debug)]
+enum Connection {
+    Http(Http),
+    WebSocket(WebSocket),
+    Ipc(Ipc),
+}
+
+impl Connection {
+    async fn connect(url: &str) -> web3::Result<Self> {
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            Ok(Connection::WebSocket(WebSocket::new(url).await?))
+        } else if let Some(path) = url.strip_prefix("ipc://") {
+            Ok(Connection::Ipc(Ipc::new(path).await?))
+        } else {
+            Ok(Connection::Http(Http::new(url)?))
+        }
+    }
+
+    fn send(&self, id: RequestId, call: rpc::Call) -> <EthereumTransport as Transport>::Out {
+        match self {
+            Connection::Http(http) => http.send(id, call).boxed(),
+            Connection::WebSocket(ws) => ws.send(id, call).boxed(),
+            Connection::Ipc(ipc) => ipc.send(id, call).boxed(),
+        }
+    }
+
+    // Results in the order of the requests
+    fn send_batch(
+        &self,
+        requests: Vec<(RequestId, rpc::Call)>,
+    ) -> BoxFuture<'static, web3::Result<Vec<web3::Result<rpc::Value>>>> {
+        match self {
+            Connection::Http(http) => http.send_batch(requests).boxed(),
+            Connection::WebSocket(ws) => ws.send_batch(requests).boxed(),
+            Connection::Ipc(ipc) => ipc.send_batch(requests).boxed(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    // WebSocket and IPC connections are opened on first use, builders are not async
+    connection: Mutex<Option<Connection>>,
+    // Tried last until then after a transient error
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => until <= Instant::now(),
+            None => true,
+        }
+    }
+
+    async fn connection(&self) -> web3::Result<Connection> {
+        if let Some(connection) = self.connection.lock().unwrap().clone() {
+            return Ok(connection);
+        }
+        let connection = Connection::connect(&self.url).await?;
+        *self.connection.lock().unwrap() = Some(connection.clone());
+        Ok(connection)
+    }
+
+    // The connection is opened again on next use, a WebSocket may have been closed
+    fn failed(&self, cooldown: Duration) {
+        let mut connection = self.connection.lock().unwrap();
+        if !matches!(*connection, Some(Connection::Http(_))) {
+            *connection = None;
+        }
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+}
+
+// Calls sent within window go in the same JSON-RPC batch, of size calls at most
+#[derive(Debug)]
+struct Batching {
+    window: Duration,
+    size: usize,
+}
+
+type Pending = (
+    RequestId,
+    rpc::Call,
+    oneshot::Sender<web3::Result<rpc::Value>>,
+);
+
+#[derive(Debug)]
+struct Inner {
+    endpoints: Vec<Endpoint>,
+    // Round-robin among the healthy endpoints
+    next: AtomicUsize,
+    id: AtomicUsize,
+    timeout: Duration,
+    retries: usize,
+    cooldown: Duration,
+    batching: Option<Batching>,
+    pending: Mutex<Vec<Pending>>,
+    // Calls and batches sent and not answered yet
+    in_flight: AtomicUsize,
+}
+
+// Counts a call or a batch in flight until dropped, even when its future is
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(in_flight)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Calls the endpoints of ethereum.network in turn, each attempt within ethereum.timeout.
+// Transient errors are retried ethereum.retries times on the next endpoint.
+// Concurrent calls are batched when ethereum.batch.window is set.
+#[derive(Clone, Debug)]
+pub struct EthereumTransport {
+    inner: Arc<Inner>,
+}
+
+// Errors worth another attempt, possibly on another endpoint.
+// Reverted calls or invalid parameters would fail the same way anywhere.
+fn is_transient(error: &web3::Error) -> bool {
+    match error {
+        web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_) => true,
+        web3::Error::Rpc(error) => matches!(
+            error.code,
+            // Internal error, limit exceeded
+            rpc::ErrorCode::InternalError | rpc::ErrorCode::ServerError(-32005)
+        ),
+        _ => false,
+    }
+}
+
+// A single endpoint or a list of them, http(s)://, ws(s):// or ipc:///path
+fn get_endpoints(config: &Config, key: &str) -> Result<Vec<String>, config::Error> {
+    match config.get_str_vec(key) {
+        Ok(endpoints) if endpoints.is_empty() => {
+            Err(config::Error::Invalid(key.into(), "no endpoint".into()))
+        }
+        Ok(endpoints) => Ok(endpoints),
+        Err(_) => config.get_str(key).map(|endpoint| vec![endpoint]),
+    }
+}
+
+fn get_urls(config: &Config, key: &str) -> Result<Vec<url::Url>, config::Error> {
+    get_endpoints(config, key)?
+        .iter()
+        .map(|endpoint| {
+            let url = url::Url::parse(endpoint)
+                .map_err(|error| config::Error::Invalid(key.into(), error.to_string()))?;
+            if SCHEMES.contains(&url.scheme()) {
+                Ok(url)
+            } else {
+                Err(config::Error::Invalid(
+                    key.into(),
+                    format!("unsupported scheme {}", url.scheme()),
+                ))
+            }
+        })
+        .collect()
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.required("ethereum.network", get_urls);
+    validation.optional("ethereum.timeout", Config::get_duration);
+    validation.optional("ethereum.retries", |config, key| {
+        usize::try_from(config.get_int(key)?)
+            .map_err(|_| config::Error::Invalid(key.into(), "negative retries".into()))
+    });
+    validation.optional("ethereum.cooldown", Config::get_duration);
+    validation.optional("ethereum.batch.window", Config::get_duration);
+    validation.optional("ethereum.batch.size", |config, key| {
+        match config.get_int(key)? {
+            size if size > 0 => Ok(size),
+            _ => Err(config::Error::Invalid(key.into(), "empty batch".into())),
+        }
+    });
+}
+
+impl EthereumTransport {
+    pub fn build(config: &Config) -> Result<Self, EthereumDaoError> {
+        let endpoints = get_endpoints(config, "ethereum.network")?
+            .into_iter()
+            .map(|url| {
+                // HTTP needs no connection, a malformed URL is reported right away
+                let connection = if url.contains("://") && !url.starts_with("http") {
+                    None
+                } else {
+                    Some(Connection::Http(Http::new(&url)?))
+                };
+                Ok(Endpoint {
+                    url,
+                    connection: Mutex::new(connection),
+                    unhealthy_until: Mutex::new(None),
+                })
+            })
+            .collect::<Result<Vec<Endpoint>, EthereumDaoError>>()?;
+
+        let retries = config.get_int("ethereum.retries").unwrap_or(2);
+        let retries = usize::try_from(retries)
+            .map_err(|_| EthereumDaoError::Parameter("ethereum.retries".into()))?;
+
+        let batching = match config.get_duration("ethereum.batch.window") {
+            Ok(window) => Some(Batching {
+                window,
+                size: config.get_int("ethereum.batch.size").unwrap_or(100).max(1) as usize,
+            }),
+            Err(_) => None,
+        };
+
+        Ok(EthereumTransport {
+            inner: Arc::new(Inner {
+                endpoints,
+                next: AtomicUsize::new(0),
+                id: AtomicUsize::new(1),
+                timeout: config
+                    .get_duration("ethereum.timeout")
+                    .unwrap_or_else(|_| Duration::from_secs(5)),
+                retries,
+                cooldown: config
+                    .get_duration("ethereum.cooldown")
+                    .unwrap_or_else(|_| Duration::from_secs(30)),
+                batching,
+                pending: Mutex::new(vec![]),
+                in_flight: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    // The same transport for the same settings, so that the DAOs batch their calls together
+    // and endpoint health survives configuration reloads
+    pub fn shared(config: &Config) -> Result<Self, EthereumDaoError> {
+        let settings = SETTINGS
+            .iter()
+            .map(|key| format!("{:?}", config.get::<serde_json::Value>(key).ok()))
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut shared = SHARED.lock().unwrap();
+        if let Some(inner) = shared.get(&settings).and_then(Weak::upgrade) {
+            return Ok(EthereumTransport { inner });
+        }
+        let transport = EthereumTransport::build(config)?;
+        shared.retain(|_, inner| inner.strong_count() > 0);
+        shared.insert(settings, Arc::downgrade(&transport.inner));
+        Ok(transport)
+    }
+
+    // Number of endpoints that are not skipped, and of endpoints
+    pub fn healthy(&self) -> (usize, usize) {
+        let endpoints = &self.inner.endpoints;
+        let healthy = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_healthy())
+            .count();
+        (healthy, endpoints.len())
+    }
+}
+
+impl Inner {
+    // Endpoints from the next one in turn, healthy ones first
+    fn candidates(&self) -> Vec<&Endpoint> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.endpoints.len();
+        let mut candidates: Vec<&Endpoint> = (0..len)
+            .map(|i| &self.endpoints[(start + i) % len])
+            .collect();
+        candidates.sort_by_key(|endpoint| !endpoint.is_healthy());
+        candidates
+    }
+
+    // Try the endpoints in turn until one answers, or fails in a way another one would
+    async fn attempt<T, F>(&self, send: F) -> web3::Result<T>
+    where
+        F: Fn(Connection) -> BoxFuture<'static, web3::Result<T>>,
+    {
+        let candidates = self.candidates();
+        let mut attempt = 0;
+        loop {
+            let endpoint = candidates[attempt % candidates.len()];
+            // Connecting counts in the time of the attempt
+            let res = actix_rt::time::timeout(self.timeout, async {
+                let connection = endpoint.connection().await?;
+                send(connection).await
+            })
+            .await
+            .unwrap_or_else(|_| {
+                Err(web3::Error::Transport(format!(
+                    "no answer within {:?}",
+                    self.timeout
+                )))
+            });
+            match res {
+                Err(error) if is_transient(&error) => {
+                    warn!("Ethereum endpoint {} failed: {}", endpoint.url, error);
+                    endpoint.failed(self.cooldown);
+                    if attempt == self.retries {
+                        return Err(error);
+                    }
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn send(&self, id: RequestId, call: rpc::Call) -> web3::Result<rpc::Value> {
+        self.attempt(|connection| connection.send(id, call.clone()))
+            .await
+    }
+
+    // A call goes out right away when nothing else is in flight. Otherwise it waits for the
+    // window started by the first call pending, the calls pending by then are sent together
+    // by a task of their own, whatever happens to the callers.
+    async fn send_batched(
+        self: Arc<Self>,
+        window: Duration,
+        id: RequestId,
+        call: rpc::Call,
+    ) -> web3::Result<rpc::Value> {
+        let batched = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.is_empty() && self.in_flight.load(Ordering::SeqCst) == 0 {
+                // Counted before the lock is released, the next call waits for this one
+                Err((InFlight::new(&self.in_flight), call))
+            } else {
+                if pending.is_empty() {
+                    let inner = self.clone();
+                    actix_rt::spawn(async move {
+                        actix_rt::time::sleep(window).await;
+                        let pending = std::mem::take(&mut *inner.pending.lock().unwrap());
+                        let _in_flight = InFlight::new(&inner.in_flight);
+                        inner.flush(pending).await;
+                    });
+                }
+                let (sender, receiver) = oneshot::channel();
+                pending.push((id, call, sender));
+                Ok(receiver)
+            }
+        };
+        match batched {
+            Ok(receiver) => receiver
+                .await
+                .unwrap_or_else(|_| Err(web3::Error::Transport("batch cancelled".into()))),
+            Err((_in_flight, call)) => self.send(id, call).await,
+        }
+    }
+
+    async fn flush(&self, mut pending: Vec<Pending>) {
+        let size = self
+            .batching
+            .as_ref()
+            .map(|batching| batching.size)
+            .unwrap_or(1);
+        let mut batches = vec![];
+        while !pending.is_empty() {
+            let rest = pending.split_off(size.min(pending.len()));
+            batches.push(std::mem::replace(&mut pending, rest));
+        }
+
+        join_all(batches.into_iter().map(|batch| async move {
+            let (requests, senders): (Vec<_>, Vec<_>) = batch
+                .into_iter()
+                .map(|(id, call, sender)| ((id, call), sender))
+                .unzip();
+            // A batch of one is a plain call
+            let results = if let [(id, call)] = requests.as_slice() {
+                Ok(vec![self.send(*id, call.clone()).await])
+            } else {
+                self.attempt(|connection| connection.send_batch(requests.clone()))
+                    .await
+            };
+            match results {
+                Ok(results) => {
+                    for (sender, result) in senders.into_iter().zip(results) {
+                        let _ = sender.send(result);
+                    }
+                }
+                Err(error) => {
+                    for sender in senders {
+                        let _ = sender.send(Err(error.clone()));
+                    }
+                }
+            }
+        }))
+        .await;
+    }
+}
+
+impl Transport for EthereumTransport {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.inner.id.fetch_add(1, Ordering::Relaxed);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, call: rpc::Call) -> Self::Out {
+        let inner = self.inner.clone();
+        async move {
+            match &inner.batching {
+                Some(batching) => {
+                    let window = batching.window;
+                    inner.send_batched(window, id, call).await
+                }
+                None => inner.send(id, call).await,
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{validate, EthereumTransport};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use ucdp::config::{Config, Validation};
+    use web3::types::Address;
+
+    // JSON-RPC over HTTP answering every call with result or error, or never when both are None
+    fn rpc_server(
+        result: Option<serde_json::Value>,
+        error: Option<serde_json::Value>,
+    ) -> (String, Arc<AtomicUsize>) {
+        rpc_server_with(move |request| match (&result, &error) {
+            (Some(result), _) => {
+                Some(serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+            }
+            (_, Some(error)) => {
+                Some(serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "error": error}))
+            }
+            _ => None,
+        })
+    }
+
+    // JSON-RPC over HTTP answering every call as told, or never on None
+    pub(crate) fn rpc_server_with<F>(answer: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn(&serde_json::Value) -> Option<serde_json::Value> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(AtomicUsize::new(0));
+        std::thread::spawn({
+            let calls = calls.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                            content_length = length.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    calls.fetch_add(1, Ordering::SeqCst);
+
+                    // Batch answers come in reverse order, they are matched by id
+                    let response = match &request {
+                        serde_json::Value::Array(requests) => requests
+                            .iter()
+                            .rev()
+                            .map(&answer)
+                            .collect::<Option<Vec<_>>>()
+                            .map(serde_json::Value::from),
+                        request => answer(request),
+                    };
+                    let response = match response {
+                        Some(response) => response,
+                        None => {
+                            // Keep the connection open
+                            std::mem::forget(stream);
+                            continue;
+                        }
+                    }
+                    .to_string();
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.len(),
+                        response
+                    );
+                }
+            }
+        });
+        (url, calls)
+    }
+
+    fn transport(network: Vec<&str>, timeout: &str, retries: i64) -> EthereumTransport {
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", network);
+        let _ = config.set("ethereum.timeout", timeout);
+        let _ = config.set("ethereum.retries", retries);
+        EthereumTransport::build(&Config::from(config)).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_transport_batch() {
+        // The balance of an address is its number
+        let (url, calls) = rpc_server_with(|request| {
+            let address = request["params"][0].as_str()?;
+            let balance = u64::from_str_radix(&address[address.len() - 16..], 16).ok()?;
+            Some(
+                serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": format!("{:#x}", balance)}),
+            )
+        });
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", url);
+        let _ = config.set("ethereum.batch.window", "200ms");
+        let _ = config.set("ethereum.batch.size", 3);
+        let transport = EthereumTransport::build(&Config::from(config)).unwrap();
+        let web3 = web3::Web3::new(transport);
+        let balance = |number: u64| web3.eth().balance(Address::from_low_u64_be(number), None);
+
+        // The first of 4 concurrent calls goes out right away, the other 3 make a batch
+        let balances = futures::future::join_all((1..=4).map(balance)).await;
+        for (number, balance) in (1..=4).zip(balances) {
+            assert_eq!(balance.unwrap().as_u64(), number);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // A call alone goes out as is, without waiting for the window
+        let start = std::time::Instant::now();
+        assert_eq!(balance(5).await.unwrap().as_u64(), 5);
+        assert!(start.elapsed() < std::time::Duration::from_millis(200));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // A caller giving up leaves the others of its batch alone
+        let (_, cancelled, kept) = futures::join!(
+            balance(6),
+            actix_rt::time::timeout(std::time::Duration::from_millis(50), balance(7)),
+            balance(8)
+        );
+        assert!(cancelled.is_err());
+        assert_eq!(kept.unwrap().as_u64(), 8);
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn ethereum_transport_shared() {
+        let config = |timeout: &str| {
+            let mut config = config::Config::default();
+            let _ = config.set("ethereum.network", "http://shared");
+            let _ = config.set("ethereum.timeout", timeout);
+            Config::from(config)
+        };
+        let transport = EthereumTransport::shared(&config("1s")).unwrap();
+        assert!(Arc::ptr_eq(
+            &transport.inner,
+            &EthereumTransport::shared(&config("1s")).unwrap().inner
+        ));
+        assert!(!Arc::ptr_eq(
+            &transport.inner,
+            &EthereumTransport::shared(&config("2s")).unwrap().inner
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_transport_failover() {
+        let (url, calls) = rpc_server(Some("0x10".into()), None);
+        // Nothing listens on port 1
+        let transport = transport(vec!["http://127.0.0.1:1", &url], "1s", 2);
+        let web3 = web3::Web3::new(transport.clone());
+
+        for _ in 0..3 {
+            assert_eq!(web3.eth().block_number().await.unwrap().as_u64(), 16);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(transport.healthy(), (1, 2));
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_transport_round_robin() {
+        let (a, a_calls) = rpc_server(Some("0x10".into()), None);
+        let (b, b_calls) = rpc_server(Some("0x10".into()), None);
+        let web3 = web3::Web3::new(transport(vec![&a, &b], "1s", 2));
+
+        for _ in 0..4 {
+            assert!(web3.eth().block_number().await.is_ok());
+        }
+        assert_eq!(a_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(b_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_transport_timeout() {
+        let (url, calls) = rpc_server(None, None);
+        let transport = transport(vec![&url], "100ms", 1);
+        let web3 = web3::Web3::new(transport.clone());
+
+        match web3.eth().block_number().await {
+            Err(web3::Error::Transport(reason)) => assert!(reason.starts_with("no answer")),
+            _ => unreachable!(),
+        }
+        // Retried once, on the only endpoint there is
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(transport.healthy(), (0, 1));
+    }
+
+    #[actix_rt::test]
+    async fn ethereum_transport_no_retry() {
+        let error = serde_json::json!({"code": -32000, "message": "execution reverted"});
+        let (url, calls) = rpc_server(None, Some(error));
+        let transport = transport(vec![&url], "1s", 2);
+        let web3 = web3::Web3::new(transport.clone());
+
+        match web3.eth().block_number().await {
+            Err(web3::Error::Rpc(error)) => assert_eq!(error.message, "execution reverted"),
+            _ => unreachable!(),
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(transport.healthy(), (1, 1));
+    }
+
+    #[test]
+    fn ethereum_transport_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set(
+            "ethereum.network",
+            vec!["https://mainnet", "wss://mainnet", "ipc:///tmp/geth.ipc"],
+        );
+        let config = Config::from(config);
+        let mut validation = Validation::new(&config);
+        validate(&mut validation);
+        assert!(validation.finish().is_ok());
+        // WebSocket and IPC endpoints are not connected yet
+        assert!(EthereumTransport::build(&config).is_ok());
+
+        let mut config = config::Config::default();
+        let _ = config.set("ethereum.network", vec!["https://mainnet", "ftp://mainnet"]);
+        let _ = config.set("ethereum.retries", -1);
+        let _ = config.set("ethereum.batch.size", 0);
+        let config = Config::from(config);
+        let mut validation = Validation::new(&config);
+        validate(&mut validation);
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec![
+                        "ethereum.network: unsupported scheme ftp",
+                        "ethereum.retries: negative retries",
+                        "ethereum.batch.size: empty batch"
+                    ]
+                )
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use crate::ucdp::dal::aerospike_dao::{
+    self, AerospikeDao, AerospikeDaoBuilder, AerospikeDaoError, Expect,
+};
+use async_trait::async_trait;
+use jsonschema::JSONSchema;
+use log::trace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use ucdp::config::{Config, Validation};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EventSchema {
+    pub version: u32,
+    pub schema: serde_json::Value,
+    #[serde(skip)]
+    compiled: Arc<JSONSchema>,
+}
+
+impl EventSchema {
+    pub fn new(version: u32, schema: serde_json::Value) -> Result<Self, Error> {
+        let compiled = JSONSchema::compile(&schema)
+            .map_err(|error| Error::InvalidSchema(error.to_string()))?;
+        Ok(EventSchema {
+            version,
+            schema,
+            compiled: Arc::new(compiled),
+        })
+    }
+
+    // Return the list of validation errors of the properties against the schema
+    pub fn validate(&self, properties: &serde_json::Value) -> Result<(), Vec<String>> {
+        self.compiled
+            .validate(properties)
+            .map_err(|errors| errors.map(|error| error.to_string()).collect())
+    }
+}
+
+// Every published version of an event schema. Versions start at 1.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct EventSchemaVersions {
+    active: u32,
+    schemas: Vec<serde_json::Value>,
+}
+
+impl EventSchemaVersions {
+    fn get(&self, version: u32) -> Option<&serde_json::Value> {
+        let index = (version as usize).checked_sub(1)?;
+        self.schemas.get(index)
+    }
+
+    fn publish(&mut self, schema: serde_json::Value) -> Result<EventSchema, Error> {
+        let event_schema = EventSchema::new(self.schemas.len() as u32 + 1, schema)?;
+        self.schemas.push(event_schema.schema.clone());
+        self.active = event_schema.version;
+        Ok(event_schema)
+    }
+
+    fn activate(&mut self, version: u32) -> Result<(), Error> {
+        self.get(version).ok_or(Error::VersionNotFound(version))?;
+        self.active = version;
+        Ok(())
+    }
+}
+
+// Schemas compiled once, by key and version. Published versions never change.
+#[derive(Default)]
+struct CompiledSchemas {
+    schemas: RwLock<HashMap<String, HashMap<u32, EventSchema>>>,
+}
+
+impl CompiledSchemas {
+    fn get(
+        &self,
+        key: &str,
+        versions: &EventSchemaVersions,
+        version: u32,
+    ) -> Result<EventSchema, Error> {
+        let schemas_r = self.schemas.read().map_err(|_| Error::Lock)?;
+        if let Some(event_schema) = schemas_r.get(key).and_then(|schemas| schemas.get(&version)) {
+            return Ok(event_schema.clone());
+        }
+        drop(schemas_r);
+
+        let schema = versions
+            .get(version)
+            .ok_or(Error::VersionNotFound(version))?;
+        let event_schema = EventSchema::new(version, schema.clone())?;
+        self.insert(key, &event_schema)?;
+        Ok(event_schema)
+    }
+
+    fn active(
+        &self,
+        key: &str,
+        versions: &EventSchemaVersions,
+    ) -> Result<Option<EventSchema>, Error> {
+        match versions.get(versions.active) {
+            Some(_) => self.get(key, versions, versions.active).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, key: &str, event_schema: &EventSchema) -> Result<(), Error> {
+        let mut schemas_w = self.schemas.write().map_err(|_| Error::Lock)?;
+        schemas_w
+            .entry(key.into())
+            .or_default()
+            .insert(event_schema.version, event_schema.clone());
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("config error")]
+    Config(#[from] ucdp::config::Error),
+
+    #[error("aerospike dao error")]
+    AerospikeDao(#[from] AerospikeDaoError),
+
+    #[error("deserialization error")]
+    Deserialization(#[from] serde_json::Error),
+
+    #[error("unknown connector: {0}")]
+    UnknownConnector(String),
+
+    #[error("lock error")]
+    Lock,
+
+    #[error("invalid schema: {0}")]
+    InvalidSchema(String),
+
+    #[error("schema not found: {0}")]
+    SchemaNotFound(String),
+
+    #[error("schema version not found: {0}")]
+    VersionNotFound(u32),
+
+    #[error("schema changed concurrently, try again")]
+    Conflict,
+}
+
+#[async_trait]
+pub trait EventSchemasDao: Send + Sync {
+    async fn get_active_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+    ) -> Result<Option<EventSchema>, Error>;
+
+    async fn publish_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        schema: serde_json::Value,
+    ) -> Result<EventSchema, Error>;
+
+    async fn activate_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        version: u32,
+    ) -> Result<EventSchema, Error>;
+}
+
+fn key(partner_id: &str, event_name: &str) -> String {
+    format!("schema/{}/{}", partner_id, event_name)
+}
+
+// Attempts of a change of the versions, when other gateways change them meanwhile
+const WRITE_ATTEMPTS: usize = 5;
+
+struct AerospikeEventSchemasDao {
+    aerospike_dao: Box<dyn AerospikeDao>,
+    compiled: CompiledSchemas,
+}
+
+impl AerospikeEventSchemasDao {
+    // Versions, with the generation of the record they were read from
+    async fn get_versions(&self, key: &str) -> Result<(Option<EventSchemaVersions>, u32), Error> {
+        let res = self.aerospike_dao.get(key).await?;
+        let generation = res.generation;
+        match res.value {
+            Some(bytes) => serde_json::from_slice::<EventSchemaVersions>(&bytes)
+                .map(|versions| (Some(versions), generation))
+                .map_err(Error::Deserialization),
+            None => Ok((None, 0)),
+        }
+    }
+
+    // Read, change and write the versions, again when they were changed meanwhile
+    async fn update_versions<T>(
+        &self,
+        key: &str,
+        update: impl Fn(Option<EventSchemaVersions>) -> Result<(EventSchemaVersions, T), Error>
+            + Send
+            + Sync,
+    ) -> Result<T, Error> {
+        for _ in 0..WRITE_ATTEMPTS {
+            let (versions, generation) = self.get_versions(key).await?;
+            let (versions, res) = update(versions)?;
+            let bytes = serde_json::to_vec(&versions)?;
+            match self
+                .aerospike_dao
+                .put_if(key, bytes, Expect::generation(generation))
+                .await
+            {
+                Ok(()) => return Ok(res),
+                Err(AerospikeDaoError::Conflict) => continue,
+                Err(error) => return Err(Error::AerospikeDao(error)),
+            }
+        }
+        Err(Error::Conflict)
+    }
+}
+
+#[async_trait]
+impl EventSchemasDao for AerospikeEventSchemasDao {
+    async fn get_active_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+    ) -> Result<Option<EventSchema>, Error> {
+        trace!(
+            "AerospikeEventSchemasDao get {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        match self.get_versions(&key).await?.0 {
+            Some(versions) => self.compiled.active(&key, &versions),
+            None => Ok(None),
+        }
+    }
+
+    async fn publish_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        schema: serde_json::Value,
+    ) -> Result<EventSchema, Error> {
+        trace!(
+            "AerospikeEventSchemasDao publish {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        let event_schema = self
+            .update_versions(&key, |versions| {
+                let mut versions = versions.unwrap_or_default();
+                let event_schema = versions.publish(schema.clone())?;
+                Ok((versions, event_schema))
+            })
+            .await?;
+        self.compiled.insert(&key, &event_schema)?;
+        Ok(event_schema)
+    }
+
+    async fn activate_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        version: u32,
+    ) -> Result<EventSchema, Error> {
+        trace!(
+            "AerospikeEventSchemasDao activate {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        let versions = self
+            .update_versions(&key, |versions| {
+                let mut versions = versions.ok_or_else(|| Error::SchemaNotFound(key.clone()))?;
+                versions.activate(version)?;
+                Ok((versions.clone(), versions))
+            })
+            .await?;
+        self.compiled.get(&key, &versions, version)
+    }
+}
+
+// Schemas are kept for the lifetime of the process
+struct InMemoryEventSchemasDao {
+    hashmap: RwLock<HashMap<String, EventSchemaVersions>>,
+    compiled: CompiledSchemas,
+}
+
+#[async_trait]
+impl EventSchemasDao for InMemoryEventSchemasDao {
+    async fn get_active_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+    ) -> Result<Option<EventSchema>, Error> {
+        trace!(
+            "InMemoryEventSchemasDao get {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        let hashmap_r = self.hashmap.read().map_err(|_| Error::Lock)?;
+        match hashmap_r.get(&key) {
+            Some(versions) => self.compiled.active(&key, versions),
+            None => Ok(None),
+        }
+    }
+
+    async fn publish_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        schema: serde_json::Value,
+    ) -> Result<EventSchema, Error> {
+        trace!(
+            "InMemoryEventSchemasDao publish {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        let mut hashmap_w = self.hashmap.write().map_err(|_| Error::Lock)?;
+        let event_schema = hashmap_w.entry(key.clone()).or_default().publish(schema)?;
+        self.compiled.insert(&key, &event_schema)?;
+        Ok(event_schema)
+    }
+
+    async fn activate_schema(
+        &self,
+        partner_id: &str,
+        event_name: &str,
+        version: u32,
+    ) -> Result<EventSchema, Error> {
+        trace!(
+            "InMemoryEventSchemasDao activate {:?} {:?}",
+            partner_id,
+            event_name
+        );
+        let key = key(partner_id, event_name);
+        let mut hashmap_w = self.hashmap.write().map_err(|_| Error::Lock)?;
+        let versions = hashmap_w
+            .get_mut(&key)
+            .ok_or_else(|| Error::SchemaNotFound(key.clone()))?;
+        versions.activate(version)?;
+        self.compiled.get(&key, versions, version)
+    }
+}
+
+pub struct EventSchemasBuilder {}
+
+impl EventSchemasBuilder {
+    pub fn validate(validation: &mut Validation) {
+        if let Some("aerospike") = validation
+            .one_of(
+                "data.event_schemas.connector",
+                &["aerospike", "in-memory"],
+                None,
+            )
+            .as_deref()
+        {
+            aerospike_dao::validate(validation);
+        }
+    }
+
+    pub fn build(config: &Config) -> Result<Box<dyn EventSchemasDao>, Error> {
+        match config.get_str("data.event_schemas.connector")?.as_str() {
+            "aerospike" => {
+                let aerospike_dao = AerospikeDaoBuilder::build(config)?;
+                let dao = AerospikeEventSchemasDao {
+                    aerospike_dao,
+                    compiled: CompiledSchemas::default(),
+                };
+                Ok(Box::new(dao))
+            }
+            "in-memory" => {
+                let dao = InMemoryEventSchemasDao {
+                    hashmap: RwLock::new(HashMap::new()),
+                    compiled: CompiledSchemas::default(),
+                };
+                Ok(Box::new(dao))
+            }
+            unknown_connector => Err(Error::UnknownConnector(unknown_connector.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AerospikeEventSchemasDao, CompiledSchemas, Error, EventSchema, InMemoryEventSchemasDao,
+    };
+    use crate::ucdp::dal::aerospike_dao::{
+        AerospikeDao, AerospikeDaoError, AerospikeDaoResult, Expect,
+    };
+    use crate::ucdp::dal::{EventSchemasBuilder, EventSchemasDao};
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::RwLock;
+    use ucdp::config::Config;
+
+    #[test]
+    fn event_schemas_builder_build_ok() {
+        for connector in ["aerospike", "in-memory"] {
+            let mut config = config::Config::default();
+            let _ = config.set("data.event_schemas.connector", connector);
+            let _ = config.set("aerospike.set", "default");
+            let _ = config.set("aerospike.host", "http://aerospike");
+            let config = Config::from(config);
+
+            let res = EventSchemasBuilder::build(&config);
+            assert!(res.is_ok());
+        }
+    }
+
+    #[test]
+    fn event_schemas_builder_build_err_unknown_connector() {
+        let mut config = config::Config::default();
+        let _ = config.set("data.event_schemas.connector", "unknown");
+        let config = Config::from(config);
+
+        let res = EventSchemasBuilder::build(&config);
+        if let Err(Error::UnknownConnector(reason)) = res {
+            assert_eq!(reason, "unknown");
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": { "price": { "type": "number" } },
+            "required": ["price"]
+        })
+    }
+
+    #[test]
+    fn event_schema_validate() {
+        let event_schema = EventSchema::new(1, schema()).unwrap();
+        assert!(event_schema.validate(&json!({ "price": 1.5 })).is_ok());
+
+        let errors = event_schema
+            .validate(&json!({ "price": "free" }))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        assert!(event_schema.validate(&serde_json::Value::Null).is_err());
+    }
+
+    fn in_memory_dao() -> InMemoryEventSchemasDao {
+        InMemoryEventSchemasDao {
+            hashmap: RwLock::new(HashMap::new()),
+            compiled: CompiledSchemas::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn in_memory_event_schemas_dao_publish_and_activate() {
+        let dao = in_memory_dao();
+        assert!(dao.get_active_schema("p", "e").await.unwrap().is_none());
+
+        let v1 = dao.publish_schema("p", "e", schema()).await.unwrap();
+        let v2 = dao
+            .publish_schema("p", "e", json!({ "type": "object" }))
+            .await
+            .unwrap();
+        assert_eq!(v1.version, 1);
+        assert_eq!(v2.version, 2);
+
+        let active = dao.get_active_schema("p", "e").await.unwrap().unwrap();
+        assert_eq!(active.version, 2);
+
+        dao.activate_schema("p", "e", 1).await.unwrap();
+        let active = dao.get_active_schema("p", "e").await.unwrap().unwrap();
+        assert_eq!(active.version, 1);
+        assert_eq!(active.schema, schema());
+
+        assert!(dao.get_active_schema("p", "other").await.unwrap().is_none());
+    }
+
+    #[actix_rt::test]
+    async fn in_memory_event_schemas_dao_publish_err_invalid_schema() {
+        let dao = in_memory_dao();
+        let res = dao
+            .publish_schema("p", "e", json!({ "type": "not a type" }))
+            .await;
+        match res {
+            Err(Error::InvalidSchema(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn in_memory_event_schemas_dao_activate_err() {
+        let dao = in_memory_dao();
+        match dao.activate_schema("p", "e", 1).await {
+            Err(Error::SchemaNotFound(_)) => (),
+            _ => unreachable!(),
+        }
+
+        dao.publish_schema("p", "e", schema()).await.unwrap();
+        for version in [0, 2] {
+            match dao.activate_schema("p", "e", version).await {
+                Err(Error::VersionNotFound(v)) => assert_eq!(v, version),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    // Records with their generation, another gateway publishes before the first conditional write
+    struct TestAerospikeDao {
+        hashmap: RwLock<HashMap<String, (Vec<u8>, u32)>>,
+        interfere: AtomicBool,
+    }
+
+    #[async_trait]
+    impl AerospikeDao for TestAerospikeDao {
+        async fn get(&self, key: &str) -> Result<AerospikeDaoResult, AerospikeDaoError> {
+            let hashmap_r = self.hashmap.read().unwrap();
+            Ok(AerospikeDaoResult {
+                value: hashmap_r.get(key).map(|(value, _)| value.clone()),
+                ttl: None,
+                generation: hashmap_r.get(key).map_or(0, |(_, generation)| *generation),
+            })
+        }
+
+        async fn put(&self, _: &str, _: Vec<u8>) {
+            unreachable!()
+        }
+
+        async fn put_if(
+            &self,
+            key: &str,
+            value: Vec<u8>,
+            expect: Expect,
+        ) -> Result<(), AerospikeDaoError> {
+            let mut hashmap_w = self.hashmap.write().unwrap();
+            if self.interfere.swap(false, Ordering::SeqCst) {
+                let versions = json!({ "active": 1, "schemas": [{ "type": "object" }] });
+                hashmap_w.insert(key.into(), (serde_json::to_vec(&versions).unwrap(), 1));
+            }
+            let generation = hashmap_w.get(key).map_or(0, |(_, generation)| *generation);
+            if expect != Expect::generation(generation) {
+                return Err(AerospikeDaoError::Conflict);
+            }
+            hashmap_w.insert(key.into(), (value, generation + 1));
+            Ok(())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn aerospike_event_schemas_dao_publish_and_activate() {
+        let dao = AerospikeEventSchemasDao {
+            aerospike_dao: Box::new(TestAerospikeDao {
+                hashmap: RwLock::new(HashMap::new()),
+                interfere: AtomicBool::new(true),
+            }),
+            compiled: CompiledSchemas::default(),
+        };
+        assert!(dao.get_active_schema("p", "e").await.unwrap().is_none());
+
+        // Published on top of the version published meanwhile
+        let v2 = dao.publish_schema("p", "e", schema()).await.unwrap();
+        assert_eq!(v2.version, 2);
+        dao.publish_schema("p", "e", json!({})).await.unwrap();
+        dao.activate_schema("p", "e", 2).await.unwrap();
+
+        let active = dao.get_active_schema("p", "e").await.unwrap().unwrap();
+        assert_eq!(active.version, 2);
+        assert_eq!(active.schema, schema());
+        assert!(active.validate(&json!({ "price": "free" })).is_err());
+    }
+}
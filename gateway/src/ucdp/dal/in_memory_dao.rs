@@ -1,9 +1,10 @@
 use log::trace;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
-use ucdp::config::Config;
+use ucdp::config::{Config, Validation};
 
 #[derive(Error, Debug)]
 pub enum InMemoryDaoError {
@@ -24,6 +25,12 @@ pub enum InMemoryDaoError {
 
     #[error("time error")]
     Time(#[from] std::time::SystemTimeError),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("deserialization error")]
+    Deserialization(#[from] serde_json::Error),
 }
 
 #[derive(Clone, Debug)]
@@ -39,6 +46,8 @@ pub trait InMemoryDao<K, V>: Send + Sync {
 
 pub struct InMemoryDaoImpl<K, V> {
     hashmap: Arc<RwLock<HashMap<K, InMemoryDaoResult<V>>>>,
+    // Items never expire when not set
+    ttl: Option<Duration>,
 }
 
 impl<K: std::fmt::Debug + Eq + std::hash::Hash + Send + Sync, V: Clone + Send + Sync>
@@ -53,11 +62,9 @@ impl<K: std::fmt::Debug + Eq + std::hash::Hash + Send + Sync, V: Clone + Send +
         match hashmap_r.get(key) {
             Some(res) => {
                 let duration = SystemTime::now().duration_since(res.date)?;
-                // TODO: expiration duration must be data driven
-                if duration.as_secs() > 10 {
-                    Err(InMemoryDaoError::Expired)
-                } else {
-                    Ok(res.clone())
+                match self.ttl {
+                    Some(ttl) if duration > ttl => Err(InMemoryDaoError::Expired),
+                    _ => Ok(res.clone()),
                 }
             }
             None => Err(InMemoryDaoError::ItemNotFound),
@@ -82,6 +89,15 @@ impl<K: std::fmt::Debug + Eq + std::hash::Hash + Send + Sync, V: Clone + Send +
     }
 }
 
+pub fn validate(validation: &mut Validation) {
+    validation.optional("in_memory.ttl", Config::get_int);
+    if let Some(path) = validation.optional("in_memory.seed", Config::get_str) {
+        if !std::path::Path::new(&path).is_file() {
+            validation.invalid("in_memory.seed", format!("no file at {}", path));
+        }
+    }
+}
+
 pub struct InMemoryDaoBuilder<K, V> {
     _k: std::marker::PhantomData<K>,
     _r: std::marker::PhantomData<V>,
@@ -92,11 +108,37 @@ impl<
         V: 'static + Clone + Send + Sync,
     > InMemoryDaoBuilder<K, V>
 {
-    pub fn build(_: &Config) -> Result<Box<dyn InMemoryDao<K, V>>, InMemoryDaoError> {
+    pub fn build(config: &Config) -> Result<Box<dyn InMemoryDao<K, V>>, InMemoryDaoError> {
+        // Time to live in seconds, 0 to keep the items forever
+        let ttl = match config.get_int("in_memory.ttl") {
+            Ok(0) => None,
+            Ok(ttl) => Some(Duration::from_secs(ttl as u64)),
+            Err(_) => Some(Duration::from_secs(10)),
+        };
+
         Ok(Box::new(InMemoryDaoImpl {
             hashmap: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
         }))
     }
+
+    // Items of a section of the seed file, nothing if no seed file is configured
+    pub fn seed<T: DeserializeOwned + Default>(
+        config: &Config,
+        section: &str,
+    ) -> Result<T, InMemoryDaoError> {
+        match config.get_str("in_memory.seed") {
+            Ok(path) => {
+                let file = std::fs::File::open(path)?;
+                let mut seed: serde_json::Value = serde_json::from_reader(file)?;
+                match seed.get_mut(section) {
+                    Some(items) => Ok(serde_json::from_value(items.take())?),
+                    None => Ok(T::default()),
+                }
+            }
+            Err(_) => Ok(T::default()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -153,6 +195,7 @@ mod tests {
     fn in_memory_dao_get_ok() {
         let dao = InMemoryDaoImpl {
             hashmap: hashmap(SystemTime::now()),
+            ttl: Some(Duration::from_secs(10)),
         };
 
         let res = dao.get(&"ABC".into()).unwrap();
@@ -172,6 +215,7 @@ mod tests {
     fn in_memory_dao_get_err_not_found() {
         let dao = InMemoryDaoImpl {
             hashmap: hashmap(SystemTime::now()),
+            ttl: Some(Duration::from_secs(10)),
         };
 
         let res = dao.get(&"not found".into());
@@ -185,6 +229,7 @@ mod tests {
     fn in_memory_dao_get_err_expired() {
         let dao = InMemoryDaoImpl {
             hashmap: hashmap(SystemTime::UNIX_EPOCH),
+            ttl: Some(Duration::from_secs(10)),
         };
 
         let res = dao.get(&"ABC".into());
@@ -198,6 +243,7 @@ mod tests {
     fn in_memory_dao_get_err_time() {
         let dao = InMemoryDaoImpl {
             hashmap: hashmap(SystemTime::now() + Duration::from_secs(60)),
+            ttl: Some(Duration::from_secs(10)),
         };
 
         let res = dao.get(&"ABC".into());
@@ -211,6 +257,7 @@ mod tests {
     fn in_memory_dao_put_ok() {
         let dao = InMemoryDaoImpl {
             hashmap: hashmap(SystemTime::now()),
+            ttl: Some(Duration::from_secs(10)),
         };
         dao.put(
             "123".into(),
@@ -231,4 +278,56 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn in_memory_dao_get_ok_no_ttl() {
+        let dao = InMemoryDaoImpl {
+            hashmap: hashmap(SystemTime::UNIX_EPOCH),
+            ttl: None,
+        };
+
+        assert!(dao.get(&"ABC".into()).is_ok());
+    }
+
+    #[test]
+    fn in_memory_dao_seed_ok() {
+        let path = std::env::temp_dir().join("ucdp-in-memory-dao-seed.json");
+        std::fs::write(
+            &path,
+            r#"{"partners": {"ABC": {"name": "ABC", "enabled": true}}}"#,
+        )
+        .unwrap();
+        let mut config = config::Config::default();
+        let _ = config.set("in_memory.seed", path.to_str().unwrap());
+        let config = Config::from(config);
+
+        let partners = InMemoryDaoBuilder::<String, Partner>::seed::<HashMap<String, Partner>>(
+            &config, "partners",
+        )
+        .unwrap();
+        assert_eq!(partners.len(), 1);
+        assert!(partners["ABC"].enabled);
+
+        let users = InMemoryDaoBuilder::<String, Partner>::seed::<HashMap<String, Vec<String>>>(
+            &config, "users",
+        )
+        .unwrap();
+        assert!(users.is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn in_memory_dao_seed_err_io() {
+        let mut config = config::Config::default();
+        let _ = config.set("in_memory.seed", "/not/a/file.json");
+        let config = Config::from(config);
+
+        match InMemoryDaoBuilder::<String, Partner>::seed::<HashMap<String, Partner>>(
+            &config, "partners",
+        ) {
+            Err(InMemoryDaoError::Io(_)) => {}
+            _ => unreachable!(),
+        }
+    }
 }
@@ -1,11 +1,17 @@
 mod authorized_partners_by_user;
+pub use self::authorized_partners_by_user::Authorization;
 pub use self::authorized_partners_by_user::AuthorizedPartnersByUserBuilder;
 pub use self::authorized_partners_by_user::AuthorizedPartnersByUserDao;
-
-#[cfg(test)]
 pub type AuthorizedPartnersByUserError = self::authorized_partners_by_user::Error;
 
+mod event_schemas;
+pub use self::event_schemas::EventSchema;
+pub use self::event_schemas::EventSchemasBuilder;
+pub use self::event_schemas::EventSchemasDao;
+pub type EventSchemasError = self::event_schemas::Error;
+
 mod partners;
+#[cfg(test)]
 pub use self::partners::Partner;
 pub use self::partners::PartnersBuilder;
 pub use self::partners::PartnersDao;
@@ -15,5 +21,10 @@ pub type PartnersError = self::partners::Error;
 
 // Implementation specific Dao
 mod aerospike_dao;
+mod consent_log;
 mod ethereum_dao;
+pub use self::ethereum_dao::contract_address_key;
+pub use self::ethereum_dao::EthereumDaoError;
+mod ethereum_transport;
+pub use self::ethereum_transport::EthereumTransport;
 mod in_memory_dao;
@@ -1,12 +1,18 @@
-use crate::ucdp::dal::aerospike_dao::{AerospikeDao, AerospikeDaoBuilder, AerospikeDaoError};
-use crate::ucdp::dal::ethereum_dao::{EthereumDao, EthereumDaoBuilder, EthereumDaoError};
-use crate::ucdp::dal::in_memory_dao::{InMemoryDao, InMemoryDaoBuilder, InMemoryDaoError};
+use crate::ucdp::dal::aerospike_dao::{self, AerospikeDao, AerospikeDaoBuilder, AerospikeDaoError};
+use crate::ucdp::dal::ethereum_dao::{self, EthereumDao, EthereumDaoBuilder, EthereumDaoError};
+use crate::ucdp::dal::in_memory_dao::{self, InMemoryDao, InMemoryDaoBuilder, InMemoryDaoError};
+use crate::ucdp::metrics;
 use async_trait::async_trait;
 use log::trace;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt};
+use opentelemetry::{Context, KeyValue};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::Instant;
 use thiserror::Error;
-use ucdp::config::Config;
+use ucdp::config::{Config, Validation};
+use ucdp::telemetry;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Partner {
@@ -59,7 +65,7 @@ impl PartnersDao for EthereumPartnersDao<'_> {
             .map_err(|_| Error::Parameter("partner_id".into()))?;
 
         self.ethereum_dao
-            .get((partner_address,))
+            .get((partner_address,), None)
             .await
             .map(|(name, enabled, _)| Partner {
                 name: String::from_utf8(name)
@@ -112,7 +118,7 @@ impl PartnersDao for InMemoryPartnersDao {
     async fn get_partner(&self, partner_id: &str) -> Result<Partner, Error> {
         trace!("InMemoryPartnersDao get {:?}", partner_id);
         self.in_memory_dao
-            .get(&String::from(partner_id))
+            .get(&partner_id.to_lowercase())
             .map(|res| res.value)
             .map_err(Error::InMemoryDao)
     }
@@ -120,11 +126,44 @@ impl PartnersDao for InMemoryPartnersDao {
     async fn put_partner(&self, partner_id: &str, partner: &Partner) {
         trace!("InMemoryPartnersDao put {:?}", partner_id);
         self.in_memory_dao
-            .put(String::from(partner_id), partner.clone())
+            .put(partner_id.to_lowercase(), partner.clone())
+    }
+}
+
+// Time and trace the lookups of a connector layer
+struct MeteredPartnersDao {
+    connector: String,
+    dao: Box<dyn PartnersDao>,
+}
+
+#[async_trait]
+impl PartnersDao for MeteredPartnersDao {
+    async fn get_partner(&self, partner_id: &str) -> Result<Partner, Error> {
+        let cx = telemetry::span("partners lookup", SpanKind::Internal, &Context::current());
+        cx.span()
+            .set_attribute(KeyValue::new("dao.connector", self.connector.clone()));
+
+        let start = Instant::now();
+        let res = self
+            .dao
+            .get_partner(partner_id)
+            .with_context(cx.clone())
+            .await;
+        metrics::observe_dao("partners", &self.connector, start);
+        if let Err(error) = &res {
+            cx.span().set_status(Status::error(error.to_string()));
+        }
+        res
+    }
+
+    async fn put_partner(&self, partner_id: &str, partner: &Partner) {
+        self.dao.put_partner(partner_id, partner).await
     }
 }
 
 struct CachePartnersDao {
+    // Connector of the cache, to label hits and misses
+    connector: String,
     cache_dao: Box<dyn PartnersDao>,
     underlying_dao: Box<dyn PartnersDao>,
 }
@@ -133,7 +172,11 @@ struct CachePartnersDao {
 impl PartnersDao for CachePartnersDao {
     async fn get_partner(&self, partner_id: &str) -> Result<Partner, Error> {
         trace!("CachePartnersDao get {:?}", partner_id);
-        match self.cache_dao.get_partner(partner_id).await {
+        let res = self.cache_dao.get_partner(partner_id).await;
+        metrics::PARTNERS_CACHE
+            .with_label_values(&[&self.connector, if res.is_ok() { "hit" } else { "miss" }])
+            .inc();
+        match res {
             Err(_) => {
                 let res = self.underlying_dao.get_partner(partner_id).await;
                 if let Ok(partner) = res {
@@ -156,10 +199,25 @@ impl PartnersDao for CachePartnersDao {
 pub struct PartnersBuilder {}
 
 impl PartnersBuilder {
+    // Sections build reads, the DAOs are kept on reload while they do not change
+    pub const SECTIONS: [&'static str; 4] = ["data.partners", "ethereum", "aerospike", "in_memory"];
+
     fn build_dao(connector: &str, config: &Config) -> Result<Box<dyn PartnersDao>, Error> {
+        let dao = PartnersBuilder::build_connector_dao(connector, config)?;
+        Ok(Box::new(MeteredPartnersDao {
+            connector: connector.into(),
+            dao,
+        }))
+    }
+
+    fn build_connector_dao(
+        connector: &str,
+        config: &Config,
+    ) -> Result<Box<dyn PartnersDao>, Error> {
         match connector {
             "ethereum" => {
-                let ethereum_dao = EthereumDaoBuilder::build(config, "partners")?;
+                let ethereum_dao =
+                    EthereumDaoBuilder::build(config, "data.partners.contract", "partners")?;
                 let dao = EthereumPartnersDao { ethereum_dao };
                 Ok(Box::new(dao))
             }
@@ -170,6 +228,12 @@ impl PartnersBuilder {
             }
             "in-memory" => {
                 let in_memory_dao = InMemoryDaoBuilder::build(config)?;
+                let partners: HashMap<String, Partner> =
+                    InMemoryDaoBuilder::<String, Partner>::seed(config, "partners")?;
+                // Addresses are compared lowercase, whatever their checksum casing
+                for (partner_id, partner) in partners {
+                    in_memory_dao.put(partner_id.to_lowercase(), partner);
+                }
                 let dao = InMemoryPartnersDao { in_memory_dao };
                 Ok(Box::new(dao))
             }
@@ -177,13 +241,33 @@ impl PartnersBuilder {
         }
     }
 
+    pub fn validate(validation: &mut Validation) {
+        let key = "data.partners.connectors";
+        if let Some(connectors) = validation.required(key, Config::get_str_vec) {
+            if connectors.is_empty() {
+                validation.invalid(key, "no connector".into());
+            }
+            for connector in connectors {
+                match connector.as_str() {
+                    "ethereum" => {
+                        ethereum_dao::validate(validation, "data.partners.contract", "partners")
+                    }
+                    "aerospike" => aerospike_dao::validate(validation),
+                    "in-memory" => in_memory_dao::validate(validation),
+                    connector => {
+                        validation.invalid(key, format!("unknown connector '{}'", connector))
+                    }
+                }
+            }
+        }
+    }
+
     pub fn build(config: &Config) -> Result<Box<dyn PartnersDao>, Error> {
         let connectors = config.get_str_vec("data.partners.connectors")?;
         PartnersBuilder::build_rec(&connectors, config)
     }
 
     fn build_rec(connectors: &[String], config: &Config) -> Result<Box<dyn PartnersDao>, Error> {
-        println!("{:?}", connectors);
         match connectors.len() {
             0 => Err(Error::UnknownConnector("".into())),
             1 => PartnersBuilder::build_dao(connectors[0].as_str(), config),
@@ -191,6 +275,7 @@ impl PartnersBuilder {
                 let cache_dao = PartnersBuilder::build_dao(connectors[0].as_str(), config)?;
                 let underlying_dao = PartnersBuilder::build_rec(&connectors[1..], config)?;
                 let dao = CachePartnersDao {
+                    connector: connectors[0].clone(),
                     cache_dao,
                     underlying_dao,
                 };
@@ -202,17 +287,49 @@ impl PartnersBuilder {
 
 #[cfg(test)]
 mod tests {
-    use crate::ucdp::dal::aerospike_dao::{AerospikeDao, AerospikeDaoError, AerospikeDaoResult};
+    use crate::ucdp::dal::aerospike_dao::{
+        AerospikeDao, AerospikeDaoError, AerospikeDaoResult, Expect,
+    };
     use crate::ucdp::dal::ethereum_dao::{EthereumDao, EthereumDaoError};
     use crate::ucdp::dal::in_memory_dao::{InMemoryDao, InMemoryDaoError, InMemoryDaoResult};
     use crate::ucdp::dal::partners::{
         AerospikePartnersDao, CachePartnersDao, Error, EthereumPartnersDao, InMemoryPartnersDao,
+        MeteredPartnersDao,
     };
     use crate::ucdp::dal::PartnersDao;
     use crate::ucdp::dal::{Partner, PartnersBuilder};
+    use crate::ucdp::metrics;
     use async_trait::async_trait;
     use std::time::SystemTime;
-    use ucdp::config::Config;
+    use ucdp::config::{Config, Validation};
+    #[test]
+    fn partnersbuilder_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set(
+            "data.partners.connectors",
+            vec!["in-memory", "ethereum", "unknown"],
+        );
+        let _ = config.set("in_memory.ttl", "forever");
+        let _ = config.set("ethereum.contract", "not an address");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        PartnersBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(ucdp::config::Error::Validation(problems)) => {
+                assert_eq!(problems.len(), 4);
+                assert!(problems[0].starts_with("in_memory.ttl: "));
+                assert_eq!(problems[1], "ethereum.network: missing");
+                assert!(problems[2].starts_with("ethereum.contract: "));
+                assert_eq!(
+                    problems[3],
+                    "data.partners.connectors: unknown connector 'unknown'"
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn partnersbuilder_build_non_cached_ok_ethereum() {
         let mut config = config::Config::default();
@@ -295,6 +412,7 @@ mod tests {
         async fn get(
             &self,
             _: (web3::types::Address,),
+            _: Option<web3::types::BlockId>,
         ) -> Result<(Vec<u8>, bool, bool), EthereumDaoError> {
             Ok((
                 vec![
@@ -313,6 +431,7 @@ mod tests {
         async fn get(
             &self,
             _: (web3::types::Address,),
+            _: Option<web3::types::BlockId>,
         ) -> Result<(Vec<u8>, bool, bool), EthereumDaoError> {
             Err(EthereumDaoError::Parameter("".into()))
         }
@@ -383,14 +502,17 @@ mod tests {
                             .to_vec(),
                     ),
                     ttl: None,
+                    generation: 1,
                 }),
                 "not found" => Ok(AerospikeDaoResult {
                     value: None,
                     ttl: None,
+                    generation: 0,
                 }),
                 "deserialization error" => Ok(AerospikeDaoResult {
                     value: Some("{\"name\":\"partner\"...".as_bytes().to_vec()),
                     ttl: None,
+                    generation: 1,
                 }),
                 _ => Err(AerospikeDaoError::ItemNotFound),
             }
@@ -399,6 +521,10 @@ mod tests {
         async fn put(&self, _: &str, _: Vec<u8>) {
             unreachable!()
         }
+
+        async fn put_if(&self, _: &str, _: Vec<u8>, _: Expect) -> Result<(), AerospikeDaoError> {
+            unreachable!()
+        }
     }
 
     #[actix_rt::test]
@@ -505,6 +631,28 @@ mod tests {
         }
     }
 
+    #[actix_rt::test]
+    async fn partners_in_memory_seeded() {
+        let path = std::env::temp_dir().join("ucdp-partners-seed.json");
+        std::fs::write(
+            &path,
+            r#"{"partners": {"0xABC": {"name": "partner", "enabled": true}}}"#,
+        )
+        .unwrap();
+        let mut config = config::Config::default();
+        let _ = config.set("data.partners.connectors", vec!["in-memory"]);
+        let _ = config.set("in_memory.seed", path.to_str().unwrap());
+        let _ = config.set("in_memory.ttl", 0);
+        let config = Config::from(config);
+
+        let partners = PartnersBuilder::build(&config).unwrap();
+        assert!(partners.get_partner("0xabc").await.unwrap().enabled);
+        assert!(partners.get_partner("0xAbC").await.unwrap().enabled);
+        assert!(partners.get_partner("0x123").await.is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+
     struct CacheHitDao {}
     #[async_trait]
     impl PartnersDao for CacheHitDao {
@@ -532,7 +680,9 @@ mod tests {
 
     #[actix_rt::test]
     async fn partners_dao_cache_hit() {
+        let hits = metrics::PARTNERS_CACHE.with_label_values(&["cache hit", "hit"]);
         let cache_partners_dao = CachePartnersDao {
+            connector: "cache hit".into(),
             cache_dao: Box::new(CacheHitDao {}),
             underlying_dao: Box::new(UnreachableDao {}),
         };
@@ -548,6 +698,7 @@ mod tests {
                 enabled: true
             }
         );
+        assert_eq!(hits.get(), 1);
     }
 
     struct CacheMissDao {}
@@ -571,7 +722,9 @@ mod tests {
 
     #[actix_rt::test]
     async fn partners_dao_cache_miss() {
+        let misses = metrics::PARTNERS_CACHE.with_label_values(&["cache miss", "miss"]);
         let cache_partners_dao = CachePartnersDao {
+            connector: "cache miss".into(),
             cache_dao: Box::new(CacheMissDao {}),
             underlying_dao: Box::new(CacheHitDao {}),
         };
@@ -587,5 +740,21 @@ mod tests {
                 enabled: true
             }
         );
+        assert_eq!(misses.get(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn partners_dao_metered() {
+        let histogram = metrics::DAO_DURATION.with_label_values(&["partners", "metered"]);
+        let metered_partners_dao = MeteredPartnersDao {
+            connector: "metered".into(),
+            dao: Box::new(CacheHitDao {}),
+        };
+
+        let res = metered_partners_dao
+            .get_partner("0x0000000000000000000000000000000000000000")
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(histogram.get_sample_count(), 1);
     }
 }
@@ -0,0 +1,418 @@
+use crate::ucdp::dal::{contract_address_key, EthereumDaoError, EthereumTransport};
+use async_trait::async_trait;
+use futures::future::join_all;
+use rdkafka::producer::{BaseProducer, Producer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use ucdp::config::Config;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("config error")]
+    Config(#[from] ucdp::config::Error),
+
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+
+    #[error("aerospike error: {0}")]
+    Aerospike(#[from] aerospike::Error),
+
+    #[error("ethereum error: {0}")]
+    Ethereum(#[from] web3::Error),
+
+    #[error("ethereum error: {0}")]
+    EthereumDao(#[from] EthereumDaoError),
+
+    #[error("parameter error: {0}")]
+    Parameter(String),
+
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("timeout")]
+    Timeout,
+}
+
+// A dependency the gateway cannot serve requests without
+#[async_trait]
+pub trait DependencyCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Some details about the dependency when it is available
+    async fn check(&self) -> Result<String, Error>;
+}
+
+struct KafkaDependencyCheck {
+    producer: Arc<BaseProducer>,
+    topic: String,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl DependencyCheck for KafkaDependencyCheck {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn check(&self) -> Result<String, Error> {
+        // Fetching metadata blocks until the brokers answer or the timeout elapses
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        let timeout = self.timeout;
+        let metadata = actix_web::web::block(move || {
+            producer
+                .client()
+                .fetch_metadata(Some(topic.as_str()), timeout)
+                .map(|metadata| metadata.brokers().len())
+        })
+        .await
+        .map_err(|error| Error::Unavailable(error.to_string()))??;
+        Ok(format!("{} brokers", metadata))
+    }
+}
+
+struct AerospikeDependencyCheck {
+    client: aerospike::Client,
+}
+
+#[async_trait]
+impl DependencyCheck for AerospikeDependencyCheck {
+    fn name(&self) -> &str {
+        "aerospike"
+    }
+
+    async fn check(&self) -> Result<String, Error> {
+        // The client keeps track of the cluster nodes in the background
+        if self.client.is_connected() {
+            Ok(format!("{} nodes", self.client.nodes().len()))
+        } else {
+            Err(Error::Unavailable("no node".into()))
+        }
+    }
+}
+
+struct EthereumDependencyCheck {
+    web3: web3::Web3<EthereumTransport>,
+    contracts: Vec<web3::types::Address>,
+}
+
+#[async_trait]
+impl DependencyCheck for EthereumDependencyCheck {
+    fn name(&self) -> &str {
+        "ethereum"
+    }
+
+    async fn check(&self) -> Result<String, Error> {
+        let block_number = self.web3.eth().block_number().await?;
+        // Failed endpoints are skipped for a while, the others answered
+        for contract in &self.contracts {
+            let code = self.web3.eth().code(*contract, None).await?;
+            if code.0.is_empty() {
+                return Err(Error::Unavailable(format!(
+                    "no contract code at {:?}",
+                    contract
+                )));
+            }
+        }
+        let (healthy, endpoints) = self.web3.transport().healthy();
+        Ok(format!(
+            "block {}, {}/{} endpoints",
+            block_number, healthy, endpoints
+        ))
+    }
+}
+
+#[derive(Serialize)]
+pub struct DependencyStatus {
+    pub status: &'static str,
+    pub detail: String,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub dependencies: BTreeMap<String, DependencyStatus>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.dependencies
+            .values()
+            .all(|dependency| dependency.status == "ok")
+    }
+}
+
+pub struct HealthChecks {
+    checks: Vec<Box<dyn DependencyCheck>>,
+    timeout: Duration,
+}
+
+impl HealthChecks {
+    pub fn new(checks: Vec<Box<dyn DependencyCheck>>, timeout: Duration) -> Self {
+        HealthChecks { checks, timeout }
+    }
+
+    // Check all the dependencies at the same time, each one within the timeout
+    pub async fn readiness(&self) -> Readiness {
+        let results = join_all(self.checks.iter().map(|check| async move {
+            let res = actix_rt::time::timeout(self.timeout, check.check())
+                .await
+                .unwrap_or(Err(Error::Timeout));
+            (check.name(), res)
+        }))
+        .await;
+
+        let dependencies: BTreeMap<String, DependencyStatus> = results
+            .into_iter()
+            .map(|(name, res)| {
+                let status = match res {
+                    Ok(detail) => DependencyStatus {
+                        status: "ok",
+                        detail,
+                    },
+                    Err(error) => DependencyStatus {
+                        status: "unavailable",
+                        detail: error.to_string(),
+                    },
+                };
+                (name.into(), status)
+            })
+            .collect();
+
+        let mut readiness = Readiness {
+            status: "ok",
+            dependencies,
+        };
+        if !readiness.is_ready() {
+            readiness.status = "unavailable";
+        }
+        readiness
+    }
+}
+
+pub struct HealthChecksBuilder {}
+
+impl HealthChecksBuilder {
+    // Only the dependencies of the configured connectors are checked
+    pub fn build(config: &Config) -> Result<HealthChecks, Error> {
+        let mut connectors = config
+            .get_str_vec("data.partners.connectors")
+            .unwrap_or_default();
+        for key in [
+            "data.authorized_partners_by_user.connector",
+            "data.event_schemas.connector",
+        ] {
+            if let Ok(connector) = config.get_str(key) {
+                connectors.push(connector);
+            }
+        }
+        let uses = |connector: &str| connectors.iter().any(|c| c == connector);
+
+        let timeout = match config.get_duration("health.timeout") {
+            Ok(timeout) => timeout,
+            Err(error) if error.is_not_found() => Duration::from_secs(2),
+            Err(error) => return Err(Error::Config(error)),
+        };
+
+        let mut checks: Vec<Box<dyn DependencyCheck>> = vec![];
+        if config.get_str("stream.connector").ok().as_deref() == Some("kafka") {
+            checks.push(HealthChecksBuilder::build_kafka(config, timeout)?);
+        }
+        if uses("aerospike") {
+            checks.push(HealthChecksBuilder::build_aerospike(config)?);
+        }
+        if uses("ethereum") {
+            checks.push(HealthChecksBuilder::build_ethereum(config)?);
+        }
+
+        Ok(HealthChecks::new(checks, timeout))
+    }
+
+    fn build_kafka(config: &Config, timeout: Duration) -> Result<Box<dyn DependencyCheck>, Error> {
+        let mut client_config = rdkafka::config::ClientConfig::new();
+        client_config.set("bootstrap.servers", config.get_str("stream.kafka.broker")?);
+        // Same properties as the stream producer, so that the same brokers are reached the same way
+        for (key, value) in config
+            .get_flat_table("stream.kafka.producer")
+            .unwrap_or_default()
+        {
+            client_config.set(key, value);
+        }
+
+        Ok(Box::new(KafkaDependencyCheck {
+            producer: Arc::new(client_config.create()?),
+            topic: config.get_str("stream.kafka.topic")?,
+            timeout,
+        }))
+    }
+
+    fn build_aerospike(config: &Config) -> Result<Box<dyn DependencyCheck>, Error> {
+        let host = config.get_str("aerospike.host")?;
+        // The check reports the connection state rather than failing to start
+        let client_policy = aerospike::ClientPolicy {
+            fail_if_not_connected: false,
+            ..Default::default()
+        };
+        let client = aerospike::Client::new(&client_policy, &host)?;
+
+        Ok(Box::new(AerospikeDependencyCheck { client }))
+    }
+
+    // Every contract read by the DAOs with an ethereum connector
+    fn build_ethereum(config: &Config) -> Result<Box<dyn DependencyCheck>, Error> {
+        let mut contracts = vec![];
+        for (connectors_key, contract_key) in [
+            ("data.partners.connectors", "data.partners.contract"),
+            (
+                "data.authorized_partners_by_user.connector",
+                "data.authorized_partners_by_user.contract",
+            ),
+        ] {
+            let connectors = config
+                .get_str_vec(connectors_key)
+                .or_else(|_| {
+                    config
+                        .get_str(connectors_key)
+                        .map(|connector| vec![connector])
+                })
+                .unwrap_or_default();
+            if !connectors.iter().any(|connector| connector == "ethereum") {
+                continue;
+            }
+            let key = contract_address_key(config, contract_key);
+            let contract = web3::types::Address::from_str(config.get_str(&key)?.as_str())
+                .map_err(|_| Error::Parameter(key))?;
+            if !contracts.contains(&contract) {
+                contracts.push(contract);
+            }
+        }
+        let transport = EthereumTransport::shared(config)?;
+
+        Ok(Box::new(EthereumDependencyCheck {
+            web3: web3::Web3::new(transport),
+            contracts,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependencyCheck, Error, HealthChecks, HealthChecksBuilder};
+    use async_trait::async_trait;
+    use std::time::Duration;
+    use ucdp::config::Config;
+
+    struct TestDependencyCheck {
+        name: &'static str,
+        available: bool,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl DependencyCheck for TestDependencyCheck {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn check(&self) -> Result<String, Error> {
+            actix_rt::time::sleep(self.delay).await;
+            if self.available {
+                Ok("detail".into())
+            } else {
+                Err(Error::Unavailable("reason".into()))
+            }
+        }
+    }
+
+    fn check(name: &'static str, available: bool, delay: u64) -> Box<dyn DependencyCheck> {
+        Box::new(TestDependencyCheck {
+            name,
+            available,
+            delay: Duration::from_millis(delay),
+        })
+    }
+
+    #[actix_rt::test]
+    async fn health_checks_ready() {
+        let health_checks = HealthChecks::new(
+            vec![check("a", true, 0), check("b", true, 0)],
+            Duration::from_secs(1),
+        );
+        let readiness = health_checks.readiness().await;
+        assert!(readiness.is_ready());
+        assert_eq!(readiness.status, "ok");
+        assert_eq!(readiness.dependencies["a"].detail, "detail");
+    }
+
+    #[actix_rt::test]
+    async fn health_checks_not_ready() {
+        let health_checks = HealthChecks::new(
+            vec![
+                check("a", true, 0),
+                check("b", false, 0),
+                check("c", true, 1000),
+            ],
+            Duration::from_millis(100),
+        );
+        let readiness = health_checks.readiness().await;
+        assert!(!readiness.is_ready());
+        assert_eq!(readiness.status, "unavailable");
+        assert_eq!(readiness.dependencies["a"].status, "ok");
+        assert_eq!(readiness.dependencies["b"].detail, "unavailable: reason");
+        assert_eq!(readiness.dependencies["c"].detail, "timeout");
+    }
+
+    #[actix_rt::test]
+    async fn health_checks_builder_build_ok() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.topic", "events");
+        let _ = config.set("data.partners.connectors", vec!["in-memory", "aerospike"]);
+        let _ = config.set("data.authorized_partners_by_user.connector", "ethereum");
+        let _ = config.set("aerospike.host", "127.0.0.1:0");
+        let _ = config.set("ethereum.network", "http://127.0.0.1:0");
+        let _ = config.set(
+            "ethereum.contract",
+            "0x0000000000000000000000000000000000000000",
+        );
+        let config = Config::from(config);
+
+        let health_checks = HealthChecksBuilder::build(&config).unwrap();
+        let names: Vec<&str> = health_checks
+            .checks
+            .iter()
+            .map(|check| check.name())
+            .collect();
+        assert_eq!(names, vec!["kafka", "aerospike", "ethereum"]);
+    }
+
+    #[test]
+    fn health_checks_builder_build_no_dependency() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "channel");
+        let _ = config.set("data.partners.connectors", vec!["in-memory"]);
+        let config = Config::from(config);
+
+        let health_checks = HealthChecksBuilder::build(&config).unwrap();
+        assert!(health_checks.checks.is_empty());
+    }
+
+    #[test]
+    fn health_checks_builder_build_err_parameter() {
+        let mut config = config::Config::default();
+        let _ = config.set("data.authorized_partners_by_user.connector", "ethereum");
+        let _ = config.set("ethereum.network", "http://127.0.0.1:0");
+        let _ = config.set("ethereum.contract", "not an address");
+        let config = Config::from(config);
+
+        match HealthChecksBuilder::build(&config) {
+            Err(Error::Parameter(parameter)) => assert_eq!(parameter, "ethereum.contract"),
+            _ => unreachable!(),
+        }
+    }
+}
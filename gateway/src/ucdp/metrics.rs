@@ -0,0 +1,91 @@
+use actix_web::dev::ServiceResponse;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use std::time::Instant;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gateway_http_requests_total",
+        "HTTP requests by method, route and status",
+        &["method", "path", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "gateway_http_request_duration_seconds",
+        "HTTP request latency by method, route and status",
+        &["method", "path", "status"]
+    )
+    .unwrap()
+});
+
+// Partners are labelled "unknown" until they have been found enabled
+pub static EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gateway_events_total",
+        "Events accepted or rejected by partner",
+        &["partner", "result"]
+    )
+    .unwrap()
+});
+
+pub static DAO_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "gateway_dao_duration_seconds",
+        "DAO lookup latency by connector layer",
+        &["dao", "connector"]
+    )
+    .unwrap()
+});
+
+pub static PARTNERS_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "gateway_partners_cache_total",
+        "Partners cache hits and misses by connector",
+        &["connector", "result"]
+    )
+    .unwrap()
+});
+
+pub static STREAM_PRODUCER_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "gateway_stream_producer_queue_depth",
+        "Events waiting to be sent to the stream"
+    )
+    .unwrap()
+});
+
+pub const UNKNOWN_PARTNER: &str = "unknown";
+
+pub fn observe_request<B>(res: &ServiceResponse<B>, start: Instant) {
+    let request = res.request();
+    // Route patterns rather than paths, so that ids do not end up in labels
+    let path = request
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".into());
+    let status = res.status().as_u16().to_string();
+    let labels = [request.method().as_str(), path.as_str(), status.as_str()];
+
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+}
+
+pub fn observe_dao(dao: &str, connector: &str, start: Instant) {
+    DAO_DURATION
+        .with_label_values(&[dao, connector])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+// Metrics of the default registry in the Prometheus text format
+pub fn encode() -> Result<(String, String), prometheus::Error> {
+    let encoder = TextEncoder::new();
+    let body = encoder.encode_to_string(&prometheus::gather())?;
+    Ok((encoder.format_type().into(), body))
+}
@@ -1,3 +1,6 @@
 pub mod api;
+pub mod consent;
 pub mod dal;
+pub mod health;
+pub mod metrics;
 pub mod web;
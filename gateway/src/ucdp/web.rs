@@ -1,71 +1,257 @@
-use crate::ucdp::api::{ErrorResponse, OkResponse};
+use crate::ucdp::api::{
+    ActivateSchemaRequest, AuthorizedPartners, ConsentRequest, ConsentResponse, ErrorResponse,
+    OkResponse,
+};
+use crate::ucdp::consent::{self, Consent, ConsentDomain, ConsentRateLimit, Listing};
 use crate::ucdp::dal::{
-    AuthorizedPartnersByUserBuilder, AuthorizedPartnersByUserDao, PartnersBuilder, PartnersDao,
+    AuthorizedPartnersByUserBuilder, AuthorizedPartnersByUserDao, AuthorizedPartnersByUserError,
+    EventSchema, EventSchemasBuilder, EventSchemasDao, EventSchemasError, PartnersBuilder,
+    PartnersDao,
 };
+use crate::ucdp::health::{HealthChecks, HealthChecksBuilder};
+use crate::ucdp::metrics;
 use actix_cors::Cors;
-use actix_web::{http::header, middleware::Logger, post, web, App, HttpResponse, HttpServer};
-use ucdp::config::Config;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{get, http::header, post, put, web, App, HttpMessage, HttpResponse, HttpServer};
+use arc_swap::ArcSwap;
+use log::{debug, info};
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt};
+use opentelemetry::{Context, KeyValue};
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use ucdp::config::{Config, Source, Validation};
+use ucdp::telemetry;
 use uuid::Uuid;
+use web3::types::{Address, Bytes};
+
+// Partner and consent lookups, each rebuilt when the sections it reads change
+struct Daos {
+    // Configuration the DAOs were built with
+    config: Config,
+    partners: Arc<dyn PartnersDao>,
+    authorized_partners_by_user: Arc<dyn AuthorizedPartnersByUserDao>,
+    // Signed consent is refused when not configured
+    consent_domain: Option<ConsentDomain>,
+    // Kept along with the domain, a reload does not give users a new allowance
+    consent_rate_limit: Arc<ConsentRateLimit>,
+}
+
+impl Daos {
+    fn build(config: &Config) -> Result<Self, String> {
+        Daos::rebuild(None, config)
+    }
+
+    // Unchanged DAOs are kept with their caches, clients and in-memory data
+    fn rebuild(previous: Option<&Daos>, config: &Config) -> Result<Self, String> {
+        let kept =
+            |sections: &[&str]| previous.filter(|daos| !daos.config.differs(config, sections));
+        Ok(Daos {
+            config: config.clone(),
+            partners: match kept(&PartnersBuilder::SECTIONS) {
+                Some(daos) => daos.partners.clone(),
+                None => Arc::from(
+                    PartnersBuilder::build(config).map_err(|error| format!("{:?}", error))?,
+                ),
+            },
+            authorized_partners_by_user: match kept(&AuthorizedPartnersByUserBuilder::SECTIONS) {
+                Some(daos) => daos.authorized_partners_by_user.clone(),
+                None => Arc::from(
+                    AuthorizedPartnersByUserBuilder::build(config)
+                        .map_err(|error| format!("{:?}", error))?,
+                ),
+            },
+            consent_domain: match kept(&ConsentDomain::SECTIONS) {
+                Some(daos) => daos.consent_domain.clone(),
+                None => ConsentDomain::build(config).map_err(|error| format!("{:?}", error))?,
+            },
+            consent_rate_limit: match kept(&ConsentDomain::SECTIONS) {
+                Some(daos) => daos.consent_rate_limit.clone(),
+                None => Arc::new(
+                    ConsentRateLimit::build(config).map_err(|error| format!("{:?}", error))?,
+                ),
+            },
+        })
+    }
+}
 
 struct AppState {
     sender: crossbeam_channel::Sender<ucdp::stream::events::Events>,
-    partners: Box<dyn PartnersDao>,
-    authorized_partners_by_user: Box<dyn AuthorizedPartnersByUserDao>,
+    daos: ArcSwap<Daos>,
+    // Not rebuilt on reload, the in-memory connector holds the published schemas
+    event_schemas: Box<dyn EventSchemasDao>,
+}
+
+impl AppState {
+    // Requests being handled complete with the DAOs they started with
+    fn reload(&self, config: &Config) -> Result<(), String> {
+        let daos = Daos::rebuild(Some(&self.daos.load()), config)?;
+        self.daos.store(Arc::new(daos));
+        Ok(())
+    }
+}
+
+// Count the events of a rejected request
+fn rejected(
+    partner_id: &str,
+    req: &crate::ucdp::api::Events,
+    response: HttpResponse,
+) -> HttpResponse {
+    metrics::EVENTS
+        .with_label_values(&[partner_id, "rejected"])
+        .inc_by(req.events.len() as u64);
+    response
+}
+
+// Id of a request, returned to the caller and carried with the events to correlate logs
+#[derive(Clone)]
+struct RequestId(String);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Listing of a user, signed by the user until the deadline
+const LISTING_DEADLINE_HEADER: &str = "x-ucdp-deadline";
+const LISTING_SIGNATURE_HEADER: &str = "x-ucdp-signature";
+
+fn new_request_id() -> String {
+    Uuid::new_v4().to_simple().to_string()
 }
 
 // TODO move to api
 #[post("/v1/events")]
 async fn proxy(
     req: web::Json<crate::ucdp::api::Events>,
+    request_id: Option<web::ReqData<RequestId>>,
     state: web::Data<AppState>,
 ) -> HttpResponse {
+    let request_id = request_id
+        .map(|request_id| request_id.into_inner().0)
+        .unwrap_or_else(new_request_id);
+
     if req.events.is_empty() {
-        return HttpResponse::BadRequest().json(&ErrorResponse {
-            error: String::from("Events array must not be empty."),
-        });
+        return rejected(
+            metrics::UNKNOWN_PARTNER,
+            &req,
+            HttpResponse::BadRequest().json(&ErrorResponse {
+                error: String::from("Events array must not be empty."),
+            }),
+        );
     }
     if req.events.len() > 100 {
-        return HttpResponse::BadRequest().json(&ErrorResponse {
-            error: String::from("Events array must not be larger than 100 events."),
-        });
+        return rejected(
+            metrics::UNKNOWN_PARTNER,
+            &req,
+            HttpResponse::BadRequest().json(&ErrorResponse {
+                error: String::from("Events array must not be larger than 100 events."),
+            }),
+        );
     }
-    // Check partner id
+    // Same DAOs for the whole request, even if the configuration changes meanwhile
+    let daos = state.daos.load_full();
+
     let partner_id = req.partner.id.as_str();
-    match state.partners.get_partner(partner_id).await {
+    let user_id = req.user.id.as_str();
+    // match state.users.get_user(user_id).await ...
+
+    // Both checks at once, so that they share a round trip to the chain
+    let (partner, authorization) = futures::join!(
+        daos.partners.get_partner(partner_id),
+        daos.authorized_partners_by_user
+            .authorization(user_id, partner_id)
+    );
+
+    // Check partner id
+    match partner {
+        // Unregistered partners read as disabled, labelling them would not be bounded
         Ok(partner) if !partner.enabled => {
-            return HttpResponse::Forbidden().json(&ErrorResponse {
-                error: String::from("Partner must be enabled."),
-            });
+            return rejected(
+                metrics::UNKNOWN_PARTNER,
+                &req,
+                HttpResponse::Forbidden().json(&ErrorResponse {
+                    error: String::from("Partner must be enabled."),
+                }),
+            );
         }
         Err(error) => {
-            return HttpResponse::InternalServerError().json(&ErrorResponse {
-                error: error.to_string(),
-            });
+            return rejected(
+                metrics::UNKNOWN_PARTNER,
+                &req,
+                HttpResponse::InternalServerError().json(&ErrorResponse {
+                    error: error.to_string(),
+                }),
+            );
         }
         _ => {}
     }
 
-    // Check user id
-    let user_id = req.user.id.as_str();
-    // match state.users.get_user(user_id).await ...
-
     // Check that user has authorized the partner ...
-    match state
-        .authorized_partners_by_user
-        .is_authorized(user_id, partner_id)
-        .await
-    {
-        Ok(false) => {
-            return HttpResponse::Forbidden().json(&ErrorResponse {
-                error: String::from("User has not autorized partner."),
-            })
+    let consent_block = match authorization {
+        Ok(authorization) if !authorization.authorized => {
+            return rejected(
+                partner_id,
+                &req,
+                HttpResponse::Forbidden().json(&ErrorResponse {
+                    error: String::from("User has not autorized partner."),
+                }),
+            )
         }
         Err(error) => {
-            return HttpResponse::InternalServerError().json(&ErrorResponse {
-                error: error.to_string(),
-            })
+            return rejected(
+                partner_id,
+                &req,
+                HttpResponse::InternalServerError().json(&ErrorResponse {
+                    error: error.to_string(),
+                }),
+            )
+        }
+        // Recorded with the events, so that they can be proven against chain state
+        Ok(authorization) => authorization.block,
+    };
+
+    // Check event properties against the active schemas declared by the partner
+    let mut event_schemas = HashMap::<&str, Option<EventSchema>>::new();
+    for event in req.events.iter() {
+        let event_name = event.name.as_str();
+        if !event_schemas.contains_key(event_name) {
+            match state
+                .event_schemas
+                .get_active_schema(partner_id, event_name)
+                .await
+            {
+                Ok(event_schema) => {
+                    event_schemas.insert(event_name, event_schema);
+                }
+                Err(error) => {
+                    return rejected(
+                        partner_id,
+                        &req,
+                        HttpResponse::InternalServerError().json(&ErrorResponse {
+                            error: error.to_string(),
+                        }),
+                    )
+                }
+            }
+        }
+        if let Some(Some(event_schema)) = event_schemas.get(event_name) {
+            let properties = event.properties.clone().unwrap_or_default();
+            if let Err(errors) = event_schema.validate(&properties) {
+                return rejected(
+                    partner_id,
+                    &req,
+                    HttpResponse::BadRequest().json(&ErrorResponse {
+                        error: format!(
+                            "Event '{}' does not match schema version {}: {}",
+                            event_name,
+                            event_schema.version,
+                            errors.join(", ")
+                        ),
+                    }),
+                );
+            }
         }
-        _ => {}
     }
 
     // Create a new token
@@ -74,63 +260,495 @@ async fn proxy(
     // Send events. Do not wait.
     let events = ucdp::stream::events::Events {
         token: token.clone(),
+        partner_id: partner_id.into(),
+        user_id: user_id.into(),
         events: req
             .events
             .iter()
             .map(|e| ucdp::stream::events::Event {
                 name: e.name.clone(),
+                properties: e.properties.clone(),
             })
             .collect(),
+        request_id: request_id.clone(),
+        consent_block,
+        // The stream producer continues the trace of the request
+        trace_context: telemetry::inject(&Context::current()),
     };
+    debug!(request_id = request_id.as_str(), token = token.as_str(); "Events accepted");
     let _ = state.sender.send(events);
+    metrics::EVENTS
+        .with_label_values(&[partner_id, "accepted"])
+        .inc_by(req.events.len() as u64);
 
     // Respond immediately
     HttpResponse::Ok().json(&OkResponse { token })
 }
 
+fn event_schemas_error_response(error: EventSchemasError) -> HttpResponse {
+    let response = ErrorResponse {
+        error: error.to_string(),
+    };
+    match error {
+        EventSchemasError::InvalidSchema(_) => HttpResponse::BadRequest().json(&response),
+        EventSchemasError::SchemaNotFound(_) | EventSchemasError::VersionNotFound(_) => {
+            HttpResponse::NotFound().json(&response)
+        }
+        EventSchemasError::Conflict => HttpResponse::Conflict().json(&response),
+        _ => HttpResponse::InternalServerError().json(&response),
+    }
+}
+
+#[get("/v1/admin/partners/{partner_id}/schemas/{event_name}")]
+async fn get_event_schema(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let (partner_id, event_name) = path.into_inner();
+    match state
+        .event_schemas
+        .get_active_schema(partner_id.as_str(), event_name.as_str())
+        .await
+    {
+        Ok(Some(event_schema)) => HttpResponse::Ok().json(&event_schema),
+        Ok(None) => HttpResponse::NotFound().json(&ErrorResponse {
+            error: format!("No schema for event '{}'.", event_name),
+        }),
+        Err(error) => event_schemas_error_response(error),
+    }
+}
+
+#[post("/v1/admin/partners/{partner_id}/schemas/{event_name}")]
+async fn publish_event_schema(
+    path: web::Path<(String, String)>,
+    schema: web::Json<serde_json::Value>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let (partner_id, event_name) = path.into_inner();
+    match state
+        .event_schemas
+        .publish_schema(
+            partner_id.as_str(),
+            event_name.as_str(),
+            schema.into_inner(),
+        )
+        .await
+    {
+        Ok(event_schema) => HttpResponse::Ok().json(&event_schema),
+        Err(error) => event_schemas_error_response(error),
+    }
+}
+
+#[put("/v1/admin/partners/{partner_id}/schemas/{event_name}/active")]
+async fn activate_event_schema(
+    path: web::Path<(String, String)>,
+    req: web::Json<ActivateSchemaRequest>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let (partner_id, event_name) = path.into_inner();
+    match state
+        .event_schemas
+        .activate_schema(partner_id.as_str(), event_name.as_str(), req.version)
+        .await
+    {
+        Ok(event_schema) => HttpResponse::Ok().json(&event_schema),
+        Err(error) => event_schemas_error_response(error),
+    }
+}
+
+fn consent_error_response(error: AuthorizedPartnersByUserError) -> HttpResponse {
+    let response = ErrorResponse {
+        error: error.to_string(),
+    };
+    match error {
+        AuthorizedPartnersByUserError::Parameter(_)
+        | AuthorizedPartnersByUserError::NotRegistered(_) => {
+            HttpResponse::BadRequest().json(&response)
+        }
+        AuthorizedPartnersByUserError::Nonce(_) => HttpResponse::Conflict().json(&response),
+        AuthorizedPartnersByUserError::Unsupported(_) => {
+            HttpResponse::NotImplemented().json(&response)
+        }
+        _ => HttpResponse::InternalServerError().json(&response),
+    }
+}
+
+// Seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+// Partners are listed to the user only, who signs the request
+#[get("/v1/users/{user_id}/partners")]
+async fn get_authorized_partners(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let error = |mut response: actix_web::HttpResponseBuilder, error: &str| {
+        response.json(&ErrorResponse {
+            error: error.into(),
+        })
+    };
+    let user_id = path.into_inner();
+    let user = match Address::from_str(&user_id) {
+        Ok(user) => user,
+        Err(_) => return error(HttpResponse::BadRequest(), "User id must be an address."),
+    };
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let deadline = header(LISTING_DEADLINE_HEADER).and_then(|deadline| deadline.parse().ok());
+    let signature = header(LISTING_SIGNATURE_HEADER)
+        .and_then(|signature| serde_json::from_value::<Bytes>(signature.into()).ok());
+    let (deadline, signature) = match (deadline, signature) {
+        (Some(deadline), Some(signature)) if deadline >= now() => (deadline, signature),
+        _ => {
+            return error(
+                HttpResponse::Unauthorized(),
+                "Listing must be signed by the user before its deadline.",
+            )
+        }
+    };
+
+    let daos = state.daos.load_full();
+    let domain = match &daos.consent_domain {
+        Some(domain) => domain,
+        None => {
+            return error(
+                HttpResponse::NotImplemented(),
+                "Signed consent is not enabled.",
+            )
+        }
+    };
+    if (Listing { user, deadline })
+        .verify(domain, &signature.0)
+        .is_err()
+    {
+        return error(
+            HttpResponse::Forbidden(),
+            "Listing must be signed by the user.",
+        );
+    }
+    let dao = &daos.authorized_partners_by_user;
+    let (partner_ids, nonce) =
+        futures::join!(dao.authorized_partners(&user_id), dao.nonce(&user_id));
+    let nonce = match nonce {
+        Ok(nonce) => Some(nonce),
+        Err(AuthorizedPartnersByUserError::Unsupported(_)) => None,
+        Err(error) => return consent_error_response(error),
+    };
+    match partner_ids {
+        Ok(partner_ids) => HttpResponse::Ok().json(&AuthorizedPartners {
+            partners: partner_ids
+                .into_iter()
+                .map(|id| crate::ucdp::api::Partner { id })
+                .collect(),
+            nonce,
+        }),
+        Err(error) => consent_error_response(error),
+    }
+}
+
+// Grant or revoke consent with a message signed by the user, who pays no gas
+#[post("/v1/users/{user_id}/consents")]
+async fn post_consent(
+    path: web::Path<String>,
+    req: web::Json<ConsentRequest>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let bad_request = |error: &str| {
+        HttpResponse::BadRequest().json(&ErrorResponse {
+            error: error.into(),
+        })
+    };
+    let user = match Address::from_str(&path.into_inner()) {
+        Ok(user) => user,
+        Err(_) => return bad_request("User id must be an address."),
+    };
+    let partner = match Address::from_str(&req.partner.id) {
+        Ok(partner) => partner,
+        Err(_) => return bad_request("Partner id must be an address."),
+    };
+    if req.deadline < now() {
+        return bad_request("Consent has expired.");
+    }
+
+    let daos = state.daos.load_full();
+    let domain = match &daos.consent_domain {
+        Some(domain) => domain,
+        None => {
+            return HttpResponse::NotImplemented().json(&ErrorResponse {
+                error: String::from("Signed consent is not enabled."),
+            })
+        }
+    };
+    let consent = Consent {
+        user,
+        partner,
+        authorized: req.authorized,
+        nonce: req.nonce,
+        deadline: req.deadline,
+    };
+    let signed = match consent.verify(domain, &req.signature.0) {
+        Ok(signed) => signed,
+        Err(consent::Error::InvalidSignature) => {
+            return HttpResponse::Forbidden().json(&ErrorResponse {
+                error: String::from("Consent must be signed by the user."),
+            })
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(&ErrorResponse {
+                error: error.to_string(),
+            })
+        }
+    };
+    // Counted once signed, nobody else can use up the allowance of the user
+    if !daos.consent_rate_limit.acquire(user) {
+        return HttpResponse::TooManyRequests().json(&ErrorResponse {
+            error: String::from("Too many consents, try again later."),
+        });
+    }
+    match daos
+        .authorized_partners_by_user
+        .apply_consent(&signed)
+        .await
+    {
+        // Consent changes once the transaction is mined
+        Ok(Some(transaction)) => HttpResponse::Accepted().json(&ConsentResponse {
+            transaction: Some(format!("{:?}", transaction)),
+        }),
+        Ok(None) => HttpResponse::Ok().json(&ConsentResponse { transaction: None }),
+        Err(error) => consent_error_response(error),
+    }
+}
+
+// Trace, time and log a request of either listener, tagged with a new request id
+fn observe<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let request_id = new_request_id();
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let cx = request_span(&req);
+    let res = srv.call(req).with_context(cx.clone());
+    async move {
+        let mut res = res.await?;
+        metrics::observe_request(&res, start);
+        end_request_span(&cx, &res);
+        access_log(&res, &request_id, start);
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            res.headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(res)
+    }
+}
+
+// Server span of a request, continuing the trace of the caller if any
+fn request_span(req: &ServiceRequest) -> Context {
+    let headers = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let cx = telemetry::span(
+        req.method().to_string(),
+        SpanKind::Server,
+        &telemetry::extract(&headers),
+    );
+    cx.span().set_attribute(KeyValue::new(
+        "http.request.method",
+        req.method().to_string(),
+    ));
+    cx
+}
+
+fn end_request_span<B>(cx: &Context, res: &ServiceResponse<B>) {
+    let span = cx.span();
+    // Routes are only known once the request has been matched
+    if let Some(route) = res.request().match_pattern() {
+        span.update_name(format!("{} {}", res.request().method(), route));
+        span.set_attribute(KeyValue::new("http.route", route));
+    }
+    span.set_attribute(KeyValue::new(
+        "http.response.status_code",
+        res.status().as_u16() as i64,
+    ));
+    if res.status().is_server_error() {
+        span.set_status(Status::error(res.status().to_string()));
+    }
+}
+
+fn access_log<B>(res: &ServiceResponse<B>, request_id: &str, start: Instant) {
+    let request = res.request();
+    info!(
+        request_id = request_id,
+        method = request.method().as_str(),
+        path = request.path(),
+        status = res.status().as_u16(),
+        duration_ms = start.elapsed().as_millis() as u64;
+        "{} {} {}",
+        request.method(),
+        request.path(),
+        res.status().as_u16()
+    );
+}
+
+// The gateway is alive as long as it serves requests
+#[get("/healthz")]
+async fn get_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+#[get("/readyz")]
+async fn get_readyz(health_checks: web::Data<HealthChecks>) -> HttpResponse {
+    let readiness = health_checks.readiness().await;
+    if readiness.is_ready() {
+        HttpResponse::Ok().json(&readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(&readiness)
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics(state: web::Data<AppState>) -> HttpResponse {
+    metrics::STREAM_PRODUCER_QUEUE_DEPTH.set(state.sender.len() as i64);
+    match metrics::encode() {
+        Ok((content_type, body)) => HttpResponse::Ok().content_type(content_type).body(body),
+        Err(error) => HttpResponse::InternalServerError().json(&ErrorResponse {
+            error: error.to_string(),
+        }),
+    }
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.required("server.bind", Config::get_socket_addr);
+    validation.required("server.admin.bind", Config::get_socket_addr);
+    validation.optional("health.timeout", Config::get_duration);
+    PartnersBuilder::validate(validation);
+    AuthorizedPartnersByUserBuilder::validate(validation);
+    ConsentDomain::validate(validation);
+    EventSchemasBuilder::validate(validation);
+}
+
+// Some builder errors cannot be sent across threads, keep their message
+fn build_error<E: std::fmt::Debug>(error: E) -> std::io::Error {
+    std::io::Error::other(format!("{:?}", error))
+}
+
+// The configuration is loaded once by the caller, the watcher reloads it from its source
 pub async fn run_http_server(
+    config: &Config,
+    source: &Source,
     sender: crossbeam_channel::Sender<ucdp::stream::events::Events>,
 ) -> std::io::Result<()> {
-    let config = Config::new(String::from("config/Main"));
     let server_binding_address = config
-        .get_str("server.bind")
-        .map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
+        .get_socket_addr("server.bind")
+        .map_err(std::io::Error::other)?;
+    let admin_binding_address = config
+        .get_socket_addr("server.admin.bind")
+        .map_err(std::io::Error::other)?;
 
     let state = web::Data::new(AppState {
         sender,
-        partners: PartnersBuilder::build(&config).unwrap(),
-        authorized_partners_by_user: AuthorizedPartnersByUserBuilder::build(&config).unwrap(),
+        daos: ArcSwap::from_pointee(Daos::build(config).map_err(std::io::Error::other)?),
+        event_schemas: EventSchemasBuilder::build(config).map_err(build_error)?,
     });
-    HttpServer::new(move || {
+    // Partner and consent lookups follow the changes of the configuration file
+    let _config_watcher = ucdp::config::watch(source, validate, {
+        let state = state.clone();
+        move |config| state.reload(config)
+    })
+    .map_err(std::io::Error::other)?;
+    let health_checks = web::Data::new(HealthChecksBuilder::build(config).map_err(build_error)?);
+    let public_server = HttpServer::new({
+        let state = state.clone();
+        move || {
+            App::new()
+                .app_data(state.clone())
+                .app_data(health_checks.clone())
+                .wrap(
+                    Cors::default()
+                        .allow_any_origin()
+                        .allowed_methods(vec!["GET", "POST"])
+                        .allowed_headers(vec![
+                            header::ACCEPT,
+                            header::CONTENT_TYPE,
+                            HeaderName::from_static(LISTING_DEADLINE_HEADER),
+                            HeaderName::from_static(LISTING_SIGNATURE_HEADER),
+                        ])
+                        .max_age(3600),
+                )
+                .wrap_fn(observe)
+                .service(proxy)
+                .service(get_authorized_partners)
+                .service(post_consent)
+                .service(get_healthz)
+                .service(get_readyz)
+        }
+    })
+    .bind(server_binding_address)?
+    // In-flight requests are given the drain timeout to complete on SIGTERM
+    .shutdown_timeout(ucdp::shutdown::drain_timeout(config).as_secs())
+    .run();
+    // Schemas are managed and metrics scraped from the private network only, without CORS
+    let admin_server = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allowed_methods(vec!["POST"])
-                    .allowed_headers(vec![header::ACCEPT, header::CONTENT_TYPE])
-                    .max_age(3600),
-            )
-            .wrap(Logger::default())
-            .service(proxy)
+            .wrap_fn(observe)
+            .service(get_metrics)
+            .service(get_event_schema)
+            .service(publish_event_schema)
+            .service(activate_event_schema)
     })
-    .bind(server_binding_address)?
-    .run()
-    .await
+    .bind(admin_binding_address)?
+    .shutdown_timeout(ucdp::shutdown::drain_timeout(config).as_secs())
+    .run();
+    futures::try_join!(public_server, admin_server)?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::ucdp::api::User;
-    use crate::ucdp::dal::{AuthorizedPartnersByUserDao, PartnersDao, PartnersError};
-    use crate::ucdp::web::{proxy, AppState};
+    use crate::ucdp::consent::{Consent, ConsentDomain, ConsentRateLimit, Listing, SignedConsent};
+    use crate::ucdp::dal::{
+        Authorization, AuthorizedPartnersByUserDao, EventSchema, EventSchemasDao,
+        EventSchemasError, PartnersDao, PartnersError,
+    };
+    use crate::ucdp::health::HealthChecks;
+    use crate::ucdp::metrics;
+    use crate::ucdp::web::{
+        activate_event_schema, get_authorized_partners, get_healthz, get_metrics, get_readyz,
+        post_consent, proxy, publish_event_schema, request_span, validate, AppState, Daos,
+        RequestId, LISTING_DEADLINE_HEADER, LISTING_SIGNATURE_HEADER,
+    };
     use actix_http::http::Method;
     use actix_web::dev::{Service, ServiceResponse};
     use actix_web::http::StatusCode;
     use actix_web::test::{init_service, TestRequest};
-    use actix_web::{web, App};
+    use actix_web::{web, App, HttpMessage};
+    use arc_swap::ArcSwap;
     use async_trait::async_trait;
     use crossbeam_channel::unbounded;
+    use serde_json::json;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use ucdp::stream::events::ConsentBlock;
+    use web3::signing::{Key, SecretKeyRef};
+    use web3::types::{Address, H256};
 
     struct OptionPartnerDao {
         partner: Option<crate::ucdp::dal::Partner>,
@@ -151,6 +769,7 @@ mod tests {
 
     struct AuthorizedPartnerByUser {
         is_partner_authorized: bool,
+        nonce: std::sync::Mutex<u64>,
     }
 
     #[async_trait]
@@ -162,33 +781,172 @@ mod tests {
         ) -> Result<bool, crate::ucdp::dal::AuthorizedPartnersByUserError> {
             Ok(self.is_partner_authorized)
         }
+
+        async fn authorization(
+            &self,
+            _: &str,
+            _: &str,
+        ) -> Result<Authorization, crate::ucdp::dal::AuthorizedPartnersByUserError> {
+            Ok(Authorization {
+                authorized: self.is_partner_authorized,
+                block: Some(ConsentBlock {
+                    number: 42,
+                    hash: "0x42".into(),
+                }),
+            })
+        }
+
+        async fn set_authorized(
+            &self,
+            _: &str,
+            _: &str,
+            _: bool,
+        ) -> Result<(), crate::ucdp::dal::AuthorizedPartnersByUserError> {
+            Ok(())
+        }
+
+        async fn authorized_partners(
+            &self,
+            _: &str,
+        ) -> Result<Vec<String>, crate::ucdp::dal::AuthorizedPartnersByUserError> {
+            Ok(match self.is_partner_authorized {
+                true => vec!["0x123456789".into()],
+                false => vec![],
+            })
+        }
+
+        async fn nonce(
+            &self,
+            _: &str,
+        ) -> Result<u64, crate::ucdp::dal::AuthorizedPartnersByUserError> {
+            Ok(*self.nonce.lock().unwrap())
+        }
+
+        async fn apply_consent(
+            &self,
+            signed: &SignedConsent,
+        ) -> Result<Option<H256>, crate::ucdp::dal::AuthorizedPartnersByUserError> {
+            let mut nonce = self.nonce.lock().unwrap();
+            if signed.consent.nonce != *nonce {
+                return Err(crate::ucdp::dal::AuthorizedPartnersByUserError::Nonce(
+                    *nonce,
+                ));
+            }
+            *nonce += 1;
+            Ok(None)
+        }
     }
 
-    async fn get_response(
+    struct OptionEventSchemasDao {
+        schema: Option<serde_json::Value>,
+    }
+
+    #[async_trait]
+    impl EventSchemasDao for OptionEventSchemasDao {
+        async fn get_active_schema(
+            &self,
+            _: &str,
+            _: &str,
+        ) -> Result<Option<EventSchema>, EventSchemasError> {
+            Ok(self
+                .schema
+                .clone()
+                .map(|schema| EventSchema::new(1, schema).unwrap()))
+        }
+
+        async fn publish_schema(
+            &self,
+            _: &str,
+            _: &str,
+            schema: serde_json::Value,
+        ) -> Result<EventSchema, EventSchemasError> {
+            if schema.is_object() {
+                EventSchema::new(1, schema)
+            } else {
+                Err(EventSchemasError::InvalidSchema("".into()))
+            }
+        }
+
+        async fn activate_schema(
+            &self,
+            _: &str,
+            _: &str,
+            version: u32,
+        ) -> Result<EventSchema, EventSchemasError> {
+            Err(EventSchemasError::VersionNotFound(version))
+        }
+    }
+
+    fn state(
         partner: Option<crate::ucdp::dal::Partner>,
         is_partner_authorized: bool,
-    ) -> ServiceResponse {
+        schema: Option<serde_json::Value>,
+    ) -> web::Data<AppState> {
         let (sender, _) = unbounded::<ucdp::stream::events::Events>();
-        let state = web::Data::new(AppState {
+        state_with_sender(sender, partner, is_partner_authorized, schema)
+    }
+
+    fn state_with_sender(
+        sender: crossbeam_channel::Sender<ucdp::stream::events::Events>,
+        partner: Option<crate::ucdp::dal::Partner>,
+        is_partner_authorized: bool,
+        schema: Option<serde_json::Value>,
+    ) -> web::Data<AppState> {
+        web::Data::new(AppState {
             sender,
-            partners: Box::new(OptionPartnerDao { partner }),
-            authorized_partners_by_user: Box::new(AuthorizedPartnerByUser {
-                is_partner_authorized,
+            daos: ArcSwap::from_pointee(Daos {
+                config: ucdp::config::Config::from(config::Config::default()),
+                partners: Arc::new(OptionPartnerDao { partner }),
+                authorized_partners_by_user: Arc::new(AuthorizedPartnerByUser {
+                    is_partner_authorized,
+                    nonce: Default::default(),
+                }),
+                consent_domain: Some(ConsentDomain::new(1337, Address::from_low_u64_be(0xc0))),
+                consent_rate_limit: Arc::new(
+                    ConsentRateLimit::build(&ucdp::config::Config::from(config::Config::default()))
+                        .unwrap(),
+                ),
             }),
-        });
+            event_schemas: Box::new(OptionEventSchemasDao { schema }),
+        })
+    }
+
+    async fn get_response(
+        partner: Option<crate::ucdp::dal::Partner>,
+        is_partner_authorized: bool,
+    ) -> ServiceResponse {
+        get_response_with_schema(partner, is_partner_authorized, None).await
+    }
+
+    async fn get_response_with_schema(
+        partner: Option<crate::ucdp::dal::Partner>,
+        is_partner_authorized: bool,
+        schema: Option<serde_json::Value>,
+    ) -> ServiceResponse {
+        get_response_for_partner("0x123456789", partner, is_partner_authorized, schema).await
+    }
+
+    async fn get_response_for_partner(
+        partner_id: &str,
+        partner: Option<crate::ucdp::dal::Partner>,
+        is_partner_authorized: bool,
+        schema: Option<serde_json::Value>,
+    ) -> ServiceResponse {
+        let state = state(partner, is_partner_authorized, schema);
         let service = init_service(App::new().app_data(state.clone()).service(proxy)).await;
         let request = TestRequest::default()
             .uri("/v1/events")
             .method(Method::POST)
             .set_json(&crate::ucdp::api::Events {
                 partner: crate::ucdp::api::Partner {
-                    id: "0x123456789".into(),
+                    id: partner_id.into(),
                 },
                 user: User {
                     id: "0x9876543210".into(),
                 },
                 events: vec![crate::ucdp::api::Event {
                     name: String::from("event1"),
+                    properties: Some(json!({ "price": 10 })),
                 }],
             })
             .to_request();
@@ -240,4 +998,397 @@ mod tests {
         .await;
         assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
+
+    #[actix_rt::test]
+    async fn http_server_events_metrics() {
+        let accepted = metrics::EVENTS.with_label_values(&["0xaccepted", "accepted"]);
+        let response = get_response_for_partner("0xaccepted", enabled_partner(), true, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(accepted.get(), 1);
+
+        let rejected = metrics::EVENTS.with_label_values(&["0xrejected", "rejected"]);
+        let response = get_response_for_partner("0xrejected", enabled_partner(), false, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(rejected.get(), 1);
+
+        // Disabled or unregistered partners are not labelled
+        let unknown = metrics::EVENTS.with_label_values(&[metrics::UNKNOWN_PARTNER, "rejected"]);
+        let before = unknown.get();
+        let disabled = Some(crate::ucdp::dal::Partner {
+            name: "".into(),
+            enabled: false,
+        });
+        let response = get_response_for_partner("0xdisabled", disabled, true, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(unknown.get() > before);
+        assert!(!metrics::encode()
+            .unwrap()
+            .1
+            .contains("partner=\"0xdisabled\""));
+    }
+
+    #[test]
+    fn http_server_request_span() {
+        let config = ucdp::config::Config::from(config::Config::default());
+        let _ = ucdp::telemetry::init(&config, "test");
+
+        let request = TestRequest::default()
+            .uri("/v1/events")
+            .method(Method::POST)
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .to_srv_request();
+        let cx = request_span(&request);
+
+        // The events sent to the stream carry the trace of the caller
+        let trace_context = ucdp::telemetry::inject(&cx);
+        assert!(trace_context["traceparent"].starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
+
+    #[actix_rt::test]
+    async fn http_server_get_healthz_readyz() {
+        let health_checks = web::Data::new(HealthChecks::new(vec![], Duration::from_secs(1)));
+        let service = init_service(
+            App::new()
+                .app_data(health_checks)
+                .service(get_healthz)
+                .service(get_readyz),
+        )
+        .await;
+
+        for uri in ["/healthz", "/readyz"] {
+            let request = TestRequest::default()
+                .uri(uri)
+                .method(Method::GET)
+                .to_request();
+            let response = service.call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+            assert_eq!(body["status"], "ok");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn http_server_get_metrics() {
+        let state = state(None, false, None);
+        let service = init_service(App::new().app_data(state.clone()).service(get_metrics)).await;
+        let request = TestRequest::default()
+            .uri("/metrics")
+            .method(Method::GET)
+            .to_request();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = actix_web::test::read_body(response).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("gateway_stream_producer_queue_depth 0"));
+    }
+
+    fn enabled_partner() -> Option<crate::ucdp::dal::Partner> {
+        Some(crate::ucdp::dal::Partner {
+            name: "".into(),
+            enabled: true,
+        })
+    }
+
+    #[actix_rt::test]
+    async fn http_server_simple_request_ok_schema() {
+        let schema = json!({ "properties": { "price": { "type": "number" } } });
+        let response = get_response_with_schema(enabled_partner(), true, Some(schema)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn http_server_simple_request_err_schema() {
+        let schema = json!({ "properties": { "price": { "type": "string" } } });
+        let response = get_response_with_schema(enabled_partner(), true, Some(schema)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn http_server_publish_event_schema() {
+        let state = state(None, false, None);
+        let service = init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(publish_event_schema),
+        )
+        .await;
+
+        let cases = vec![
+            (json!({ "type": "object" }), StatusCode::OK),
+            (json!("not an object"), StatusCode::BAD_REQUEST),
+        ];
+        for (schema, status) in cases {
+            let request = TestRequest::default()
+                .uri("/v1/admin/partners/0x123456789/schemas/event1")
+                .method(Method::POST)
+                .set_json(&schema)
+                .to_request();
+            let response = service.call(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn http_server_activate_event_schema_err_not_found() {
+        let state = state(None, false, None);
+        let service = init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(activate_event_schema),
+        )
+        .await;
+
+        let request = TestRequest::default()
+            .uri("/v1/admin/partners/0x123456789/schemas/event1/active")
+            .method(Method::PUT)
+            .set_json(&crate::ucdp::api::ActivateSchemaRequest { version: 2 })
+            .to_request();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn http_server_events_request_id() {
+        let (sender, receiver) = unbounded::<ucdp::stream::events::Events>();
+        let state = state_with_sender(sender, enabled_partner(), true, None);
+        let service = init_service(App::new().app_data(state).service(proxy)).await;
+        let request = TestRequest::default()
+            .uri("/v1/events")
+            .method(Method::POST)
+            .set_json(&json!({
+                "partner": { "id": "0x123456789" },
+                "user": { "id": "0x9876543210" },
+                "events": [{ "name": "event1" }]
+            }))
+            .to_request();
+        request.extensions_mut().insert(RequestId("request".into()));
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Events carry the id of the request they were received with, and the block consent was checked at
+        let events = receiver.try_recv().unwrap();
+        assert_eq!(events.request_id, "request");
+        assert_eq!(events.consent_block.unwrap().number, 42);
+    }
+
+    #[actix_rt::test]
+    async fn http_server_get_authorized_partners() {
+        let state = state(None, true, None);
+        let service = init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(get_authorized_partners),
+        )
+        .await;
+
+        let key = secp256k1::SecretKey::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let key = SecretKeyRef::new(&key);
+        let domain = ConsentDomain::new(1337, Address::from_low_u64_be(0xc0));
+        let request = |user: Address, deadline: Option<u64>| {
+            let mut request = TestRequest::default()
+                .uri(&format!("/v1/users/{:?}/partners", user))
+                .method(Method::GET);
+            if let Some(deadline) = deadline {
+                let listing = Listing {
+                    user: key.address(),
+                    deadline,
+                };
+                let signature = key.sign(listing.digest(&domain).as_bytes(), None).unwrap();
+                let mut bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+                bytes.push(signature.v as u8);
+                request = request
+                    .insert_header((LISTING_DEADLINE_HEADER, deadline.to_string()))
+                    .insert_header((
+                        LISTING_SIGNATURE_HEADER,
+                        json!(web3::types::Bytes(bytes))
+                            .as_str()
+                            .unwrap()
+                            .to_string(),
+                    ));
+            }
+            request.to_request()
+        };
+
+        // Unsigned, expired, or signed by another user
+        let deadline = 4_000_000_000;
+        let cases = vec![
+            (request(key.address(), None), StatusCode::UNAUTHORIZED),
+            (
+                request(key.address(), Some(1_600_000_000)),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                request(Address::from_low_u64_be(0x456), Some(deadline)),
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+        for (request, status) in cases {
+            let response = service.call(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let response = service
+            .call(request(key.address(), Some(deadline)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            json!({ "partners": [{ "id": "0x123456789" }], "nonce": 0 })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn http_server_post_consent() {
+        let state = state(None, true, None);
+        let service = init_service(App::new().app_data(state.clone()).service(post_consent)).await;
+
+        let key = secp256k1::SecretKey::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let key = SecretKeyRef::new(&key);
+        let domain = ConsentDomain::new(1337, Address::from_low_u64_be(0xc0));
+        let sign = |nonce: u64, deadline: u64, domain: &ConsentDomain| {
+            let consent = Consent {
+                user: key.address(),
+                partner: Address::from_low_u64_be(0x123),
+                authorized: true,
+                nonce,
+                deadline,
+            };
+            let signature = key.sign(consent.digest(domain).as_bytes(), None).unwrap();
+            let mut bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+            bytes.push(signature.v as u8);
+            json!({
+                "partner": { "id": format!("{:?}", consent.partner) },
+                "authorized": true,
+                "nonce": nonce,
+                "deadline": deadline,
+                "signature": web3::types::Bytes(bytes),
+            })
+        };
+
+        let deadline = 4_000_000_000;
+        let cases = vec![
+            (sign(0, deadline, &domain), StatusCode::OK),
+            // Replayed, consent expired, signed for another contract
+            (sign(0, deadline, &domain), StatusCode::CONFLICT),
+            (sign(0, 1_600_000_000, &domain), StatusCode::BAD_REQUEST),
+            (
+                sign(0, deadline, &ConsentDomain::new(1, Address::zero())),
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+        for (body, status) in cases {
+            let request = TestRequest::default()
+                .uri(&format!("/v1/users/{:?}/consents", key.address()))
+                .method(Method::POST)
+                .set_json(&body)
+                .to_request();
+            let response = service.call(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        // 10 signed consents of the user an hour, 2 of them above
+        let statuses = [vec![StatusCode::OK; 8], vec![StatusCode::TOO_MANY_REQUESTS]].concat();
+        for (nonce, status) in (1..).zip(statuses) {
+            let request = TestRequest::default()
+                .uri(&format!("/v1/users/{:?}/consents", key.address()))
+                .method(Method::POST)
+                .set_json(&sign(nonce, deadline, &domain))
+                .to_request();
+            let response = service.call(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[test]
+    fn http_server_validate_main_config() {
+        let source = ucdp::config::Source::new("config/Main", None);
+        let config = ucdp::config::Config::load(&source).unwrap();
+        let mut validation = ucdp::config::Validation::new(&config);
+        ucdp::stream::producer::StreamProducerBuilder::validate(&mut validation);
+        validate(&mut validation);
+        assert!(validation.finish().is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn http_server_reload() {
+        let state = state(None, false, None);
+
+        let mut config = config::Config::default();
+        let _ = config.set("data.partners.connectors", vec!["in-memory"]);
+        let _ = config.set("data.authorized_partners_by_user.connector", "in-memory");
+        let _ = config.set("in_memory.seed", "../all-in-one/config/seed.json");
+        let config = ucdp::config::Config::from(config);
+        assert!(state.reload(&config).is_ok());
+
+        // Lookups go through the DAOs of the new configuration
+        let daos = state.daos.load_full();
+        let partner = daos
+            .partners
+            .get_partner("0x0000000000000000000000000000000000000123")
+            .await
+            .unwrap();
+        assert!(partner.enabled);
+
+        // Only the DAOs reading a changed section are rebuilt
+        let mut changed = config::Config::default();
+        let _ = changed.set("data.partners.connectors", vec!["in-memory"]);
+        let _ = changed.set("data.authorized_partners_by_user.connector", "in-memory");
+        let _ = changed.set("in_memory.seed", "../all-in-one/config/seed.json");
+        let _ = changed.set("consent.chain_id", 1337);
+        let _ = changed.set(
+            "consent.verifying_contract",
+            "0x00000000000000000000000000000000000000c0",
+        );
+        assert!(state.reload(&ucdp::config::Config::from(changed)).is_ok());
+        let reloaded = state.daos.load_full();
+        assert!(Arc::ptr_eq(&daos.partners, &reloaded.partners));
+        assert!(Arc::ptr_eq(
+            &daos.authorized_partners_by_user,
+            &reloaded.authorized_partners_by_user
+        ));
+        assert!(reloaded.consent_domain.is_some());
+        assert!(!Arc::ptr_eq(
+            &daos.consent_rate_limit,
+            &reloaded.consent_rate_limit
+        ));
+        let daos = reloaded;
+
+        let mut changed = config::Config::default();
+        let _ = changed.set("data.partners.connectors", vec!["in-memory"]);
+        let _ = changed.set("data.authorized_partners_by_user.connector", "in-memory");
+        let config = ucdp::config::Config::from(changed);
+        assert!(state.reload(&config).is_ok());
+        let reloaded = state.daos.load_full();
+        assert!(!Arc::ptr_eq(&daos.partners, &reloaded.partners));
+        assert!(reloaded.consent_domain.is_none());
+        // Users are given no new allowance while the consent section is unchanged
+        let kept = state.daos.load_full();
+        assert!(state.reload(&config).is_ok());
+        assert!(Arc::ptr_eq(
+            &kept.consent_rate_limit,
+            &state.daos.load_full().consent_rate_limit
+        ));
+        let daos = state.daos.load_full();
+
+        // The DAOs are kept when the new ones cannot be built
+        let mut config = config::Config::default();
+        let _ = config.set("data.partners.connectors", vec!["unknown"]);
+        let config = ucdp::config::Config::from(config);
+        assert!(state.reload(&config).is_err());
+        assert!(Arc::ptr_eq(&daos, &state.daos.load_full()));
+    }
 }
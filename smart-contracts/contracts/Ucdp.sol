@@ -21,13 +21,45 @@ contract Ucdp {
     // see function authorizePartner(address partner)
     mapping(address => mapping(address => bool)) public authorizedPartnersByUser;
 
+    // Every change of authorization, so that the partners of a user can be listed.
+    event Authorization(
+        address indexed user,
+        address indexed partner,
+        bool authorized
+    );
+
+    // EIP-712 signed authorizations, relayed by anyone so that users do not pay for gas.
+    // see function setAuthorizationBySig(...)
+    bytes32 public constant CONSENT_TYPEHASH =
+        keccak256(
+            "Consent(address user,address partner,bool authorized,uint256 nonce,uint256 deadline)"
+        );
+
+    bytes32 public immutable DOMAIN_SEPARATOR;
+
+    // Next nonce of the signed authorizations of each user.
+    mapping(address => uint256) public nonces;
+
     constructor() {
+        DOMAIN_SEPARATOR = keccak256(
+            abi.encode(
+                keccak256(
+                    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"
+                ),
+                keccak256(bytes("Ucdp")),
+                keccak256(bytes("1")),
+                block.chainid,
+                address(this)
+            )
+        );
+
         // Insert a dummy partner for test.
         partners[address(0x123)] = Partner("partner", true, true);
 
         // Insert a dummy user that authorized the dummy partner to use its data.
         users[address(0x456)] = User("user auth", true);
         authorizedPartnersByUser[address(0x456)][address(0x123)] = true;
+        emit Authorization(address(0x456), address(0x123), true);
 
         // Insert a dummy user with no partner authorization.
         users[address(0x789)] = User("user no-auth", true);
@@ -50,26 +82,61 @@ contract Ucdp {
     }
 
     function authorizePartner(address partner) external {
-        require(
-            users[msg.sender].registered == true,
-            "Sender must be registered as a User"
+        setAuthorization(msg.sender, partner, true);
+    }
+
+    function unauthorizePartner(address partner) external {
+        setAuthorization(msg.sender, partner, false);
+    }
+
+    function setAuthorizationBySig(
+        address user,
+        address partner,
+        bool authorized,
+        uint256 deadline,
+        uint8 v,
+        bytes32 r,
+        bytes32 s
+    ) external {
+        require(block.timestamp <= deadline, "Signature has expired");
+        bytes32 digest = keccak256(
+            abi.encodePacked(
+                "\x19\x01",
+                DOMAIN_SEPARATOR,
+                keccak256(
+                    abi.encode(
+                        CONSENT_TYPEHASH,
+                        user,
+                        partner,
+                        authorized,
+                        nonces[user]++,
+                        deadline
+                    )
+                )
+            )
         );
+        address signer = ecrecover(digest, v, r, s);
         require(
-            partners[partner].registered == true,
-            "Partner must be registered"
+            signer != address(0) && signer == user,
+            "Signature must be the user's"
         );
-        authorizedPartnersByUser[msg.sender][partner] = true;
+        setAuthorization(user, partner, authorized);
     }
 
-    function unauthorizePartner(address partner) external {
+    function setAuthorization(
+        address user,
+        address partner,
+        bool authorized
+    ) private {
         require(
-            users[msg.sender].registered == true,
+            users[user].registered == true,
             "Sender must be registered as a User"
         );
         require(
             partners[partner].registered == true,
             "Partner must be registered"
         );
-        authorizedPartnersByUser[msg.sender][partner] = false;
+        authorizedPartnersByUser[user][partner] = authorized;
+        emit Authorization(user, partner, authorized);
     }
 }
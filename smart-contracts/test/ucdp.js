@@ -114,6 +114,101 @@ contract("Ucdp", async (accounts) => {
     assert.equal(isAuthorized, false);
   });
 
+  it("should authorize a partner with a signature of the user", async () => {
+    const ucdp = await Ucdp.deployed();
+    // Reuse registred user from account[0]
+    const partnerAddress = web3.utils.toChecksumAddress(
+      web3.utils.padLeft(0x123, 40)
+    );
+    const nonce = (await ucdp.nonces(accounts[0])).toNumber();
+    const deadline = Math.floor(Date.now() / 1000) + 3600;
+    const typedData = {
+      types: {
+        EIP712Domain: [
+          { name: "name", type: "string" },
+          { name: "version", type: "string" },
+          { name: "chainId", type: "uint256" },
+          { name: "verifyingContract", type: "address" },
+        ],
+        Consent: [
+          { name: "user", type: "address" },
+          { name: "partner", type: "address" },
+          { name: "authorized", type: "bool" },
+          { name: "nonce", type: "uint256" },
+          { name: "deadline", type: "uint256" },
+        ],
+      },
+      primaryType: "Consent",
+      domain: {
+        name: "Ucdp",
+        version: "1",
+        chainId: await web3.eth.getChainId(),
+        verifyingContract: ucdp.address,
+      },
+      message: {
+        user: accounts[0],
+        partner: partnerAddress,
+        authorized: true,
+        nonce,
+        deadline,
+      },
+    };
+    const signature = await new Promise((resolve, reject) =>
+      web3.currentProvider.send(
+        {
+          jsonrpc: "2.0",
+          id: Date.now(),
+          method: "eth_signTypedData_v4",
+          params: [accounts[0], JSON.stringify(typedData)],
+        },
+        (error, response) => (error ? reject(error) : resolve(response.result))
+      )
+    );
+    const r = signature.slice(0, 66);
+    const s = "0x" + signature.slice(66, 130);
+    const v = parseInt(signature.slice(130, 132), 16);
+
+    // Relayed by another account, which pays for gas
+    await ucdp.setAuthorizationBySig(
+      accounts[0],
+      partnerAddress,
+      true,
+      deadline,
+      v,
+      r,
+      s,
+      { from: accounts[1] }
+    );
+    const isAuthorized = await ucdp.authorizedPartnersByUser(
+      accounts[0],
+      partnerAddress
+    );
+    assert.equal(isAuthorized, true);
+    assert.equal((await ucdp.nonces(accounts[0])).toNumber(), nonce + 1);
+
+    // The nonce has been used, the signature cannot be replayed
+    let hasRaisedException = false;
+    try {
+      await ucdp.setAuthorizationBySig(
+        accounts[0],
+        partnerAddress,
+        true,
+        deadline,
+        v,
+        r,
+        s,
+        { from: accounts[1] }
+      );
+    } catch (exception) {
+      hasRaisedException = true;
+      assert.equal(
+        exception.message.includes("Signature must be the user's"),
+        true
+      );
+    }
+    assert.equal(hasRaisedException, true);
+  });
+
   it("should not authorize an unregistered user", async () => {
     const ucdp = await Ucdp.deployed();
 
@@ -0,0 +1,132 @@
+use crate::config::{self, Config, Source, Validation};
+use crate::logging;
+use clap::{Parser, ValueEnum};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cannot load configuration from {0}: {1}")]
+    Load(String, config::Error),
+
+    #[error("{0}")]
+    Logging(#[from] logging::Error),
+
+    #[error("{0}")]
+    Invalid(config::Error),
+}
+
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Args {
+    /// Configuration file, without extension
+    #[arg(long, default_value = "config/Main")]
+    pub config: String,
+
+    /// Profile file layered over the configuration file, <config>.<profile>
+    #[arg(long, value_enum)]
+    pub profile: Option<Profile>,
+
+    /// Print the configuration, secrets redacted, and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Dev,
+    Prod,
+}
+
+impl Profile {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Prod => "prod",
+        }
+    }
+}
+
+impl Args {
+    // Exits with the usage when the command line is not valid
+    pub fn parse() -> Self {
+        <Args as Parser>::parse()
+    }
+
+    pub fn source(&self) -> Source {
+        Source::new(&self.config, self.profile.map(|profile| profile.as_str()))
+    }
+}
+
+// Load the configuration once for the whole process, then log and validate it.
+// None once the configuration is printed, there is nothing left to run.
+pub fn init(args: &Args, validate: fn(&mut Validation)) -> Result<Option<Config>, Error> {
+    let config = Config::load(&args.source())
+        .map_err(|error| Error::Load(args.source().to_string(), error))?;
+
+    if args.print_config {
+        print!("{}", config);
+        return Ok(None);
+    }
+
+    logging::init(&config)?;
+
+    // Report every configuration problem at once rather than failing on the first one
+    let mut validation = Validation::new(&config);
+    validate(&mut validation);
+    validation.finish().map_err(Error::Invalid)?;
+
+    Ok(Some(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init, Args, Error, Profile};
+    use crate::config::{Config, Validation};
+    use clap::Parser;
+
+    #[test]
+    fn cli_init_err() {
+        fn validate(validation: &mut Validation) {
+            validation.required("missing", Config::get_str);
+        }
+
+        let args = Args::try_parse_from(["ucdp", "--config", "unknown/Main"]).unwrap();
+        match init(&args, validate) {
+            Err(Error::Load(source, _)) => assert_eq!(source, "unknown/Main"),
+            _ => unreachable!(),
+        }
+
+        let args = Args::try_parse_from(["ucdp", "--config", "../gateway/config/Main"]).unwrap();
+        match init(&args, validate) {
+            Err(Error::Invalid(error)) => assert!(error.to_string().contains("missing: missing")),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn cli_args() {
+        let args = Args::try_parse_from(["ucdp"]).unwrap();
+        assert_eq!(args.config, "config/Main");
+        assert_eq!(args.profile, None);
+        assert!(!args.print_config);
+        assert_eq!(args.source().to_string(), "config/Main");
+
+        let args = Args::try_parse_from([
+            "ucdp",
+            "--config",
+            "/etc/ucdp/Main",
+            "--profile",
+            "prod",
+            "--print-config",
+        ])
+        .unwrap();
+        assert_eq!(args.profile, Some(Profile::Prod));
+        assert!(args.print_config);
+        assert_eq!(
+            args.source().to_string(),
+            "/etc/ucdp/Main + /etc/ucdp/Main.prod"
+        );
+
+        assert!(Args::try_parse_from(["ucdp", "--profile", "staging"]).is_err());
+    }
+}
@@ -1,45 +1,502 @@
 use config::{ConfigError, Environment};
+use log::{info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
+use url::Url;
+
+pub use ethereum_types::Address;
 
 #[derive(Clone)]
 pub struct Config {
     config: config::Config,
+    // Values of the keys set with a ${file:...} or ${env:...} reference
+    secrets: HashMap<String, String>,
+    // References that could not be resolved, reported by validation
+    unresolved: Vec<String>,
 }
 
+const REDACTED: &str = "[redacted]";
+
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("config error")]
+    #[error("config error: {0}")]
     Config(#[from] ConfigError),
+
+    #[error("invalid value for {0}: {1}")]
+    Invalid(String, String),
+
+    #[error("watch error: {0}")]
+    Watch(#[from] notify::Error),
+
+    #[error("invalid configuration:{}", .0.iter().map(|problem| format!("\n  - {}", problem)).collect::<String>())]
+    Validation(Vec<String>),
+}
+
+impl Error {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Config(ConfigError::NotFound(_)))
+    }
+}
+
+// Files a configuration is layered from, the profile file (path.profile) overrides the base one
+#[derive(Clone, Debug)]
+pub struct Source {
+    path: String,
+    profile: Option<String>,
+}
+
+impl Source {
+    pub fn new(path: &str, profile: Option<&str>) -> Self {
+        Source {
+            path: path.into(),
+            profile: profile.map(String::from),
+        }
+    }
+
+    // Without extension, as given to config::File::with_name
+    fn files(&self) -> Vec<String> {
+        let mut files = vec![self.path.clone()];
+        if let Some(profile) = &self.profile {
+            files.push(format!("{}.{}", self.path, profile));
+        }
+        files
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.files().join(" + "))
+    }
 }
 
 impl Config {
-    pub fn new(path: String) -> Self {
+    // A file that cannot be read or parsed, or a secret that cannot be resolved, is an error.
+    // Environment variables override the files.
+    pub fn load(source: &Source) -> Result<Self, Error> {
         let mut config = config::Config::default();
-        let _ = config.merge(config::File::with_name(&path));
-        let _ = config.merge(
+        for file in source.files() {
+            // The extension is looked for in place of the last one, ".prod" would be replaced
+            config.merge(config::File::with_name(&format!("{}.toml", file)))?;
+        }
+        config.merge(
             Environment::with_prefix("ucdp")
                 .separator("_")
                 .ignore_empty(false),
-        );
+        )?;
 
-        Config { config }
+        let config = Config::from(config);
+        if config.unresolved.is_empty() {
+            Ok(config)
+        } else {
+            Err(Error::Validation(config.unresolved))
+        }
+    }
+
+    // Replace the secrets found in a text, such as an error message about a value
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets
+            .values()
+            .filter(|secret| !secret.is_empty())
+            .fold(text.to_string(), |text, secret| {
+                text.replace(secret.as_str(), REDACTED)
+            })
     }
 
     pub fn get_str(&self, key: &str) -> Result<String, Error> {
         self.config.get_str(key).map_err(Error::Config)
     }
 
+    pub fn get_int(&self, key: &str) -> Result<i64, Error> {
+        self.config.get_int(key).map_err(Error::Config)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<bool, Error> {
+        self.config.get_bool(key).map_err(Error::Config)
+    }
+
+    // Integers are seconds, strings carry their unit ("500ms", "30s", "5m")
+    pub fn get_duration(&self, key: &str) -> Result<Duration, Error> {
+        match self.config.get_int(key) {
+            Ok(seconds) if seconds >= 0 => Ok(Duration::from_secs(seconds as u64)),
+            Ok(_) => Err(Error::Invalid(key.into(), "negative duration".into())),
+            Err(ConfigError::NotFound(key)) => Err(Error::Config(ConfigError::NotFound(key))),
+            Err(_) => humantime::parse_duration(self.get_str(key)?.as_str())
+                .map_err(|error| Error::Invalid(key.into(), error.to_string())),
+        }
+    }
+
+    // Host names are resolved, the first address is used
+    pub fn get_socket_addr(&self, key: &str) -> Result<SocketAddr, Error> {
+        self.get_str(key)?
+            .to_socket_addrs()
+            .map_err(|error| Error::Invalid(key.into(), error.to_string()))?
+            .next()
+            .ok_or_else(|| Error::Invalid(key.into(), "no address".into()))
+    }
+
+    pub fn get_url(&self, key: &str) -> Result<Url, Error> {
+        Url::parse(self.get_str(key)?.as_str())
+            .map_err(|error| Error::Invalid(key.into(), error.to_string()))
+    }
+
+    pub fn get_ethereum_address(&self, key: &str) -> Result<Address, Error> {
+        Address::from_str(self.get_str(key)?.as_str())
+            .map_err(|error| Error::Invalid(key.into(), error.to_string()))
+    }
+
+    // Deserialize a whole section, or any value, into T
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, Error> {
+        self.config.get::<T>(key).map_err(Error::Config)
+    }
+
+    // Whether any of the sections is set differently in the other configuration
+    pub fn differs(&self, other: &Config, sections: &[&str]) -> bool {
+        sections.iter().any(|section| {
+            self.get::<serde_json::Value>(section).ok()
+                != other.get::<serde_json::Value>(section).ok()
+        })
+    }
+
     pub fn get_str_vec(&self, key: &str) -> Result<Vec<String>, Error> {
         self.config
             .get_array(key)
             .map(|values| values.into_iter().map(|value| value.to_string()).collect())
             .map_err(Error::Config)
     }
+
+    // Get all the values under a key, nested keys are joined with dots.
+    // Nested keys (as set by environment variables) take precedence over quoted dotted keys.
+    pub fn get_flat_table(&self, key: &str) -> Result<HashMap<String, String>, Error> {
+        let table = self.config.get_table(key).map_err(Error::Config)?;
+        let mut flat_table = HashMap::new();
+        flatten("", table, &mut flat_table);
+        Ok(flat_table)
+    }
+}
+
+fn flatten(
+    prefix: &str,
+    table: HashMap<String, config::Value>,
+    flat_table: &mut HashMap<String, String>,
+) {
+    let mut tables = vec![];
+    for (key, value) in table {
+        let key = format!("{}{}", prefix, key);
+        match value.clone().into_table() {
+            Ok(table) => tables.push((key, table)),
+            Err(_) => {
+                flat_table.insert(key, value.to_string());
+            }
+        }
+    }
+    for (key, table) in tables {
+        flatten(&format!("{}.", key), table, flat_table);
+    }
+}
+
+// Replace the ${file:/path} and ${env:NAME} references of a value, None when there is none.
+// Files are read without their trailing newline.
+fn resolve(value: &str) -> Result<Option<String>, String> {
+    let mut resolved = String::new();
+    let mut rest = value;
+    let mut found = false;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let secret = match rest[start + 2..end].split_once(':') {
+            Some(("file", path)) => std::fs::read_to_string(path)
+                .map_err(|error| format!("cannot read {}: {}", path, error))?
+                .trim_end_matches(&['\r', '\n'][..])
+                .to_string(),
+            Some(("env", name)) => std::env::var(name)
+                .map_err(|_| format!("environment variable {} is not set", name))?,
+            // Not a reference, keep it as is
+            _ => rest[start..=end].to_string(),
+        };
+        found |= secret != rest[start..=end];
+        resolved.push_str(&rest[..start]);
+        resolved.push_str(&secret);
+        rest = &rest[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(if found { Some(resolved) } else { None })
+}
+
+// Keys of the string values holding references, with the resolved values
+fn references(
+    prefix: &str,
+    table: HashMap<String, config::Value>,
+    found: &mut Vec<(String, Result<String, String>)>,
+) {
+    for (key, value) in table {
+        let key = format!("{}{}", prefix, key);
+        if let Ok(table) = value.clone().into_table() {
+            references(&format!("{}.", key), table, found);
+        } else if value.clone().into_array().is_err() {
+            if let Some(resolved) = resolve(&value.to_string()).transpose() {
+                found.push((key, resolved));
+            }
+        }
+    }
+}
+
+// Problems found in a configuration, gathered so that they are all reported at once.
+// Each component declares the keys its builder reads in a validate function next to it,
+// with the getters the builder uses, so that a value validated is a value the builder accepts.
+pub struct Validation<'a> {
+    config: &'a Config,
+    problems: Vec<String>,
+}
+
+impl<'a> Validation<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Validation {
+            config,
+            problems: config.unresolved.clone(),
+        }
+    }
+
+    fn problem(&mut self, key: &str, error: Error) {
+        let problem = match error {
+            Error::Config(ConfigError::NotFound(_)) => format!("{}: missing", key),
+            Error::Config(error) => format!("{}: {}", key, error),
+            Error::Invalid(_, reason) => format!("{}: {}", key, reason),
+            error => format!("{}: {}", key, error),
+        };
+        // Errors may quote the value
+        let problem = self.config.redact(&problem);
+        // Components sharing a key report its problem once
+        if !self.problems.contains(&problem) {
+            self.problems.push(problem);
+        }
+    }
+
+    // A problem the getters cannot tell, such as a value inconsistent with another one
+    pub fn invalid(&mut self, key: &str, reason: String) {
+        self.problem(key, Error::Invalid(key.into(), reason));
+    }
+
+    // The value of a key that must be set
+    pub fn required<T>(
+        &mut self,
+        key: &str,
+        get: fn(&Config, &str) -> Result<T, Error>,
+    ) -> Option<T> {
+        match get(self.config, key) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.problem(key, error);
+                None
+            }
+        }
+    }
+
+    // The value of a key that may be left unset, a default is used then
+    pub fn optional<T>(
+        &mut self,
+        key: &str,
+        get: fn(&Config, &str) -> Result<T, Error>,
+    ) -> Option<T> {
+        match get(self.config, key) {
+            Ok(value) => Some(value),
+            Err(error) if error.is_not_found() => None,
+            Err(error) => {
+                self.problem(key, error);
+                None
+            }
+        }
+    }
+
+    // The value of a key among known values, the default when unset if there is one
+    pub fn one_of(&mut self, key: &str, values: &[&str], default: Option<&str>) -> Option<String> {
+        let value = match (self.config.get_str(key), default) {
+            (Ok(value), _) => value,
+            (Err(error), Some(default)) if error.is_not_found() => default.into(),
+            (Err(error), _) => {
+                self.problem(key, error);
+                return None;
+            }
+        };
+        if values.contains(&value.as_str()) {
+            Some(value)
+        } else {
+            let reason = format!(
+                "unknown value '{}', expected one of {}",
+                value,
+                values.join(", ")
+            );
+            self.invalid(key, reason);
+            None
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(self.problems))
+        }
+    }
 }
 
+// Load and validate the configuration, then apply it.
+// Nothing changes when any step fails, the previous configuration stays in use.
+pub fn reload<F>(source: &Source, validate: fn(&mut Validation), apply: &F) -> Result<(), Error>
+where
+    F: Fn(&Config) -> Result<(), String>,
+{
+    let config = Config::load(source)?;
+    let mut validation = Validation::new(&config);
+    validate(&mut validation);
+    validation.finish()?;
+    apply(&config).map_err(|reason| Error::Validation(vec![config.redact(&reason)]))
+}
+
+// Stops watching once dropped
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+// Reload the configuration when any of its files changes
+pub fn watch<F>(
+    source: &Source,
+    validate: fn(&mut Validation),
+    apply: F,
+) -> Result<ConfigWatcher, Error>
+where
+    F: Fn(&Config) -> Result<(), String> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let _ = sender.send(event);
+        }
+    })?;
+
+    // Editors often replace the file rather than write it, watch its directory.
+    // The profile file is next to the base one.
+    let file = Path::new(&source.path);
+    let directory = match file.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+    let names: Vec<_> = source
+        .files()
+        .iter()
+        .filter_map(|file| Path::new(file).file_name().map(|name| name.to_os_string()))
+        .collect();
+    let source = source.clone();
+    thread::spawn(move || {
+        // The channel is closed once the watcher is dropped
+        while let Ok(event) = receiver.recv() {
+            if event.kind.is_access()
+                || !event.paths.iter().any(|changed| {
+                    changed
+                        .file_stem()
+                        .map(|stem| names.iter().any(|name| name == stem))
+                        .unwrap_or(false)
+                })
+            {
+                continue;
+            }
+            // A file is usually written in several steps, reload once they are all done
+            thread::sleep(Duration::from_millis(100));
+            while receiver.try_recv().is_ok() {}
+
+            match reload(&source, validate, &apply) {
+                Ok(()) => info!("Configuration reloaded from {}", source),
+                Err(error) => warn!(
+                    "Configuration change rejected, keeping the previous one: {}",
+                    error
+                ),
+            }
+        }
+    });
+
+    Ok(ConfigWatcher { _watcher: watcher })
+}
+
+// Secrets are resolved once, when the configuration is loaded
 impl From<config::Config> for Config {
-    fn from(config: config::Config) -> Self {
-        Config { config }
+    fn from(mut config: config::Config) -> Self {
+        let mut found = vec![];
+        if let Ok(table) = config.clone().try_into::<HashMap<String, config::Value>>() {
+            references("", table, &mut found);
+        }
+
+        let mut secrets = HashMap::new();
+        let mut unresolved = vec![];
+        for (key, resolved) in found {
+            match resolved {
+                Ok(value) => {
+                    // Set as nested keys, they take precedence over quoted dotted keys
+                    let _ = config.set(&key, value.clone());
+                    secrets.insert(key, value);
+                }
+                Err(reason) => unresolved.push(format!("{}: {}", key, reason)),
+            }
+        }
+        unresolved.sort();
+
+        Config {
+            config,
+            secrets,
+            unresolved,
+        }
+    }
+}
+
+impl Config {
+    // All the values by flat key, secrets redacted
+    fn redacted(&self) -> BTreeMap<String, String> {
+        let mut flat_table = HashMap::new();
+        if let Ok(table) = self
+            .config
+            .clone()
+            .try_into::<HashMap<String, config::Value>>()
+        {
+            flatten("", table, &mut flat_table);
+        }
+        flat_table
+            .into_iter()
+            .map(|(key, value)| {
+                let value = if self.secrets.contains_key(&key) {
+                    REDACTED.into()
+                } else {
+                    value
+                };
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.redacted().iter()).finish()
+    }
+}
+
+// One key = value line per value, as printed by --print-config
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in self.redacted() {
+            writeln!(f, "{} = {:?}", key, value)?;
+        }
+        Ok(())
     }
 }
 
@@ -52,19 +509,353 @@ mod tests {
         let mut config = config::Config::default();
         let _ = config.set("abc", "123");
 
-        let config = Config { config };
+        let config = Config::from(config);
         assert_eq!(config.get_str("abc").unwrap().as_str(), "123");
         assert!(config.get_str("def").is_err());
     }
 
+    #[test]
+    fn config_get_int() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc", 123);
+        let _ = config.set("def", "456");
+        let _ = config.set("ghi", "jkl");
+
+        let config = Config::from(config);
+        assert_eq!(config.get_int("abc").unwrap(), 123);
+        assert_eq!(config.get_int("def").unwrap(), 456);
+        assert!(config.get_int("ghi").is_err());
+        assert!(config.get_int("mno").is_err());
+    }
+
     #[test]
     fn config_get_str_vec() {
         let mut config = config::Config::default();
         let _ = config.set("abc", vec!["123", "456"]);
 
-        let config = Config { config };
+        let config = Config::from(config);
         let vec = config.get_str_vec("abc").unwrap();
         assert_eq!(vec[0].as_str(), "123");
         assert_eq!(vec[1].as_str(), "456");
     }
+
+    #[test]
+    fn config_get_flat_table() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc.linger.ms", 5);
+        let _ = config.set("abc.acks", "all");
+        let _ = config.set("abc.enable.idempotence", true);
+
+        let config = Config::from(config);
+        let table = config.get_flat_table("abc").unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table["linger.ms"].as_str(), "5");
+        assert_eq!(table["acks"].as_str(), "all");
+        assert_eq!(table["enable.idempotence"].as_str(), "true");
+        assert!(config.get_flat_table("def").is_err());
+    }
+
+    #[test]
+    fn config_get_flat_table_nested_keys_precedence() {
+        let mut config = config::Config::default();
+        let mut table = HashMap::<String, config::Value>::new();
+        table.insert("group.id".into(), "from file".into());
+        let _ = config.set("abc", table);
+        let _ = config.set("abc.group.id", "from env");
+
+        let config = Config::from(config);
+        let table = config.get_flat_table("abc").unwrap();
+        assert_eq!(table["group.id"].as_str(), "from env");
+    }
+
+    #[test]
+    fn config_get_bool() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc", true);
+        let _ = config.set("def", "false");
+
+        let config = Config::from(config);
+        assert!(config.get_bool("abc").unwrap());
+        assert!(!config.get_bool("def").unwrap());
+        assert!(config.get_bool("ghi").is_err());
+    }
+
+    #[test]
+    fn config_differs() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc.def", 1);
+        let _ = config.set("ghi.jkl", "value");
+        let before = Config::from(config.clone());
+        let _ = config.set("abc.def", 2);
+        let after = Config::from(config);
+
+        assert!(before.differs(&after, &["abc"]));
+        assert!(!before.differs(&after, &["ghi", "unset"]));
+    }
+
+    #[test]
+    fn config_get_duration() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc", 30);
+        let _ = config.set("def", "500ms");
+        let _ = config.set("ghi", "5m");
+        let _ = config.set("jkl", "soon");
+        let _ = config.set("mno", -1);
+
+        let config = Config::from(config);
+        assert_eq!(config.get_duration("abc").unwrap(), Duration::from_secs(30));
+        assert_eq!(
+            config.get_duration("def").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            config.get_duration("ghi").unwrap(),
+            Duration::from_secs(300)
+        );
+        match config.get_duration("jkl") {
+            Err(Error::Invalid(key, _)) => assert_eq!(key, "jkl"),
+            _ => unreachable!(),
+        }
+        assert!(config.get_duration("mno").is_err());
+        assert!(config.get_duration("pqr").unwrap_err().is_not_found());
+    }
+
+    #[test]
+    fn config_get_socket_addr_url_ethereum_address() {
+        let mut config = config::Config::default();
+        let _ = config.set("bind", "127.0.0.1:8080");
+        let _ = config.set("url", "redis://127.0.0.1:6379");
+        let _ = config.set("address", "0xa80E74Ee52efc3D28CF3778d1B54B4dc0c23028b");
+        let _ = config.set("invalid", "not valid");
+
+        let config = Config::from(config);
+        assert_eq!(config.get_socket_addr("bind").unwrap().port(), 8080);
+        assert_eq!(config.get_url("url").unwrap().scheme(), "redis");
+        assert_eq!(
+            format!("{:?}", config.get_ethereum_address("address").unwrap()),
+            "0xa80e74ee52efc3d28cf3778d1b54b4dc0c23028b"
+        );
+        assert!(config.get_socket_addr("invalid").is_err());
+        assert!(config.get_url("invalid").is_err());
+        assert!(config.get_ethereum_address("invalid").is_err());
+    }
+
+    #[test]
+    fn config_get_section() {
+        #[derive(serde::Deserialize)]
+        struct Section {
+            host: String,
+            port: u16,
+        }
+
+        let mut config = config::Config::default();
+        let _ = config.set("abc.host", "127.0.0.1");
+        let _ = config.set("abc.port", "3000");
+        let _ = config.set("def.host", "127.0.0.1");
+
+        let config = Config::from(config);
+        let section = config.get::<Section>("abc").unwrap();
+        assert_eq!(section.host, "127.0.0.1");
+        assert_eq!(section.port, 3000);
+        assert!(config.get::<Section>("def").is_err());
+    }
+
+    #[test]
+    fn config_validation() {
+        let mut config = config::Config::default();
+        let _ = config.set("bind", "127.0.0.1:8080");
+        let _ = config.set("timeout", "soon");
+        let _ = config.set("connector", "kafka");
+        let _ = config.set("codec", "xml");
+
+        let config = Config::from(config);
+        let mut validation = Validation::new(&config);
+        assert!(validation
+            .required("bind", Config::get_socket_addr)
+            .is_some());
+        assert!(validation.required("topic", Config::get_str).is_none());
+        assert!(validation.required("topic", Config::get_str).is_none());
+        assert!(validation
+            .optional("timeout", Config::get_duration)
+            .is_none());
+        assert!(validation.optional("ttl", Config::get_int).is_none());
+        assert_eq!(
+            validation
+                .one_of("connector", &["kafka", "nats"], None)
+                .as_deref(),
+            Some("kafka")
+        );
+        assert_eq!(
+            validation
+                .one_of("destination", &["http", "debug"], Some("http"))
+                .as_deref(),
+            Some("http")
+        );
+        assert!(validation
+            .one_of("codec", &["json", "avro"], Some("json"))
+            .is_none());
+
+        // Every problem is reported, once
+        match validation.finish() {
+            Err(Error::Validation(problems)) => {
+                assert_eq!(problems.len(), 3);
+                assert_eq!(problems[0], "topic: missing");
+                assert!(problems[1].starts_with("timeout: "));
+                assert_eq!(
+                    problems[2],
+                    "codec: unknown value 'xml', expected one of json, avro"
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        let mut validation = Validation::new(&config);
+        validation.required("bind", Config::get_socket_addr);
+        assert!(validation.finish().is_ok());
+    }
+
+    fn validate_abc(validation: &mut Validation) {
+        validation.required("abc", Config::get_int);
+    }
+
+    #[test]
+    fn config_reload() {
+        let directory = std::env::temp_dir().join("ucdp-config-reload");
+        let _ = std::fs::create_dir_all(&directory);
+        let path = directory.join("Main");
+        let source = Source::new(path.to_str().unwrap(), None);
+        let applied = std::sync::Mutex::new(vec![]);
+        let apply = |config: &Config| {
+            let abc = config.get_int("abc").unwrap();
+            if abc < 0 {
+                return Err("negative".to_string());
+            }
+            applied.lock().unwrap().push(abc);
+            Ok(())
+        };
+
+        std::fs::write(directory.join("Main.toml"), "abc = 1").unwrap();
+        assert!(reload(&source, validate_abc, &apply).is_ok());
+
+        // Neither unparsable, invalid nor rejected configurations are applied
+        std::fs::write(directory.join("Main.toml"), "abc = ").unwrap();
+        assert!(reload(&source, validate_abc, &apply).is_err());
+        std::fs::write(directory.join("Main.toml"), "abc = \"def\"").unwrap();
+        assert!(reload(&source, validate_abc, &apply).is_err());
+        std::fs::write(directory.join("Main.toml"), "abc = -1").unwrap();
+        assert!(reload(&source, validate_abc, &apply).is_err());
+
+        assert_eq!(*applied.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn config_watch() {
+        let directory = std::env::temp_dir().join("ucdp-config-watch");
+        let _ = std::fs::create_dir_all(&directory);
+        std::fs::write(directory.join("Main.toml"), "abc = 1").unwrap();
+        std::fs::write(directory.join("Main.dev.toml"), "def = 1").unwrap();
+        let source = Source::new(directory.join("Main").to_str().unwrap(), Some("dev"));
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        let _watcher = watch(&source, validate_abc, move |config| {
+            let _ = sender.lock().unwrap().send(config.get_int("abc").unwrap());
+            Ok(())
+        })
+        .unwrap();
+
+        std::fs::write(directory.join("Other.toml"), "abc = 2").unwrap();
+        std::fs::write(directory.join("Main.toml"), "abc = 3").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), 3);
+
+        // The profile file is watched as well
+        std::fs::write(directory.join("Main.dev.toml"), "abc = 4").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), 4);
+    }
+
+    #[test]
+    fn config_load_profile() {
+        let directory = std::env::temp_dir().join("ucdp-config-load-profile");
+        let _ = std::fs::create_dir_all(&directory);
+        std::fs::write(directory.join("Main.toml"), "abc = 1\ndef = 2\n").unwrap();
+        std::fs::write(directory.join("Main.prod.toml"), "def = 3\n").unwrap();
+        let path = directory.join("Main");
+        let path = path.to_str().unwrap();
+
+        let config = Config::load(&Source::new(path, None)).unwrap();
+        assert_eq!(config.get_int("def").unwrap(), 2);
+
+        // The profile file overrides the base one
+        let config = Config::load(&Source::new(path, Some("prod"))).unwrap();
+        assert_eq!(config.get_int("abc").unwrap(), 1);
+        assert_eq!(config.get_int("def").unwrap(), 3);
+
+        // A profile without file is an error rather than the base configuration
+        assert!(Config::load(&Source::new(path, Some("dev"))).is_err());
+    }
+
+    #[test]
+    fn config_secrets() {
+        let path = std::env::temp_dir().join("ucdp-config-secrets");
+        std::fs::write(&path, "from file\n").unwrap();
+        std::env::set_var("UCDP_TEST_SECRET", "from env");
+
+        let mut config = config::Config::default();
+        let _ = config.set("abc", format!("${{file:{}}}", path.to_str().unwrap()));
+        let mut table = HashMap::<String, config::Value>::new();
+        table.insert("sasl.password".into(), "${env:UCDP_TEST_SECRET}".into());
+        let _ = config.set("def", table);
+        let _ = config.set("ghi", "https://host/?token=${env:UCDP_TEST_SECRET}");
+        let _ = config.set("jkl", "${unknown:reference}");
+        let _ = config.set("mno", 123);
+
+        let config = Config::from(config);
+        assert_eq!(config.get_str("abc").unwrap(), "from file");
+        assert_eq!(
+            config.get_flat_table("def").unwrap()["sasl.password"],
+            "from env"
+        );
+        assert_eq!(
+            config.get_str("ghi").unwrap(),
+            "https://host/?token=from env"
+        );
+        assert_eq!(config.get_str("jkl").unwrap(), "${unknown:reference}");
+        assert!(Validation::new(&config).finish().is_ok());
+
+        // Secrets never show
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("from"));
+        assert!(debug.contains("\"abc\": \"[redacted]\""));
+        assert!(debug.contains("\"mno\": \"123\""));
+        let display = config.to_string();
+        assert!(!display.contains("from"));
+        assert!(display.contains("abc = \"[redacted]\"\n"));
+        assert!(display.contains("def.sasl.password = \"[redacted]\"\n"));
+        let mut validation = Validation::new(&config);
+        validation.required("ghi", Config::get_int);
+        match validation.finish() {
+            Err(Error::Validation(problems)) => assert!(!problems[0].contains("from env")),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn config_secrets_unresolved() {
+        let mut config = config::Config::default();
+        let _ = config.set("abc", "${env:UCDP_TEST_UNSET}");
+        let _ = config.set("def", "${file:/no/such/file}");
+
+        let config = Config::from(config);
+        match Validation::new(&config).finish() {
+            Err(Error::Validation(problems)) => {
+                assert_eq!(problems.len(), 2);
+                assert_eq!(
+                    problems[0],
+                    "abc: environment variable UCDP_TEST_UNSET is not set"
+                );
+                assert!(problems[1].starts_with("def: cannot read /no/such/file"));
+            }
+            _ => unreachable!(),
+        }
+    }
 }
@@ -1,2 +1,6 @@
+pub mod cli;
 pub mod config;
+pub mod logging;
+pub mod shutdown;
 pub mod stream;
+pub mod telemetry;
@@ -0,0 +1,149 @@
+use crate::config::Config;
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+use std::io::Write;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("unknown log format: {0}")]
+    UnknownFormat(String),
+
+    #[error("logger error")]
+    Logger(#[from] log::SetLoggerError),
+}
+
+// Key-values of a record as JSON fields
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_bool() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_i64() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_u64() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_f64() {
+            serde_json::Value::from(value)
+        } else {
+            serde_json::Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+// Key-values of a record appended to the message
+struct HumanFields(String);
+
+impl<'kvs> VisitSource<'kvs> for HumanFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push_str(&format!(" {}={}", key, value));
+        Ok(())
+    }
+}
+
+fn json_line(record: &Record, timestamp: &str) -> serde_json::Value {
+    let mut fields = JsonFields(serde_json::Map::new());
+    fields.0.insert("timestamp".into(), timestamp.into());
+    fields
+        .0
+        .insert("level".into(), record.level().as_str().into());
+    fields.0.insert("target".into(), record.target().into());
+    fields
+        .0
+        .insert("message".into(), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut fields);
+    serde_json::Value::Object(fields.0)
+}
+
+fn human_line(record: &Record, timestamp: &str) -> String {
+    let mut fields = HumanFields(String::new());
+    let _ = record.key_values().visit(&mut fields);
+    format!(
+        "[{} {:<5} {}] {}{}",
+        timestamp,
+        record.level(),
+        record.target(),
+        record.args(),
+        fields.0
+    )
+}
+
+// Logs are filtered with RUST_LOG as before, log.format picks human (default) or json output
+pub fn init(config: &Config) -> Result<(), Error> {
+    let mut builder = env_logger::Builder::from_default_env();
+    match config
+        .get_str("log.format")
+        .unwrap_or_else(|_| "human".into())
+        .as_str()
+    {
+        "human" => builder.format(|buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            writeln!(buf, "{}", human_line(record, &timestamp))
+        }),
+        "json" => builder.format(|buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            writeln!(buf, "{}", json_line(record, &timestamp))
+        }),
+        format => return Err(Error::UnknownFormat(format.into())),
+    };
+    builder.try_init()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{human_line, init, json_line, Error};
+    use crate::config::Config;
+    use log::{Level, Record};
+
+    #[test]
+    fn logging_json_line() {
+        let key_values = [("request_id", "abc"), ("token", "t")];
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Events {}", "delivered"))
+                .level(Level::Info)
+                .target("ucdp")
+                .key_values(&key_values)
+                .build(),
+            "2021-10-01T00:00:00.000Z",
+        );
+        assert_eq!(line["timestamp"], "2021-10-01T00:00:00.000Z");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "ucdp");
+        assert_eq!(line["message"], "Events delivered");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["token"], "t");
+    }
+
+    #[test]
+    fn logging_human_line() {
+        let key_values = [("request_id", "abc")];
+        let line = human_line(
+            &Record::builder()
+                .args(format_args!("Events delivered"))
+                .level(Level::Warn)
+                .target("ucdp")
+                .key_values(&key_values)
+                .build(),
+            "2021-10-01T00:00:00.000Z",
+        );
+        assert_eq!(
+            line,
+            "[2021-10-01T00:00:00.000Z WARN  ucdp] Events delivered request_id=abc"
+        );
+    }
+
+    #[test]
+    fn logging_init_err_unknown_format() {
+        let mut config = config::Config::default();
+        let _ = config.set("log.format", "xml");
+        match init(&Config::from(config)) {
+            Err(Error::UnknownFormat(format)) => assert_eq!(format, "xml"),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::config::{Config, Validation};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError};
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long in-flight work is given to complete once asked to stop, 30 seconds by default
+pub fn drain_timeout(config: &Config) -> Duration {
+    config
+        .get_duration("shutdown.drain_timeout")
+        .unwrap_or_else(|_| Duration::from_secs(30))
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.optional("shutdown.drain_timeout", Config::get_duration);
+}
+
+// Set on SIGTERM or SIGINT, when draining starts
+pub struct Terminate {
+    drain_timeout: Duration,
+    deadline: OnceCell<Instant>,
+}
+
+impl Terminate {
+    pub fn is_set(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    // In-flight work must be done by then, every drain step shares it.
+    // Draining starts now if no signal has started it.
+    pub fn deadline(&self) -> Instant {
+        *self
+            .deadline
+            .get_or_init(|| Instant::now() + self.drain_timeout)
+    }
+}
+
+// Draining starts on SIGTERM or SIGINT.
+// The process exits anyway if it has not stopped by itself by the deadline.
+pub fn on_terminate(drain_timeout: Duration) -> Result<Arc<Terminate>, std::io::Error> {
+    let terminate = Arc::new(Terminate {
+        drain_timeout,
+        deadline: OnceCell::new(),
+    });
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+    let flag = terminate.clone();
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("Received signal {}, draining", signal);
+            let deadline = flag.deadline();
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            warn!("Drain timeout elapsed, exiting");
+            std::process::exit(1);
+        }
+    });
+
+    Ok(terminate)
+}
+
+// A thread whose end can be waited for until a deadline
+pub struct Worker {
+    handle: JoinHandle<()>,
+    // Disconnected once the thread is done, even if it panicked
+    done: Receiver<()>,
+}
+
+pub fn spawn<F>(f: F) -> Worker
+where
+    F: FnOnce() + Send + 'static,
+{
+    let (sender, done) = bounded::<()>(0);
+    let handle = thread::spawn(move || {
+        let _done = sender;
+        f()
+    });
+    Worker { handle, done }
+}
+
+impl Worker {
+    // Return false if the thread did not finish by the deadline
+    pub fn join_until(self, deadline: Instant) -> bool {
+        match self.done.recv_deadline(deadline) {
+            Err(RecvTimeoutError::Disconnected) => self.handle.join().is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{drain_timeout, spawn};
+    use crate::config::Config;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn shutdown_drain_timeout() {
+        let mut config = config::Config::default();
+        assert_eq!(
+            drain_timeout(&Config::from(config.clone())),
+            Duration::from_secs(30)
+        );
+
+        let _ = config.set("shutdown.drain_timeout", 5);
+        assert_eq!(drain_timeout(&Config::from(config)), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn shutdown_join_until() {
+        let worker = spawn(|| thread::sleep(Duration::from_millis(10)));
+        assert!(worker.join_until(Instant::now() + Duration::from_secs(1)));
+
+        let worker = spawn(|| thread::sleep(Duration::from_secs(1)));
+        assert!(!worker.join_until(Instant::now() + Duration::from_millis(10)));
+
+        let worker = spawn(|| panic!("worker panicked"));
+        assert!(!worker.join_until(Instant::now() + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,753 @@
+// Avro binary encoding driven by schemas, with the schema resolution rules of the specification:
+// data written with one schema is read with another one, fields are matched by name,
+// fields unknown to the reader are skipped and fields unknown to the writer take their default.
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid schema: {0}")]
+    Schema(String),
+
+    #[error("invalid data: {0}")]
+    Data(String),
+
+    #[error("writer schema cannot be read as reader schema: {0}")]
+    Resolution(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub schema: Schema,
+    pub default: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record { name: String, fields: Vec<Field> },
+    Enum { name: String, symbols: Vec<String> },
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed { name: String, size: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Bytes(Vec<u8>),
+    String(String),
+    Record(Vec<(String, Value)>),
+    Enum(String),
+    Array(Vec<Value>),
+    Map(HashMap<String, Value>),
+    // Value of the branch of a union schema
+    Union(usize, Box<Value>),
+    Fixed(Vec<u8>),
+}
+
+impl Value {
+    // Field of a record
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Record(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // Value of a union branch, the value itself otherwise
+    pub fn unwrap_union(&self) -> &Value {
+        match self {
+            Value::Union(_, value) => value,
+            value => value,
+        }
+    }
+}
+
+impl Schema {
+    pub fn parse(schema: &str) -> Result<Schema, Error> {
+        let json: serde_json::Value =
+            serde_json::from_str(schema).map_err(|error| Error::Schema(error.to_string()))?;
+        Schema::parse_json(&json, None, &mut HashMap::new())
+    }
+
+    fn parse_json(
+        json: &serde_json::Value,
+        namespace: Option<&str>,
+        names: &mut HashMap<String, Schema>,
+    ) -> Result<Schema, Error> {
+        match json {
+            serde_json::Value::String(name) => Schema::parse_name(name, namespace, names),
+            serde_json::Value::Array(branches) => Ok(Schema::Union(
+                branches
+                    .iter()
+                    .map(|branch| Schema::parse_json(branch, namespace, names))
+                    .collect::<Result<_, _>>()?,
+            )),
+            serde_json::Value::Object(object) => {
+                let kind = object
+                    .get("type")
+                    .ok_or_else(|| Error::Schema("missing type".into()))?;
+                let kind = match kind {
+                    serde_json::Value::String(kind) => kind.as_str(),
+                    kind => return Schema::parse_json(kind, namespace, names),
+                };
+                let str_of = |key: &str| {
+                    object
+                        .get(key)
+                        .and_then(|value| value.as_str())
+                        .ok_or_else(|| Error::Schema(format!("{} must have a {}", kind, key)))
+                };
+                match kind {
+                    "record" | "error" => {
+                        let (name, namespace) =
+                            full_name(str_of("name")?, object.get("namespace"), namespace);
+                        let fields = object
+                            .get("fields")
+                            .and_then(|fields| fields.as_array())
+                            .ok_or_else(|| Error::Schema(format!("{} must have fields", name)))?
+                            .iter()
+                            .map(|field| {
+                                let field_name = field
+                                    .get("name")
+                                    .and_then(|name| name.as_str())
+                                    .ok_or_else(|| {
+                                    Error::Schema("field must have a name".into())
+                                })?;
+                                let schema = field.get("type").ok_or_else(|| {
+                                    Error::Schema(format!("field {} must have a type", field_name))
+                                })?;
+                                Ok(Field {
+                                    name: field_name.into(),
+                                    schema: Schema::parse_json(
+                                        schema,
+                                        namespace.as_deref(),
+                                        names,
+                                    )?,
+                                    default: field.get("default").cloned(),
+                                })
+                            })
+                            .collect::<Result<_, Error>>()?;
+                        let schema = Schema::Record {
+                            name: name.clone(),
+                            fields,
+                        };
+                        names.insert(name, schema.clone());
+                        Ok(schema)
+                    }
+                    "enum" => {
+                        let (name, _) =
+                            full_name(str_of("name")?, object.get("namespace"), namespace);
+                        let symbols = object
+                            .get("symbols")
+                            .and_then(|symbols| symbols.as_array())
+                            .ok_or_else(|| Error::Schema(format!("{} must have symbols", name)))?
+                            .iter()
+                            .map(|symbol| {
+                                symbol
+                                    .as_str()
+                                    .map(String::from)
+                                    .ok_or_else(|| Error::Schema("invalid symbol".into()))
+                            })
+                            .collect::<Result<_, _>>()?;
+                        let schema = Schema::Enum {
+                            name: name.clone(),
+                            symbols,
+                        };
+                        names.insert(name, schema.clone());
+                        Ok(schema)
+                    }
+                    "fixed" => {
+                        let (name, _) =
+                            full_name(str_of("name")?, object.get("namespace"), namespace);
+                        let size = object
+                            .get("size")
+                            .and_then(|size| size.as_u64())
+                            .ok_or_else(|| Error::Schema(format!("{} must have a size", name)))?;
+                        let schema = Schema::Fixed {
+                            name: name.clone(),
+                            size: size as usize,
+                        };
+                        names.insert(name, schema.clone());
+                        Ok(schema)
+                    }
+                    "array" => Ok(Schema::Array(Box::new(Schema::parse_json(
+                        object
+                            .get("items")
+                            .ok_or_else(|| Error::Schema("array must have items".into()))?,
+                        namespace,
+                        names,
+                    )?))),
+                    "map" => Ok(Schema::Map(Box::new(Schema::parse_json(
+                        object
+                            .get("values")
+                            .ok_or_else(|| Error::Schema("map must have values".into()))?,
+                        namespace,
+                        names,
+                    )?))),
+                    // Logical types are read as their underlying type
+                    kind => Schema::parse_name(kind, namespace, names),
+                }
+            }
+            json => Err(Error::Schema(format!("unexpected {}", json))),
+        }
+    }
+
+    fn parse_name(
+        name: &str,
+        namespace: Option<&str>,
+        names: &HashMap<String, Schema>,
+    ) -> Result<Schema, Error> {
+        match name {
+            "null" => Ok(Schema::Null),
+            "boolean" => Ok(Schema::Boolean),
+            "int" => Ok(Schema::Int),
+            "long" => Ok(Schema::Long),
+            "float" => Ok(Schema::Float),
+            "double" => Ok(Schema::Double),
+            "bytes" => Ok(Schema::Bytes),
+            "string" => Ok(Schema::String),
+            name => {
+                let (full_name, _) = full_name(name, None, namespace);
+                names
+                    .get(&full_name)
+                    .or_else(|| names.get(name))
+                    .cloned()
+                    .ok_or_else(|| Error::Schema(format!("unknown type {}", name)))
+            }
+        }
+    }
+
+    // Name of a named type, compared when resolving schemas
+    fn name(&self) -> Option<&str> {
+        match self {
+            Schema::Record { name, .. }
+            | Schema::Enum { name, .. }
+            | Schema::Fixed { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    // Whether data written with the writer schema can be read with this schema, unions aside
+    fn matches(&self, writer: &Schema) -> bool {
+        match (writer, self) {
+            (Schema::Int, Schema::Long | Schema::Float | Schema::Double)
+            | (Schema::Long, Schema::Float | Schema::Double)
+            | (Schema::Float, Schema::Double)
+            | (Schema::String, Schema::Bytes)
+            | (Schema::Bytes, Schema::String) => true,
+            (Schema::Array(_), Schema::Array(_)) | (Schema::Map(_), Schema::Map(_)) => true,
+            (Schema::Record { .. }, Schema::Record { .. })
+            | (Schema::Enum { .. }, Schema::Enum { .. })
+            | (Schema::Fixed { .. }, Schema::Fixed { .. }) => writer.name() == self.name(),
+            (Schema::Union(_), _) | (_, Schema::Union(_)) => false,
+            (writer, reader) => std::mem::discriminant(writer) == std::mem::discriminant(reader),
+        }
+    }
+}
+
+// Full name of a named type and the namespace of its fields
+fn full_name(
+    name: &str,
+    namespace: Option<&serde_json::Value>,
+    enclosing: Option<&str>,
+) -> (String, Option<String>) {
+    if name.contains('.') {
+        let namespace = name.rsplit_once('.').map(|(namespace, _)| namespace.into());
+        return (name.into(), namespace);
+    }
+    match namespace
+        .and_then(|namespace| namespace.as_str())
+        .or(enclosing)
+    {
+        Some(namespace) if !namespace.is_empty() => {
+            (format!("{}.{}", namespace, name), Some(namespace.into()))
+        }
+        _ => (name.into(), None),
+    }
+}
+
+fn write_long(bytes: &mut Vec<u8>, value: i64) {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    while n & !0x7f != 0 {
+        bytes.push(((n & 0x7f) | 0x80) as u8);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    write_long(bytes, value.len() as i64);
+    bytes.extend_from_slice(value);
+}
+
+// Append the value, which must follow the schema
+pub fn encode(bytes: &mut Vec<u8>, value: &Value, schema: &Schema) -> Result<(), Error> {
+    match (schema, value) {
+        (Schema::Null, Value::Null) => {}
+        (Schema::Boolean, Value::Boolean(value)) => bytes.push(*value as u8),
+        (Schema::Int, Value::Int(value)) => write_long(bytes, *value as i64),
+        (Schema::Long, Value::Long(value)) => write_long(bytes, *value),
+        (Schema::Float, Value::Float(value)) => bytes.extend_from_slice(&value.to_le_bytes()),
+        (Schema::Double, Value::Double(value)) => bytes.extend_from_slice(&value.to_le_bytes()),
+        (Schema::Bytes, Value::Bytes(value)) => write_bytes(bytes, value),
+        (Schema::String, Value::String(value)) => write_bytes(bytes, value.as_bytes()),
+        (Schema::Record { fields, .. }, Value::Record(_)) => {
+            for field in fields {
+                let value = value
+                    .field(&field.name)
+                    .ok_or_else(|| Error::Data(format!("missing field {}", field.name)))?;
+                encode(bytes, value, &field.schema)?;
+            }
+        }
+        (Schema::Enum { symbols, .. }, Value::Enum(symbol)) => {
+            let index = symbols
+                .iter()
+                .position(|s| s == symbol)
+                .ok_or_else(|| Error::Data(format!("unknown symbol {}", symbol)))?;
+            write_long(bytes, index as i64);
+        }
+        (Schema::Array(items), Value::Array(values)) => {
+            if !values.is_empty() {
+                write_long(bytes, values.len() as i64);
+                for value in values {
+                    encode(bytes, value, items)?;
+                }
+            }
+            write_long(bytes, 0);
+        }
+        (Schema::Map(values_schema), Value::Map(values)) => {
+            if !values.is_empty() {
+                write_long(bytes, values.len() as i64);
+                for (key, value) in values {
+                    write_bytes(bytes, key.as_bytes());
+                    encode(bytes, value, values_schema)?;
+                }
+            }
+            write_long(bytes, 0);
+        }
+        (Schema::Union(branches), Value::Union(index, value)) => {
+            let branch = branches
+                .get(*index)
+                .ok_or_else(|| Error::Data(format!("invalid union index: {}", index)))?;
+            write_long(bytes, *index as i64);
+            encode(bytes, value, branch)?;
+        }
+        (Schema::Fixed { size, .. }, Value::Fixed(value)) if value.len() == *size => {
+            bytes.extend_from_slice(value)
+        }
+        (schema, value) => {
+            return Err(Error::Data(format!(
+                "{:?} does not match {:?}",
+                value, schema
+            )))
+        }
+    }
+    Ok(())
+}
+
+// Items of the arrays and maps of a payload. Items taking no bytes, such as nulls, would
+// otherwise let a few bytes declare any number of them.
+const MAX_ITEMS: usize = 100_000;
+
+impl Schema {
+    // Bytes a value of the schema takes at the very least
+    fn min_size(&self) -> usize {
+        match self {
+            Schema::Null => 0,
+            Schema::Float => 4,
+            Schema::Double => 8,
+            Schema::Record { fields, .. } => {
+                fields.iter().map(|field| field.schema.min_size()).sum()
+            }
+            Schema::Fixed { size, .. } => *size,
+            // A varint, a length, an index or the count ending the blocks
+            _ => 1,
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    // Items of arrays and maps read so far
+    items: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.bytes.len() {
+            return Err(Error::Data("unexpected end of data".into()));
+        }
+        let (value, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(value)
+    }
+
+    fn read_long(&mut self) -> Result<i64, Error> {
+        let mut n: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.take(1)?[0];
+            if shift > 63 {
+                return Err(Error::Data("invalid long".into()));
+            }
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.read_long()?;
+        if len < 0 {
+            return Err(Error::Data("invalid length".into()));
+        }
+        self.take(len as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.read_bytes()?.to_vec())
+            .map_err(|_| Error::Data("invalid string".into()))
+    }
+
+    // Item count of the next block, skipping the optional block size.
+    // A count the data left cannot hold is refused before reading any item.
+    fn read_block_count(&mut self, item_size: usize) -> Result<usize, Error> {
+        let count = self.read_long()?;
+        if count < 0 {
+            let _size = self.read_long()?;
+        }
+        let count = usize::try_from(count.unsigned_abs())
+            .map_err(|_| Error::Data("invalid block count".into()))?;
+        if item_size > 0 && count > self.bytes.len() / item_size {
+            return Err(Error::Data("block count beyond the data".into()));
+        }
+        self.items = self.items.saturating_add(count);
+        if self.items > MAX_ITEMS {
+            return Err(Error::Data(format!("more than {} items", MAX_ITEMS)));
+        }
+        Ok(count)
+    }
+
+    // Read data written with the writer schema as the reader schema
+    fn read(&mut self, writer: &Schema, reader: &Schema) -> Result<Value, Error> {
+        match (writer, reader) {
+            (Schema::Union(branches), reader) => {
+                let index = self.read_long()?;
+                let branch = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| branches.get(index))
+                    .ok_or_else(|| Error::Data(format!("invalid union index: {}", index)))?;
+                self.read(branch, reader)
+            }
+            (writer, Schema::Union(branches)) => {
+                let (index, branch) = branches
+                    .iter()
+                    .enumerate()
+                    .find(|(_, branch)| branch.matches(writer))
+                    .ok_or_else(|| Error::Resolution(format!("{:?} in union", writer)))?;
+                Ok(Value::Union(index, Box::new(self.read(writer, branch)?)))
+            }
+            (writer, reader) if !reader.matches(writer) => {
+                Err(Error::Resolution(format!("{:?} as {:?}", writer, reader)))
+            }
+            (Schema::Null, _) => Ok(Value::Null),
+            (Schema::Boolean, _) => match self.take(1)?[0] {
+                0 => Ok(Value::Boolean(false)),
+                1 => Ok(Value::Boolean(true)),
+                _ => Err(Error::Data("invalid boolean".into())),
+            },
+            (Schema::Int, reader) => {
+                let value = self.read_long()?;
+                let value = i32::try_from(value).map_err(|_| Error::Data("invalid int".into()))?;
+                Ok(match reader {
+                    Schema::Long => Value::Long(value as i64),
+                    Schema::Float => Value::Float(value as f32),
+                    Schema::Double => Value::Double(value as f64),
+                    _ => Value::Int(value),
+                })
+            }
+            (Schema::Long, reader) => {
+                let value = self.read_long()?;
+                Ok(match reader {
+                    Schema::Float => Value::Float(value as f32),
+                    Schema::Double => Value::Double(value as f64),
+                    _ => Value::Long(value),
+                })
+            }
+            (Schema::Float, reader) => {
+                let value = f32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default());
+                Ok(match reader {
+                    Schema::Double => Value::Double(value as f64),
+                    _ => Value::Float(value),
+                })
+            }
+            (Schema::Double, _) => Ok(Value::Double(f64::from_le_bytes(
+                self.take(8)?.try_into().unwrap_or_default(),
+            ))),
+            (Schema::Bytes | Schema::String, Schema::String) => {
+                Ok(Value::String(self.read_string()?))
+            }
+            (Schema::Bytes | Schema::String, _) => Ok(Value::Bytes(self.read_bytes()?.to_vec())),
+            (
+                Schema::Record {
+                    fields: writer_fields,
+                    ..
+                },
+                Schema::Record {
+                    fields: reader_fields,
+                    ..
+                },
+            ) => {
+                let mut values = HashMap::new();
+                for writer_field in writer_fields {
+                    match reader_fields
+                        .iter()
+                        .find(|field| field.name == writer_field.name)
+                    {
+                        Some(reader_field) => {
+                            let value = self.read(&writer_field.schema, &reader_field.schema)?;
+                            values.insert(writer_field.name.as_str(), value);
+                        }
+                        // Unknown to the reader
+                        None => self.skip(&writer_field.schema)?,
+                    }
+                }
+                let fields = reader_fields
+                    .iter()
+                    .map(|field| {
+                        let value = match values.remove(field.name.as_str()) {
+                            Some(value) => value,
+                            // Unknown to the writer
+                            None => default_value(field)?,
+                        };
+                        Ok((field.name.clone(), value))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Ok(Value::Record(fields))
+            }
+            (Schema::Enum { symbols, .. }, Schema::Enum { symbols: known, .. }) => {
+                let index = self.read_long()?;
+                let symbol = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| symbols.get(index))
+                    .ok_or_else(|| Error::Data(format!("invalid enum index: {}", index)))?;
+                if !known.contains(symbol) {
+                    return Err(Error::Resolution(format!("unknown symbol {}", symbol)));
+                }
+                Ok(Value::Enum(symbol.clone()))
+            }
+            (Schema::Array(writer_items), Schema::Array(reader_items)) => {
+                let mut values = vec![];
+                loop {
+                    let count = self.read_block_count(writer_items.min_size())?;
+                    if count == 0 {
+                        break;
+                    }
+                    for _ in 0..count {
+                        values.push(self.read(writer_items, reader_items)?);
+                    }
+                }
+                Ok(Value::Array(values))
+            }
+            (Schema::Map(writer_values), Schema::Map(reader_values)) => {
+                let mut values = HashMap::new();
+                loop {
+                    // Keys are strings
+                    let count = self.read_block_count(1 + writer_values.min_size())?;
+                    if count == 0 {
+                        break;
+                    }
+                    for _ in 0..count {
+                        let key = self.read_string()?;
+                        values.insert(key, self.read(writer_values, reader_values)?);
+                    }
+                }
+                Ok(Value::Map(values))
+            }
+            (Schema::Fixed { size, .. }, Schema::Fixed { size: known, .. }) if size == known => {
+                Ok(Value::Fixed(self.take(*size)?.to_vec()))
+            }
+            (writer, reader) => Err(Error::Resolution(format!("{:?} as {:?}", writer, reader))),
+        }
+    }
+
+    fn skip(&mut self, writer: &Schema) -> Result<(), Error> {
+        self.read(writer, writer).map(|_| ())
+    }
+}
+
+// Default of a field missing from the data, given as JSON in the schema
+fn default_value(field: &Field) -> Result<Value, Error> {
+    let default = field
+        .default
+        .as_ref()
+        .ok_or_else(|| Error::Resolution(format!("field {} has no default", field.name)))?;
+    json_value(default, &field.schema)
+        .ok_or_else(|| Error::Schema(format!("invalid default of field {}", field.name)))
+}
+
+fn json_value(json: &serde_json::Value, schema: &Schema) -> Option<Value> {
+    match (schema, json) {
+        (Schema::Null, serde_json::Value::Null) => Some(Value::Null),
+        (Schema::Boolean, serde_json::Value::Bool(value)) => Some(Value::Boolean(*value)),
+        (Schema::Int, json) => json
+            .as_i64()
+            .and_then(|value| i32::try_from(value).ok())
+            .map(Value::Int),
+        (Schema::Long, json) => json.as_i64().map(Value::Long),
+        (Schema::Float, json) => json.as_f64().map(|value| Value::Float(value as f32)),
+        (Schema::Double, json) => json.as_f64().map(Value::Double),
+        (Schema::String, serde_json::Value::String(value)) => Some(Value::String(value.clone())),
+        (Schema::Bytes, serde_json::Value::String(value)) => {
+            Some(Value::Bytes(value.chars().map(|c| c as u8).collect()))
+        }
+        (Schema::Enum { symbols, .. }, serde_json::Value::String(symbol))
+            if symbols.contains(symbol) =>
+        {
+            Some(Value::Enum(symbol.clone()))
+        }
+        (Schema::Record { fields, .. }, serde_json::Value::Object(object)) => fields
+            .iter()
+            .map(|field| {
+                let value = match object.get(&field.name) {
+                    Some(json) => json_value(json, &field.schema)?,
+                    None => default_value(field).ok()?,
+                };
+                Some((field.name.clone(), value))
+            })
+            .collect::<Option<_>>()
+            .map(Value::Record),
+        (Schema::Array(items), serde_json::Value::Array(values)) => values
+            .iter()
+            .map(|value| json_value(value, items))
+            .collect::<Option<_>>()
+            .map(Value::Array),
+        (Schema::Map(values_schema), serde_json::Value::Object(object)) => object
+            .iter()
+            .map(|(key, value)| Some((key.clone(), json_value(value, values_schema)?)))
+            .collect::<Option<_>>()
+            .map(Value::Map),
+        // The default of a union is a value of its first branch
+        (Schema::Union(branches), json) => branches
+            .first()
+            .and_then(|branch| json_value(json, branch))
+            .map(|value| Value::Union(0, Box::new(value))),
+        _ => None,
+    }
+}
+
+// Read data written with the writer schema as the reader schema, all of it
+pub fn decode(bytes: &[u8], writer: &Schema, reader: &Schema) -> Result<Value, Error> {
+    let mut data = Reader { bytes, items: 0 };
+    let value = data.read(writer, reader)?;
+    if !data.bytes.is_empty() {
+        return Err(Error::Data("trailing data".into()));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Error, Schema, Value};
+
+    #[test]
+    fn avro_resolve() {
+        let writer = Schema::parse(
+            r#"{ "type": "record", "name": "R", "fields": [
+                { "name": "a", "type": "int" },
+                { "name": "dropped", "type": { "type": "array", "items": "string" } },
+                { "name": "b", "type": ["null", "string"] }
+            ] }"#,
+        )
+        .unwrap();
+        let reader = Schema::parse(
+            r#"{ "type": "record", "name": "R", "fields": [
+                { "name": "b", "type": ["null", "string"], "default": null },
+                { "name": "a", "type": "long" },
+                { "name": "added", "type": "string", "default": "none" }
+            ] }"#,
+        )
+        .unwrap();
+
+        let value = Value::Record(vec![
+            ("a".into(), Value::Int(-3)),
+            (
+                "dropped".into(),
+                Value::Array(vec![Value::String("x".into())]),
+            ),
+            (
+                "b".into(),
+                Value::Union(1, Box::new(Value::String("y".into()))),
+            ),
+        ]);
+        let mut bytes = vec![];
+        encode(&mut bytes, &value, &writer).unwrap();
+
+        // Fields are matched by name, promoted, skipped or defaulted
+        let value = decode(&bytes, &writer, &reader).unwrap();
+        assert_eq!(value.field("a"), Some(&Value::Long(-3)));
+        assert_eq!(
+            value.field("b").map(Value::unwrap_union),
+            Some(&Value::String("y".into()))
+        );
+        assert_eq!(value.field("added"), Some(&Value::String("none".into())));
+        assert_eq!(value.field("dropped"), None);
+
+        // A field unknown to the writer must have a default
+        let reader = Schema::parse(
+            r#"{ "type": "record", "name": "R", "fields": [{ "name": "c", "type": "string" }] }"#,
+        )
+        .unwrap();
+        match decode(&bytes, &writer, &reader) {
+            Err(Error::Resolution(_)) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn avro_block_count() {
+        // i64::MAX nulls declared in a few bytes
+        let nulls = Schema::parse(r#"{ "type": "array", "items": "null" }"#).unwrap();
+        let bytes = [
+            0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00,
+        ];
+        match decode(&bytes, &nulls, &nulls) {
+            Err(Error::Data(reason)) => assert_eq!(reason, "more than 100000 items"),
+            _ => unreachable!(),
+        }
+
+        // More longs than bytes left
+        let longs = Schema::parse(r#"{ "type": "map", "values": "long" }"#).unwrap();
+        match decode(&[0x80, 0x01, 0x02, 0x61, 0x02, 0x00], &longs, &longs) {
+            Err(Error::Data(reason)) => assert_eq!(reason, "block count beyond the data"),
+            _ => unreachable!(),
+        }
+
+        let value = Value::Array(vec![Value::Null; 3]);
+        let mut bytes = vec![];
+        encode(&mut bytes, &value, &nulls).unwrap();
+        assert_eq!(decode(&bytes, &nulls, &nulls).unwrap(), value);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::stream::consumer::{EventsConsumer, StreamConsumer};
+use crate::stream::events::Events;
+use crate::stream::metrics;
+use crate::stream::producer::{Delivery, Error, StreamProducer};
+use async_channel::{unbounded, Receiver, Sender};
+use async_trait::async_trait;
+use futures::future::{self, Either};
+use futures_timer::Delay;
+use log::warn;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+type Channel = (Sender<Events>, Receiver<Events>);
+
+// In-process channels by name, shared by the producers and consumers of a process
+static CHANNELS: Lazy<Mutex<HashMap<String, Channel>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn channel(name: &str) -> Channel {
+    let mut channels = CHANNELS.lock().unwrap_or_else(|error| error.into_inner());
+    channels
+        .entry(name.into())
+        .or_insert_with(unbounded)
+        .clone()
+}
+
+pub struct ChannelStreamProducer {
+    sender: Sender<Events>,
+    offset: AtomicI64,
+}
+
+impl ChannelStreamProducer {
+    pub fn new(name: &str) -> Self {
+        let (sender, _) = channel(name);
+        ChannelStreamProducer {
+            sender,
+            offset: AtomicI64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl StreamProducer for ChannelStreamProducer {
+    async fn produce(&self, events: &Events) -> Result<Delivery, Error> {
+        // Events are not serialized, they are handed over to the consumer as is
+        let events = events.clone();
+        self.sender
+            .send(events)
+            .await
+            .map_err(|_| Error::Disconnected)?;
+
+        Ok(Delivery {
+            partition: 0,
+            offset: self.offset.fetch_add(1, Ordering::SeqCst),
+        })
+    }
+}
+
+pub struct ChannelStreamConsumer {
+    receiver: Receiver<Events>,
+    events_consumer: Box<dyn EventsConsumer>,
+}
+
+impl ChannelStreamConsumer {
+    pub fn new(name: &str, events_consumer: Box<dyn EventsConsumer>) -> Self {
+        let (_, receiver) = channel(name);
+        ChannelStreamConsumer {
+            receiver,
+            events_consumer,
+        }
+    }
+}
+
+#[async_trait]
+impl StreamConsumer for ChannelStreamConsumer {
+    async fn consume(&self) {
+        // Do not block forever so that the caller gets a chance to stop consuming
+        let timeout = Delay::new(Duration::from_millis(100));
+        if let Either::Left((Ok(events), _)) = future::select(self.receiver.recv(), timeout).await {
+            metrics::consumed("channel");
+            if let Err(error) = self.events_consumer.consume(&events).await {
+                warn!("Error while consuming events: {:?}", error)
+            }
+        }
+    }
+
+    async fn close(&self) {
+        // Events still in the channel would be lost otherwise
+        while let Ok(events) = self.receiver.try_recv() {
+            if let Err(error) = self.events_consumer.consume(&events).await {
+                warn!("Error while consuming events: {:?}", error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelStreamConsumer, ChannelStreamProducer};
+    use crate::stream::consumer::{Error, EventsConsumer, StreamConsumer};
+    use crate::stream::events::{Event, Events};
+    use crate::stream::producer::StreamProducer;
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use std::sync::{Arc, Mutex};
+
+    struct TestEventsConsumer {
+        tokens: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for TestEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), Error> {
+            self.tokens.lock().unwrap().push(events.token.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn channel_stream_produce_and_consume() {
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let producer = ChannelStreamProducer::new("channel_stream_produce_and_consume");
+        let consumer = ChannelStreamConsumer::new(
+            "channel_stream_produce_and_consume",
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        );
+
+        for token in ["token1", "token2"] {
+            let events = Events {
+                token: token.into(),
+                partner_id: "partner".into(),
+                user_id: "user".into(),
+                events: vec![Event {
+                    name: "event".into(),
+                    properties: None,
+                }],
+                request_id: Default::default(),
+                consent_block: None,
+                trace_context: Default::default(),
+            };
+            block_on(producer.produce(&events)).unwrap();
+        }
+
+        block_on(consumer.consume());
+        block_on(consumer.consume());
+        block_on(consumer.consume()); // times out, nothing left
+        assert_eq!(*tokens.lock().unwrap(), vec!["token1", "token2"]);
+    }
+
+    #[test]
+    fn channel_stream_close_drains() {
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let producer = ChannelStreamProducer::new("channel_stream_close_drains");
+        let consumer = ChannelStreamConsumer::new(
+            "channel_stream_close_drains",
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        );
+
+        for token in ["token1", "token2"] {
+            let events = Events {
+                token: token.into(),
+                partner_id: "partner".into(),
+                user_id: "user".into(),
+                events: vec![],
+                request_id: Default::default(),
+                consent_block: None,
+                trace_context: Default::default(),
+            };
+            block_on(producer.produce(&events)).unwrap();
+        }
+
+        block_on(consumer.close());
+        assert_eq!(*tokens.lock().unwrap(), vec!["token1", "token2"]);
+    }
+}
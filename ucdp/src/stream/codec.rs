@@ -0,0 +1,527 @@
+use crate::config::{Config, Validation};
+use crate::stream::avro::{self, Schema, Value};
+use crate::stream::events::{ConsentBlock, Event, Events};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use thiserror::Error;
+
+// Name of the Kafka message header holding the content type of the payload
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("unknown codec: {0}")]
+    UnknownCodec(String),
+
+    #[error("unknown content type: {0}")]
+    UnknownContentType(String),
+
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+
+    #[error("protobuf encoding error")]
+    ProtobufEncode(#[from] prost::EncodeError),
+
+    #[error("protobuf decoding error")]
+    ProtobufDecode(#[from] prost::DecodeError),
+
+    #[error("avro error")]
+    Avro(#[from] avro::Error),
+
+    #[error("unknown avro schema id: {0}")]
+    UnknownSchema(u32),
+}
+
+pub trait Codec: Send + Sync {
+    fn content_type(&self) -> &'static str;
+    fn encode(&self, events: &Events) -> Result<Vec<u8>, Error>;
+    fn decode(&self, payload: &[u8]) -> Result<Events, Error>;
+}
+
+pub struct JsonCodec {}
+
+impl Codec for JsonCodec {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, events: &Events) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(events).map_err(Error::Json)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Events, Error> {
+        serde_json::from_slice(payload).map_err(Error::Json)
+    }
+}
+
+// Protobuf messages matching res/events.proto.
+// Event properties are free-form and carried as JSON strings.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Event {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, optional, tag = "2")]
+        pub properties: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ConsentBlock {
+        #[prost(uint64, tag = "1")]
+        pub number: u64,
+        #[prost(string, tag = "2")]
+        pub hash: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Events {
+        #[prost(string, tag = "1")]
+        pub token: String,
+        #[prost(message, repeated, tag = "2")]
+        pub events: Vec<Event>,
+        #[prost(string, tag = "3")]
+        pub partner_id: String,
+        #[prost(string, tag = "4")]
+        pub user_id: String,
+        #[prost(string, tag = "5")]
+        pub request_id: String,
+        #[prost(message, optional, tag = "6")]
+        pub consent_block: Option<ConsentBlock>,
+    }
+}
+
+fn properties_to_string(properties: &Option<serde_json::Value>) -> Result<Option<String>, Error> {
+    properties
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(Error::Json)
+}
+
+fn properties_from_str(properties: Option<&str>) -> Result<Option<serde_json::Value>, Error> {
+    properties
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(Error::Json)
+}
+
+pub struct ProtobufCodec {}
+
+impl Codec for ProtobufCodec {
+    fn content_type(&self) -> &'static str {
+        "application/x-protobuf"
+    }
+
+    fn encode(&self, events: &Events) -> Result<Vec<u8>, Error> {
+        let message = proto::Events {
+            token: events.token.clone(),
+            partner_id: events.partner_id.clone(),
+            user_id: events.user_id.clone(),
+            request_id: events.request_id.clone(),
+            consent_block: events
+                .consent_block
+                .as_ref()
+                .map(|block| proto::ConsentBlock {
+                    number: block.number,
+                    hash: block.hash.clone(),
+                }),
+            events: events
+                .events
+                .iter()
+                .map(|event| {
+                    Ok(proto::Event {
+                        name: event.name.clone(),
+                        properties: properties_to_string(&event.properties)?,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+        };
+
+        let mut bytes = Vec::with_capacity(prost::Message::encoded_len(&message));
+        prost::Message::encode(&message, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Events, Error> {
+        let message: proto::Events = prost::Message::decode(payload)?;
+        Ok(Events {
+            token: message.token,
+            partner_id: message.partner_id,
+            user_id: message.user_id,
+            events: message
+                .events
+                .into_iter()
+                .map(|event| {
+                    Ok(Event {
+                        name: event.name,
+                        properties: properties_from_str(event.properties.as_deref())?,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+            request_id: message.request_id,
+            consent_block: message.consent_block.map(|block| ConsentBlock {
+                number: block.number,
+                hash: block.hash,
+            }),
+            trace_context: HashMap::new(),
+        })
+    }
+}
+
+// Writer schemas of the Avro payloads. Payloads are written with the latest one.
+pub const EVENTS_AVRO_SCHEMA_V1: &str = include_str!("../../res/events.v1.avsc");
+pub const EVENTS_AVRO_SCHEMA_V2: &str = include_str!("../../res/events.v2.avsc");
+pub const EVENTS_AVRO_SCHEMA_V3: &str = include_str!("../../res/events.v3.avsc");
+pub const EVENTS_AVRO_SCHEMA_V4: &str = include_str!("../../res/events.v4.avsc");
+pub const EVENTS_AVRO_SCHEMA: &str = EVENTS_AVRO_SCHEMA_V4;
+
+// Stand-in for a schema registry: schemas are known in advance and looked up by id.
+// Payloads use the registry wire format: magic byte 0, schema id (u32 big endian), avro data.
+pub struct LocalSchemaRegistry {
+    schemas: HashMap<u32, Schema>,
+}
+
+impl LocalSchemaRegistry {
+    pub fn new() -> Result<Self, Error> {
+        let mut schemas = HashMap::new();
+        for (id, schema) in [
+            (1, EVENTS_AVRO_SCHEMA_V1),
+            (2, EVENTS_AVRO_SCHEMA_V2),
+            (3, EVENTS_AVRO_SCHEMA_V3),
+            (4, EVENTS_AVRO_SCHEMA_V4),
+        ] {
+            schemas.insert(id, Schema::parse(schema)?);
+        }
+        Ok(LocalSchemaRegistry { schemas })
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Schema> {
+        self.schemas.get(&id)
+    }
+
+    pub fn id(&self, schema: &Schema) -> Option<u32> {
+        self.schemas
+            .iter()
+            .find(|(_, s)| *s == schema)
+            .map(|(id, _)| *id)
+    }
+}
+
+fn string_field(value: &Value, name: &str) -> Result<String, Error> {
+    match value.field(name).map(Value::unwrap_union) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        _ => Err(avro::Error::Data(format!("invalid field {}", name)).into()),
+    }
+}
+
+fn events_to_value(events: &Events) -> Result<Value, Error> {
+    let string = |value: &str| Value::String(value.into());
+    Ok(Value::Record(vec![
+        ("token".into(), string(&events.token)),
+        (
+            "events".into(),
+            Value::Array(
+                events
+                    .events
+                    .iter()
+                    .map(|event| {
+                        let properties = match properties_to_string(&event.properties)? {
+                            None => Value::Union(0, Box::new(Value::Null)),
+                            Some(properties) => Value::Union(1, Box::new(string(&properties))),
+                        };
+                        Ok(Value::Record(vec![
+                            ("name".into(), string(&event.name)),
+                            ("properties".into(), properties),
+                        ]))
+                    })
+                    .collect::<Result<_, Error>>()?,
+            ),
+        ),
+        ("partner_id".into(), string(&events.partner_id)),
+        ("user_id".into(), string(&events.user_id)),
+        ("request_id".into(), string(&events.request_id)),
+        (
+            "consent_block".into(),
+            match &events.consent_block {
+                None => Value::Union(0, Box::new(Value::Null)),
+                Some(block) => Value::Union(
+                    1,
+                    Box::new(Value::Record(vec![
+                        ("number".into(), Value::Long(block.number as i64)),
+                        ("hash".into(), string(&block.hash)),
+                    ])),
+                ),
+            },
+        ),
+    ]))
+}
+
+fn events_from_value(value: &Value) -> Result<Events, Error> {
+    let events = match value.field("events") {
+        Some(Value::Array(events)) => events
+            .iter()
+            .map(|event| {
+                let properties = match event.field("properties").map(Value::unwrap_union) {
+                    Some(Value::String(properties)) => Some(properties.as_str()),
+                    _ => None,
+                };
+                Ok(Event {
+                    name: string_field(event, "name")?,
+                    properties: properties_from_str(properties)?,
+                })
+            })
+            .collect::<Result<_, Error>>()?,
+        _ => return Err(avro::Error::Data("invalid field events".into()).into()),
+    };
+    let consent_block = match value.field("consent_block").map(Value::unwrap_union) {
+        Some(block @ Value::Record(_)) => Some(ConsentBlock {
+            number: match block.field("number") {
+                Some(Value::Long(number)) => *number as u64,
+                _ => return Err(avro::Error::Data("invalid field number".into()).into()),
+            },
+            hash: string_field(block, "hash")?,
+        }),
+        _ => None,
+    };
+    Ok(Events {
+        token: string_field(value, "token")?,
+        partner_id: string_field(value, "partner_id")?,
+        user_id: string_field(value, "user_id")?,
+        events,
+        request_id: string_field(value, "request_id")?,
+        consent_block,
+        trace_context: HashMap::new(),
+    })
+}
+
+// Payloads are written with the latest schema, and read as the latest schema
+// whichever registered schema they were written with.
+pub struct AvroCodec {
+    registry: LocalSchemaRegistry,
+    schema: Schema,
+    schema_id: u32,
+}
+
+impl AvroCodec {
+    pub fn new(registry: LocalSchemaRegistry) -> Result<Self, Error> {
+        let schema = Schema::parse(EVENTS_AVRO_SCHEMA)?;
+        let schema_id = registry.id(&schema).ok_or_else(|| {
+            Error::Avro(avro::Error::Schema(
+                "events schema is not registered".into(),
+            ))
+        })?;
+        Ok(AvroCodec {
+            registry,
+            schema,
+            schema_id,
+        })
+    }
+}
+
+impl Codec for AvroCodec {
+    fn content_type(&self) -> &'static str {
+        "avro/binary"
+    }
+
+    fn encode(&self, events: &Events) -> Result<Vec<u8>, Error> {
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&self.schema_id.to_be_bytes());
+        avro::encode(&mut bytes, &events_to_value(events)?, &self.schema)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Events, Error> {
+        if payload.len() < 5 || payload[0] != 0 {
+            return Err(avro::Error::Data("invalid wire format".into()).into());
+        }
+        let schema_id = u32::from_be_bytes(payload[1..5].try_into().unwrap_or_default());
+        let writer = self
+            .registry
+            .get(schema_id)
+            .ok_or(Error::UnknownSchema(schema_id))?;
+        events_from_value(&avro::decode(&payload[5..], writer, &self.schema)?)
+    }
+}
+
+const CODECS: [&str; 3] = ["json", "protobuf", "avro"];
+
+// Codecs decoding consumed messages, built once.
+// They only fail to build on an invalid bundled schema, which the tests cover.
+static DECODERS: Lazy<Vec<Box<dyn Codec>>> = Lazy::new(|| {
+    CODECS
+        .iter()
+        .map(|name| CodecBuilder::build_codec(name).expect("bundled codecs are valid"))
+        .collect()
+});
+
+pub struct CodecBuilder {}
+
+impl CodecBuilder {
+    fn build_codec(name: &str) -> Result<Box<dyn Codec>, Error> {
+        match name {
+            "json" => Ok(Box::new(JsonCodec {})),
+            "protobuf" => Ok(Box::new(ProtobufCodec {})),
+            "avro" => Ok(Box::new(AvroCodec::new(LocalSchemaRegistry::new()?)?)),
+            name => Err(Error::UnknownCodec(name.into())),
+        }
+    }
+
+    // Codec used to encode messages. Defaults to json.
+    pub fn build(config: &Config) -> Result<Box<dyn Codec>, Error> {
+        let name = config
+            .get_str("stream.codec")
+            .unwrap_or_else(|_| "json".into());
+        CodecBuilder::build_codec(name.as_str())
+    }
+
+    pub fn validate(validation: &mut Validation) {
+        validation.one_of("stream.codec", &CODECS, Some("json"));
+    }
+
+    // Codec used to decode messages of the given content type.
+    // Messages without content type predate codecs and are json.
+    pub fn build_for_content_type(content_type: Option<&str>) -> Result<&'static dyn Codec, Error> {
+        let content_type = content_type.unwrap_or("application/json");
+        DECODERS
+            .iter()
+            .find(|codec| codec.content_type() == content_type)
+            .map(|codec| codec.as_ref())
+            .ok_or_else(|| Error::UnknownContentType(content_type.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, CodecBuilder, Error};
+    use crate::config::Config;
+    use crate::stream::events::{ConsentBlock, Event, Events};
+    use serde_json::json;
+
+    fn events() -> Events {
+        Events {
+            token: "token".into(),
+            partner_id: "partner".into(),
+            user_id: "user".into(),
+            events: vec![
+                Event {
+                    name: "event1".into(),
+                    properties: Some(json!({ "price": 10, "currency": "EUR" })),
+                },
+                Event {
+                    name: "event2".into(),
+                    properties: None,
+                },
+            ],
+            request_id: "request".into(),
+            consent_block: Some(ConsentBlock {
+                number: 42,
+                hash: "0xabc".into(),
+            }),
+            trace_context: Default::default(),
+        }
+    }
+
+    fn assert_round_trip(codec: &dyn Codec) {
+        let bytes = codec.encode(&events()).unwrap();
+        let decoded = codec.decode(&bytes).unwrap();
+        assert_eq!(decoded.token, "token");
+        assert_eq!(decoded.partner_id, "partner");
+        assert_eq!(decoded.user_id, "user");
+        assert_eq!(decoded.request_id, "request");
+        assert_eq!(
+            decoded.consent_block,
+            Some(ConsentBlock {
+                number: 42,
+                hash: "0xabc".into(),
+            })
+        );
+        assert_eq!(decoded.events.len(), 2);
+        assert_eq!(decoded.events[0].name, "event1");
+        assert_eq!(
+            decoded.events[0].properties,
+            Some(json!({ "price": 10, "currency": "EUR" }))
+        );
+        assert_eq!(decoded.events[1].name, "event2");
+        assert_eq!(decoded.events[1].properties, None);
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        for name in ["json", "protobuf", "avro"] {
+            let mut config = config::Config::default();
+            let _ = config.set("stream.codec", name);
+            let codec = CodecBuilder::build(&Config::from(config)).unwrap();
+            assert_round_trip(codec.as_ref());
+
+            let codec = CodecBuilder::build_for_content_type(Some(codec.content_type())).unwrap();
+            assert_round_trip(codec);
+        }
+    }
+
+    #[test]
+    fn codec_builder_default_json() {
+        let config = Config::from(config::Config::default());
+        let codec = CodecBuilder::build(&config).unwrap();
+        assert_eq!(codec.content_type(), "application/json");
+
+        let codec = CodecBuilder::build_for_content_type(None).unwrap();
+        assert_eq!(codec.content_type(), "application/json");
+    }
+
+    #[test]
+    fn codec_builder_err_unknown() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.codec", "xml");
+        match CodecBuilder::build(&Config::from(config)) {
+            Err(Error::UnknownCodec(name)) => assert_eq!(name, "xml"),
+            _ => unreachable!(),
+        }
+
+        match CodecBuilder::build_for_content_type(Some("text/xml")) {
+            Err(Error::UnknownContentType(content_type)) => assert_eq!(content_type, "text/xml"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn avro_codec_decode_err_unknown_schema() {
+        let codec = CodecBuilder::build_for_content_type(Some("avro/binary")).unwrap();
+        match codec.decode(&[0, 0, 0, 0, 42, 0, 0]) {
+            Err(Error::UnknownSchema(id)) => assert_eq!(id, 42),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn avro_codec_decode_v1() {
+        let codec = CodecBuilder::build_for_content_type(Some("avro/binary")).unwrap();
+        // token "t", one event named "e" without properties
+        let payload = [0, 0, 0, 0, 1, 2, b't', 2, 2, b'e', 0, 0];
+        let events = codec.decode(&payload).unwrap();
+        assert_eq!(events.token, "t");
+        assert_eq!(events.partner_id, "");
+        assert_eq!(events.user_id, "");
+        assert_eq!(events.events.len(), 1);
+        assert_eq!(events.events[0].name, "e");
+    }
+
+    #[test]
+    fn avro_codec_decode_v2() {
+        let codec = CodecBuilder::build_for_content_type(Some("avro/binary")).unwrap();
+        // token "t", no event, partner "p" and user "u"
+        let payload = [0, 0, 0, 0, 2, 2, b't', 0, 2, b'p', 2, b'u'];
+        let events = codec.decode(&payload).unwrap();
+        assert_eq!(events.partner_id, "p");
+        assert_eq!(events.user_id, "u");
+        assert_eq!(events.request_id, "");
+        assert_eq!(events.consent_block, None);
+    }
+
+    #[test]
+    fn avro_codec_decode_err_truncated() {
+        let codec = CodecBuilder::build_for_content_type(Some("avro/binary")).unwrap();
+        let bytes = codec.encode(&events()).unwrap();
+        assert!(codec.decode(&bytes[..bytes.len() - 3]).is_err());
+    }
+}
@@ -1,96 +1,799 @@
-use crate::config::Config;
+use crate::config::{Config, Validation};
+use crate::stream::channel::ChannelStreamConsumer;
+use crate::stream::codec::{CodecBuilder, CONTENT_TYPE_HEADER};
 use crate::stream::events::Events;
+use crate::stream::file::FileStreamConsumer;
+use crate::stream::metrics;
+use crate::stream::nats::NatsStreamConsumer;
+use crate::stream::redis::RedisStreamConsumer;
+use crate::stream::runtime::RUNTIME;
+use crate::stream::CONNECTORS;
+use crate::telemetry;
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
-use log::{info, warn};
-use rdkafka::consumer::{CommitMode, Consumer};
-use rdkafka::message::Message;
+use futures::future::{self, Either};
+use futures::pin_mut;
+use futures_timer::Delay;
+use isahc::http::StatusCode;
+use log::{debug, error, info, warn};
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt};
+use opentelemetry::KeyValue;
+use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext, Rebalance};
+use rdkafka::message::{BorrowedMessage, Headers, Message};
+use rdkafka::{ClientContext, Offset, Statistics, TopicPartitionList};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Mutex};
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("config error")]
     Config(#[from] crate::config::Error),
 
-    #[error("unknown connector: {0}")]
+    #[error("kafka error")]
     Kafka(#[from] rdkafka::error::KafkaError),
 
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("nats error: {0}")]
+    Nats(String),
+
+    #[error("redis error")]
+    Redis(#[from] redis::RedisError),
+
+    #[error("unknown connector: {0}")]
+    UnknownConnector(String),
+
+    #[error("unknown processing order: {0}")]
+    UnknownProcessingOrder(String),
+
     #[error("deserialization error")]
     Deserialization,
+
+    #[error("destination error: {0}")]
+    Destination(String),
+
+    #[error("events rejected by the destination: {0}")]
+    Rejected(String),
+
+    #[error("lock error")]
+    Lock,
+}
+
+impl Error {
+    // Delivering the same events again would fail the same way
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Error::Rejected(_))
+    }
 }
 
 #[async_trait]
 pub trait StreamConsumer: Send + Sync {
     async fn consume(&self);
-}
 
-struct KafkaStreamConsumer {
-    pub kafka_consumer: rdkafka::consumer::StreamConsumer,
-    pub events_consumer: Box<dyn EventsConsumer>,
+    // Called once consuming has stopped, to consume what has already been received and save progress
+    async fn close(&self) {}
 }
 
 #[async_trait]
 pub trait EventsConsumer: Send + Sync {
-    async fn consume(&self, events: &Events);
+    // An error means that the events have not been consumed.
+    // Kafka, NATS and Redis deliver them again, the other streams log the error and move on.
+    // Kafka drops them after stream.kafka.max_attempts, or at once when rejected by the destination.
+    async fn consume(&self, events: &Events) -> Result<(), Error>;
 }
 
-struct DebugEventsConsumer {}
+#[async_trait]
+impl<T: EventsConsumer + ?Sized> EventsConsumer for Arc<T> {
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        (**self).consume(events).await
+    }
+}
+
+pub struct DebugEventsConsumer {}
 
 #[async_trait]
 impl EventsConsumer for DebugEventsConsumer {
-    async fn consume(&self, events: &Events) {
-        info!("{:?}", events)
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        info!(request_id = events.request_id.as_str(); "{:?}", events);
+        Ok(())
     }
 }
 
 pub struct DestinationEventsConsumer {
     pub destination_endpoint: String,
+    // Sent with every request, such as an authorization token
+    pub headers: HashMap<String, String>,
 }
 
 #[async_trait]
 impl EventsConsumer for DestinationEventsConsumer {
-    async fn consume(&self, events: &Events) {
-        let response = isahc::post(
-            self.destination_endpoint.as_str(),
-            serde_json::to_string(&events).unwrap_or_default(),
-        )
-        .unwrap();
-        info!("{:?}", response);
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        let request = self
+            .headers
+            .iter()
+            .fold(
+                isahc::Request::post(self.destination_endpoint.as_str()),
+                |request, (name, value)| request.header(name.as_str(), value.as_str()),
+            )
+            .body(serde_json::to_string(&events).unwrap_or_default())
+            .map_err(|error| Error::Destination(error.to_string()))?;
+        let response = isahc::send_async(request)
+            .await
+            .map_err(|error| Error::Destination(error.to_string()))?;
+        debug!(request_id = events.request_id.as_str(); "{:?}", response);
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error()
+            && status != StatusCode::REQUEST_TIMEOUT
+            && status != StatusCode::TOO_MANY_REQUESTS
+        {
+            Err(Error::Rejected(format!("unexpected status: {}", status)))
+        } else {
+            Err(Error::Destination(format!(
+                "unexpected status: {}",
+                response.status()
+            )))
+        }
     }
 }
 
+// Times and traces the deliveries to the destination
+pub struct MeteredEventsConsumer {
+    pub destination: String,
+    pub events_consumer: Box<dyn EventsConsumer>,
+}
+
 #[async_trait]
-impl StreamConsumer for KafkaStreamConsumer {
-    async fn consume(&self) {
-        match self.kafka_consumer.recv().await {
-            Err(_) => {}
-            Ok(message) => {
-                match message.payload_view::<[u8]>() {
-                    Some(Ok(payload)) => match serde_json::from_slice::<Events>(payload) {
-                        Ok(events) => self.events_consumer.consume(&events).await,
-                        Err(error) => {
-                            warn!("Error while deserializing message payload: {:?}", error)
-                        }
-                    },
-                    Some(Err(error)) => {
-                        warn!("Error while fetching message payload: {:?}", error);
+impl EventsConsumer for MeteredEventsConsumer {
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        let cx = telemetry::span(
+            format!("{} deliver", self.destination),
+            SpanKind::Client,
+            &telemetry::extract(&events.trace_context),
+        );
+
+        let start = Instant::now();
+        let res = self
+            .events_consumer
+            .consume(events)
+            .with_context(cx.clone())
+            .await;
+        metrics::observe_delivery(&self.destination, start, res.is_ok());
+        match &res {
+            Ok(()) => info!(
+                request_id = events.request_id.as_str(),
+                token = events.token.as_str(),
+                destination = self.destination.as_str(),
+                duration_ms = start.elapsed().as_millis() as u64;
+                "Events delivered"
+            ),
+            Err(error) => {
+                warn!(
+                    request_id = events.request_id.as_str(),
+                    token = events.token.as_str(),
+                    destination = self.destination.as_str();
+                    "Error while delivering events: {:?}", error
+                );
+                cx.span().set_status(Status::error(error.to_string()));
+            }
+        }
+        res
+    }
+}
+
+// Destination routing rebuilt when the configuration changes.
+// Events being delivered complete with the destination they started with.
+pub struct ReloadableEventsConsumer {
+    events_consumer: ArcSwap<MeteredEventsConsumer>,
+}
+
+impl ReloadableEventsConsumer {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        Ok(ReloadableEventsConsumer {
+            events_consumer: ArcSwap::from_pointee(StreamConsumerBuilder::build_events_consumer(
+                config,
+            )?),
+        })
+    }
+
+    // The current destination is kept when the new one cannot be built
+    pub fn reload(&self, config: &Config) -> Result<(), Error> {
+        let events_consumer = StreamConsumerBuilder::build_events_consumer(config)?;
+        self.events_consumer.store(Arc::new(events_consumer));
+        Ok(())
+    }
+
+    pub fn destination(&self) -> String {
+        self.events_consumer.load().destination.clone()
+    }
+}
+
+#[async_trait]
+impl EventsConsumer for ReloadableEventsConsumer {
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        let events_consumer = self.events_consumer.load_full();
+        events_consumer.consume(events).await
+    }
+}
+
+// Content type of the payload, if the message has been produced with one
+fn content_type<M: Message>(message: &M) -> Option<&str> {
+    let headers = message.headers()?;
+    (0..headers.count())
+        .filter_map(|index| headers.get(index))
+        .find(|(name, _)| *name == CONTENT_TYPE_HEADER)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok())
+}
+
+// Headers with a text value, trace context headers among them
+fn text_headers<M: Message>(message: &M) -> HashMap<String, String> {
+    message
+        .headers()
+        .map(|headers| {
+            (0..headers.count())
+                .filter_map(|index| headers.get(index))
+                .filter_map(|(name, value)| {
+                    Some((name.into(), std::str::from_utf8(value).ok()?.into()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Order in which the messages are processed when processed concurrently
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProcessingOrder {
+    // One message at a time per partition, partitions are processed concurrently
+    Partition,
+    // Any message of any partition
+    Unordered,
+}
+
+const PROCESSING_ORDERS: [&str; 2] = ["partition", "unordered"];
+
+impl std::str::FromStr for ProcessingOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "partition" => Ok(ProcessingOrder::Partition),
+            "unordered" => Ok(ProcessingOrder::Unordered),
+            unknown => Err(Error::UnknownProcessingOrder(unknown.into())),
+        }
+    }
+}
+
+// Offsets of the messages of a partition being processed.
+// Messages complete in any order but offsets are committed in order so that none is skipped.
+#[derive(Default)]
+struct PartitionOffsets {
+    // Whether the message at an offset has been processed
+    offsets: BTreeMap<i64, bool>,
+}
+
+impl PartitionOffsets {
+    fn start(&mut self, offset: i64) {
+        self.offsets.insert(offset, false);
+    }
+
+    // Offset to commit, if completing this message let the partition move forward
+    fn complete(&mut self, offset: i64) -> Option<i64> {
+        if let Some(processed) = self.offsets.get_mut(&offset) {
+            *processed = true;
+        }
+        let mut commit = None;
+        while let Some((&offset, &true)) = self.offsets.iter().next() {
+            self.offsets.remove(&offset);
+            commit = Some(offset + 1);
+        }
+        commit
+    }
+}
+
+// Bumped on rebalance and on close. Messages received before are neither retried nor committed.
+struct Generation(watch::Sender<u64>);
+
+impl Generation {
+    fn new() -> Self {
+        Generation(watch::channel(0).0)
+    }
+
+    fn current(&self) -> u64 {
+        *self.0.borrow()
+    }
+
+    fn next(&self) {
+        self.0.send_modify(|generation| *generation += 1);
+    }
+}
+
+// Reports the consumer lag from the statistics emitted by librdkafka, and the rebalances
+struct KafkaConsumerContext {
+    generation: Arc<Generation>,
+}
+
+impl ClientContext for KafkaConsumerContext {
+    fn stats(&self, statistics: Statistics) {
+        metrics::observe_kafka_statistics(&statistics);
+    }
+}
+
+impl ConsumerContext for KafkaConsumerContext {
+    fn pre_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {
+        info!("Rebalancing partitions: {:?}", rebalance);
+        self.generation.next();
+    }
+}
+
+// Processing of a message is retried until it succeeds or the attempts run out, waiting longer after each failure
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+// About ten minutes of retries
+const DEFAULT_MAX_ATTEMPTS: i64 = 25;
+
+#[derive(Debug, PartialEq)]
+struct Completion {
+    generation: u64,
+    partition: i32,
+    offset: i64,
+    // False when retrying stopped, the message is delivered again then
+    processed: bool,
+}
+
+// Consumers polled outside of a tokio runtime process messages on the shared one
+fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    match Handle::try_current() {
+        Ok(handle) => drop(handle.spawn(future)),
+        Err(_) => drop(RUNTIME.spawn(future)),
+    }
+}
+
+// Do not block forever so that the caller gets a chance to stop consuming
+async fn poll_timeout<F: Future>(future: F) -> Option<F::Output> {
+    pin_mut!(future);
+    match future::select(future, Delay::new(Duration::from_millis(100))).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[derive(Default)]
+struct KafkaStreamConsumerState {
+    // Generation of the partition assignment the state belongs to
+    generation: u64,
+    offsets: HashMap<i32, PartitionOffsets>,
+    // Offset committed last for each partition, every message before it has been processed
+    committed: HashMap<i32, i64>,
+    // Partitions with a message being processed, when processed in partition order
+    busy: HashSet<i32>,
+    // Messages waiting for the previous message of their partition to be processed
+    queued: HashMap<i32, VecDeque<(i64, Events)>>,
+    // Messages being processed or queued
+    outstanding: usize,
+}
+
+struct KafkaStreamConsumer {
+    kafka_consumer: rdkafka::consumer::StreamConsumer<KafkaConsumerContext>,
+    generation: Arc<Generation>,
+    topic: String,
+    events_consumer: Arc<dyn EventsConsumer>,
+    concurrency: usize,
+    // Attempts at processing a message before it is given up on and committed past
+    max_attempts: usize,
+    order: ProcessingOrder,
+    state: Mutex<KafkaStreamConsumerState>,
+    completion_sender: UnboundedSender<Completion>,
+    completion_receiver: Mutex<UnboundedReceiver<Completion>>,
+}
+
+impl KafkaStreamConsumer {
+    fn new(
+        kafka_consumer: rdkafka::consumer::StreamConsumer<KafkaConsumerContext>,
+        generation: Arc<Generation>,
+        topic: String,
+        events_consumer: Arc<dyn EventsConsumer>,
+        concurrency: usize,
+        max_attempts: usize,
+        order: ProcessingOrder,
+    ) -> Self {
+        let (completion_sender, completion_receiver) = unbounded_channel();
+        KafkaStreamConsumer {
+            kafka_consumer,
+            generation,
+            topic,
+            events_consumer,
+            concurrency,
+            max_attempts,
+            order,
+            state: Mutex::new(KafkaStreamConsumerState::default()),
+            completion_sender,
+            completion_receiver: Mutex::new(completion_receiver),
+        }
+    }
+
+    fn decode(message: &BorrowedMessage) -> Option<Events> {
+        match message.payload_view::<[u8]>() {
+            Some(Ok(payload)) => {
+                match CodecBuilder::build_for_content_type(content_type(message))
+                    .and_then(|codec| codec.decode(payload))
+                {
+                    Ok(events) => Some(events),
+                    Err(error) => {
+                        warn!("Error while deserializing message payload: {:?}", error);
+                        None
                     }
-                    None => {
-                        warn!("Error while fetching message payload: Unknown error");
+                }
+            }
+            Some(Err(error)) => {
+                warn!("Error while fetching message payload: {:?}", error);
+                None
+            }
+            None => {
+                warn!("Error while fetching message payload: Unknown error");
+                None
+            }
+        }
+    }
+
+    // Process the events in the background, completion is notified through the channel.
+    // Failures are retried, the offset stays outstanding in the meantime.
+    // Events rejected by the destination or failing every attempt are dropped, so that they do not block the partition.
+    fn dispatch(&self, generation: u64, partition: i32, offset: i64, events: Events) {
+        let events_consumer = self.events_consumer.clone();
+        let completion_sender = self.completion_sender.clone();
+        let mut current_generation = self.generation.0.subscribe();
+        let max_attempts = self.max_attempts;
+        spawn(async move {
+            let mut backoff = RETRY_BACKOFF;
+            let mut attempts = 0;
+            let processed = loop {
+                attempts += 1;
+                match events_consumer.consume(&events).await {
+                    Ok(()) => break true,
+                    Err(error) if error.is_permanent() || attempts >= max_attempts => {
+                        error!(
+                            request_id = events.request_id.as_str(),
+                            token = events.token.as_str(),
+                            partition = partition,
+                            offset = offset;
+                            "Dropping events after {} attempts: {:?}", attempts, error
+                        );
+                        metrics::dropped("kafka");
+                        break true;
                     }
+                    Err(error) => warn!(
+                        "Error while consuming events, retrying in {:?}: {:?}",
+                        backoff, error
+                    ),
+                }
+                // The partition may have been revoked, or consuming stopped
+                if *current_generation.borrow() != generation {
+                    break false;
+                }
+                let changed = current_generation.changed();
+                pin_mut!(changed);
+                if let Either::Left(_) = future::select(changed, Delay::new(backoff)).await {
+                    break false;
+                }
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            };
+            let _ = completion_sender.send(Completion {
+                generation,
+                partition,
+                offset,
+                processed,
+            });
+        });
+    }
+
+    fn start(
+        &self,
+        state: &mut KafkaStreamConsumerState,
+        partition: i32,
+        offset: i64,
+        events: Events,
+    ) {
+        state.outstanding += 1;
+        if self.order == ProcessingOrder::Partition && !state.busy.insert(partition) {
+            state
+                .queued
+                .entry(partition)
+                .or_default()
+                .push_back((offset, events));
+        } else {
+            self.dispatch(state.generation, partition, offset, events);
+        }
+    }
+
+    fn complete(&self, state: &mut KafkaStreamConsumerState, completion: Completion) {
+        // Messages of revoked partitions have been forgotten
+        if completion.generation != state.generation {
+            return;
+        }
+        let Completion {
+            partition, offset, ..
+        } = completion;
+        state.outstanding -= 1;
+        if self.order == ProcessingOrder::Partition {
+            match state
+                .queued
+                .get_mut(&partition)
+                .and_then(|queue| queue.pop_front())
+            {
+                Some((offset, events)) => {
+                    self.dispatch(state.generation, partition, offset, events)
+                }
+                None => {
+                    state.busy.remove(&partition);
+                }
+            }
+        }
+        // An unprocessed offset holds back the commits of the partition, so it is delivered again
+        if completion.processed {
+            self.processed(state, partition, offset);
+        }
+    }
+
+    // After a rebalance the partitions may belong to other consumers, which consume from the
+    // last committed offsets. Messages received before are forgotten.
+    fn rebalanced(&self, state: &mut KafkaStreamConsumerState) {
+        let generation = self.generation.current();
+        if state.generation != generation {
+            *state = KafkaStreamConsumerState {
+                generation,
+                ..Default::default()
+            };
+        }
+    }
+
+    fn processed(&self, state: &mut KafkaStreamConsumerState, partition: i32, offset: i64) {
+        let commit = state.offsets.entry(partition).or_default().complete(offset);
+        if let Some(offset) = commit {
+            state.committed.insert(partition, offset);
+            if let Err(error) = self.commit(&[(partition, offset)], CommitMode::Async) {
+                warn!("Error while committing offset: {:?}", error);
+            }
+        }
+    }
+
+    fn commit(&self, offsets: &[(i32, i64)], mode: CommitMode) -> rdkafka::error::KafkaResult<()> {
+        let mut topic_partition_list = TopicPartitionList::new();
+        for (partition, offset) in offsets {
+            topic_partition_list.add_partition_offset(
+                &self.topic,
+                *partition,
+                Offset::Offset(*offset),
+            )?;
+        }
+        self.kafka_consumer.commit(&topic_partition_list, mode)
+    }
+}
+
+#[async_trait]
+impl StreamConsumer for KafkaStreamConsumer {
+    async fn consume(&self) {
+        let mut state = self.state.lock().await;
+        let mut completion_receiver = self.completion_receiver.lock().await;
+
+        self.rebalanced(&mut state);
+        while let Ok(completion) = completion_receiver.try_recv() {
+            self.complete(&mut state, completion);
+        }
+
+        if state.outstanding >= self.concurrency {
+            if let Some(Some(completion)) = poll_timeout(completion_receiver.recv()).await {
+                self.complete(&mut state, completion);
+            }
+            return;
+        }
+
+        if let Some(Ok(message)) = poll_timeout(self.kafka_consumer.recv()).await {
+            self.rebalanced(&mut state);
+            metrics::consumed("kafka");
+            let (partition, offset) = (message.partition(), message.offset());
+            state.offsets.entry(partition).or_default().start(offset);
+            match KafkaStreamConsumer::decode(&message) {
+                Some(mut events) => {
+                    // The consume span continues the trace of the producer, deliveries are its children
+                    let cx = telemetry::span(
+                        format!("{} receive", self.topic),
+                        SpanKind::Consumer,
+                        &telemetry::extract(&text_headers(&message)),
+                    );
+                    cx.span().set_attributes([
+                        KeyValue::new("messaging.system", "kafka"),
+                        KeyValue::new("messaging.destination.name", self.topic.clone()),
+                        KeyValue::new("messaging.destination.partition.id", partition.to_string()),
+                        KeyValue::new("messaging.kafka.offset", offset),
+                    ]);
+                    events.trace_context = telemetry::inject(&cx);
+                    self.start(&mut state, partition, offset, events)
                 }
+                None => {
+                    metrics::deserialization_failed("kafka");
+                    self.processed(&mut state, partition, offset)
+                }
+            }
+        }
+    }
+
+    async fn close(&self) {
+        let mut state = self.state.lock().await;
+        let mut completion_receiver = self.completion_receiver.lock().await;
+
+        // Wait for the messages being processed, failed ones are not retried anymore
+        self.rebalanced(&mut state);
+        self.generation.next();
+        while state.outstanding > 0 {
+            match completion_receiver.recv().await {
+                Some(completion) => self.complete(&mut state, completion),
+                None => break,
+            }
+        }
 
-                self.kafka_consumer
-                    .commit_message(&message, CommitMode::Async)
-                    .unwrap();
+        // Async commits may still be pending, the offsets processed in order are committed again.
+        // Messages still queued, being retried or abandoned are delivered again.
+        let committed: Vec<(i32, i64)> = state.committed.drain().collect();
+        if !committed.is_empty() {
+            if let Err(error) = self.commit(&committed, CommitMode::Sync) {
+                warn!("Error while committing offsets: {:?}", error);
             }
         }
     }
 }
 
+// Consumer group members must have distinct names, workers may share a configuration
+fn default_consumer_name() -> String {
+    let hostname = hostname::get()
+        .map(|hostname| hostname.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "worker".into());
+    format!("{}-{}", hostname, std::process::id())
+}
+
 pub struct StreamConsumerBuilder {}
 
 impl StreamConsumerBuilder {
     pub fn build(config: &Config) -> Result<Box<dyn StreamConsumer>, Error> {
+        let events_consumer = StreamConsumerBuilder::build_events_consumer(config)?;
+        StreamConsumerBuilder::build_with_events_consumer(config, Box::new(events_consumer))
+    }
+
+    // Consume the stream into the given events consumer, such as a reloadable one
+    pub fn build_with_events_consumer(
+        config: &Config,
+        events_consumer: Box<dyn EventsConsumer>,
+    ) -> Result<Box<dyn StreamConsumer>, Error> {
+        match config.get_str("stream.connector")?.as_str() {
+            "kafka" => StreamConsumerBuilder::build_kafka(config, events_consumer),
+            "nats" => {
+                let stream_consumer = NatsStreamConsumer::new(
+                    config.get_str("stream.nats.url")?.as_str(),
+                    config.get_str("stream.nats.stream")?.as_str(),
+                    config.get_str("stream.nats.subject")?.as_str(),
+                    config
+                        .get_str("stream.nats.consumer")
+                        .unwrap_or_else(|_| "workers".into())
+                        .as_str(),
+                    events_consumer,
+                )?;
+                Ok(Box::new(stream_consumer))
+            }
+            "redis" => {
+                let reclaim_idle = match config.get_duration("stream.redis.reclaim_idle") {
+                    Ok(reclaim_idle) => reclaim_idle,
+                    Err(error) if error.is_not_found() => Duration::from_secs(60),
+                    Err(error) => return Err(Error::Config(error)),
+                };
+                let stream_consumer = RedisStreamConsumer::new(
+                    config.get_url("stream.redis.url")?.as_str(),
+                    config.get_str("stream.redis.key")?.as_str(),
+                    config
+                        .get_str("stream.redis.group")
+                        .unwrap_or_else(|_| "workers".into())
+                        .as_str(),
+                    config
+                        .get_str("stream.redis.consumer")
+                        .unwrap_or_else(|_| default_consumer_name())
+                        .as_str(),
+                    reclaim_idle,
+                    events_consumer,
+                )?;
+                Ok(Box::new(stream_consumer))
+            }
+            "channel" => {
+                let name = config.get_str("stream.channel.name")?;
+                let stream_consumer = ChannelStreamConsumer::new(name.as_str(), events_consumer);
+                Ok(Box::new(stream_consumer))
+            }
+            "file" => {
+                let path = config.get_str("stream.file.path")?;
+                let stream_consumer = FileStreamConsumer::new(path.as_str(), events_consumer)?;
+                Ok(Box::new(stream_consumer))
+            }
+            connector => Err(Error::UnknownConnector(connector.into())),
+        }
+    }
+
+    pub fn validate(validation: &mut Validation) {
+        if let Some("http") = validation
+            .one_of("destination.connector", &["http", "debug"], Some("http"))
+            .as_deref()
+        {
+            validation.optional("destination.endpoint", Config::get_url);
+            validation.optional("destination.headers", Config::get_flat_table);
+        }
+
+        match validation
+            .one_of("stream.connector", &CONNECTORS, None)
+            .as_deref()
+        {
+            Some("kafka") => {
+                validation.required("stream.kafka.broker", Config::get_str);
+                validation.required("stream.kafka.topic", Config::get_str);
+                validation.optional("stream.kafka.consumer", Config::get_flat_table);
+                validation.optional("stream.kafka.concurrency", Config::get_int);
+                validation.optional("stream.kafka.max_attempts", Config::get_int);
+                validation.one_of("stream.kafka.order", &PROCESSING_ORDERS, Some("partition"));
+            }
+            Some("nats") => {
+                validation.required("stream.nats.url", Config::get_str);
+                validation.required("stream.nats.stream", Config::get_str);
+                validation.required("stream.nats.subject", Config::get_str);
+                validation.optional("stream.nats.consumer", Config::get_str);
+            }
+            Some("redis") => {
+                validation.required("stream.redis.url", Config::get_url);
+                validation.required("stream.redis.key", Config::get_str);
+                validation.optional("stream.redis.group", Config::get_str);
+                validation.optional("stream.redis.consumer", Config::get_str);
+                validation.optional("stream.redis.reclaim_idle", Config::get_duration);
+            }
+            Some("channel") => {
+                validation.required("stream.channel.name", Config::get_str);
+            }
+            Some("file") => {
+                validation.required("stream.file.path", Config::get_str);
+            }
+            _ => (),
+        }
+    }
+
+    // Events are posted to the destination endpoint, or logged with the debug connector
+    fn build_events_consumer(config: &Config) -> Result<MeteredEventsConsumer, Error> {
+        let destination = config
+            .get_str("destination.connector")
+            .unwrap_or_else(|_| "http".into());
+        let events_consumer: Box<dyn EventsConsumer> = match destination.as_str() {
+            "http" => Box::new(DestinationEventsConsumer {
+                destination_endpoint: config
+                    .get_str("destination.endpoint")
+                    .unwrap_or_else(|_| "https://httpbin.org/post".into()),
+                headers: config
+                    .get_flat_table("destination.headers")
+                    .unwrap_or_default(),
+            }),
+            "debug" => Box::new(DebugEventsConsumer {}),
+            connector => return Err(Error::UnknownConnector(connector.into())),
+        };
+        Ok(MeteredEventsConsumer {
+            destination,
+            events_consumer,
+        })
+    }
+
+    fn build_kafka(
+        config: &Config,
+        events_consumer: Box<dyn EventsConsumer>,
+    ) -> Result<Box<dyn StreamConsumer>, Error> {
         let kafka_broker = config
             .get_str("stream.kafka.broker")
             .map_err(Error::Config)?;
@@ -98,23 +801,55 @@ impl StreamConsumerBuilder {
             .get_str("stream.kafka.topic")
             .map_err(Error::Config)?;
 
-        let kafka_consumer: rdkafka::consumer::StreamConsumer =
-            rdkafka::config::ClientConfig::new()
-                .set("group.id", "workers")
-                .set("bootstrap.servers", kafka_broker)
-                .create()
-                .map_err(Error::Kafka)?;
+        let mut client_config = rdkafka::config::ClientConfig::new();
+        client_config
+            .set("group.id", "workers")
+            .set("bootstrap.servers", kafka_broker)
+            // Offsets are committed once messages are processed, never ahead of time.
+            // Nor are they stored as received, which a commit of the consumer state would commit.
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            // Statistics carry the consumer lag
+            .set("statistics.interval.ms", "5000");
+        // Pass through any librdkafka consumer property (group.id, sasl.*...)
+        for (key, value) in config
+            .get_flat_table("stream.kafka.consumer")
+            .unwrap_or_default()
+        {
+            client_config.set(key, value);
+        }
+
+        let generation = Arc::new(Generation::new());
+        let context = KafkaConsumerContext {
+            generation: generation.clone(),
+        };
+        let kafka_consumer: rdkafka::consumer::StreamConsumer<KafkaConsumerContext> = client_config
+            .create_with_context(context)
+            .map_err(Error::Kafka)?;
 
         kafka_consumer
             .subscribe(&[kafka_topic.as_str()])
             .map_err(Error::Kafka)?;
 
-        let stream_consumer = KafkaStreamConsumer {
+        // Messages processed at the same time, one by default
+        let concurrency = config.get_int("stream.kafka.concurrency").unwrap_or(1);
+        let max_attempts = config
+            .get_int("stream.kafka.max_attempts")
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+        let order = config
+            .get_str("stream.kafka.order")
+            .unwrap_or_else(|_| "partition".into())
+            .parse::<ProcessingOrder>()?;
+
+        let stream_consumer = KafkaStreamConsumer::new(
             kafka_consumer,
-            events_consumer: Box::new(DestinationEventsConsumer {
-                destination_endpoint: "https://httpbin.org/post".into(),
-            }),
-        };
+            generation,
+            kafka_topic,
+            Arc::from(events_consumer),
+            concurrency.max(1) as usize,
+            max_attempts.max(1) as usize,
+            order,
+        );
 
         Ok(Box::new(stream_consumer))
     }
@@ -122,19 +857,548 @@ impl StreamConsumerBuilder {
 
 #[cfg(test)]
 mod tests {
-    use crate::stream::consumer::{Config, StreamConsumerBuilder};
+    use super::{
+        Completion, EventsConsumer, Generation, KafkaStreamConsumer, MeteredEventsConsumer,
+        PartitionOffsets, ProcessingOrder, ReloadableEventsConsumer, StreamConsumer,
+    };
+    use crate::config::Validation;
+    use crate::stream::consumer::{Config, Error, StreamConsumerBuilder};
+    use crate::stream::events::Events;
+    use crate::stream::metrics;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct TestEventsConsumer {
+        tokens: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for TestEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), Error> {
+            self.tokens.lock().unwrap().push(events.token.clone());
+            Ok(())
+        }
+    }
+
+    // Fails the first attempts of each token
+    struct FailingEventsConsumer {
+        tokens: Arc<std::sync::Mutex<Vec<String>>>,
+        failures: usize,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for FailingEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), Error> {
+            let mut tokens = self.tokens.lock().unwrap();
+            tokens.push(events.token.clone());
+            if tokens
+                .iter()
+                .filter(|token| **token == events.token)
+                .count()
+                > self.failures
+            {
+                Ok(())
+            } else {
+                Err(Error::Destination("failed attempt".into()))
+            }
+        }
+    }
+
+    struct RejectingEventsConsumer {
+        tokens: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for RejectingEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), Error> {
+            self.tokens.lock().unwrap().push(events.token.clone());
+            Err(Error::Rejected("unexpected status: 400 Bad Request".into()))
+        }
+    }
+
+    fn kafka_stream_consumer_with(
+        order: ProcessingOrder,
+        events_consumer: Arc<dyn EventsConsumer>,
+    ) -> KafkaStreamConsumer {
+        let generation = Arc::new(Generation::new());
+        let kafka_consumer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", "0.0.0.0:0000")
+            .set("group.id", "group")
+            .create_with_context(super::KafkaConsumerContext {
+                generation: generation.clone(),
+            })
+            .unwrap();
+        KafkaStreamConsumer::new(
+            kafka_consumer,
+            generation,
+            "topic".into(),
+            events_consumer,
+            2,
+            usize::MAX,
+            order,
+        )
+    }
+
+    fn kafka_stream_consumer(
+        order: ProcessingOrder,
+        tokens: Arc<std::sync::Mutex<Vec<String>>>,
+    ) -> KafkaStreamConsumer {
+        kafka_stream_consumer_with(order, Arc::new(TestEventsConsumer { tokens }))
+    }
+
+    fn completion(partition: i32, offset: i64) -> Completion {
+        Completion {
+            generation: 0,
+            partition,
+            offset,
+            processed: true,
+        }
+    }
+
+    fn events(token: &str) -> Events {
+        Events {
+            token: token.into(),
+            partner_id: "partner".into(),
+            user_id: "user".into(),
+            events: vec![],
+            request_id: Default::default(),
+            consent_block: None,
+            trace_context: Default::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn metered_events_consumer() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = MeteredEventsConsumer {
+            destination: "metered_events_consumer".into(),
+            events_consumer: Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        };
+
+        assert!(consumer.consume(&events("token")).await.is_ok());
+        assert_eq!(tokens.lock().unwrap().len(), 1);
+        let labels = ["metered_events_consumer"];
+        assert_eq!(
+            metrics::DELIVERY_DURATION
+                .with_label_values(&labels)
+                .get_sample_count(),
+            1
+        );
+        assert_eq!(metrics::DELIVERY_ERRORS.with_label_values(&labels).get(), 0);
+    }
 
     #[test]
-    fn stream_consumer_builder_ok() {
+    fn partition_offsets_commit_in_order() {
+        let mut offsets = PartitionOffsets::default();
+        offsets.start(10);
+        offsets.start(11);
+        offsets.start(12);
+
+        assert_eq!(offsets.complete(11), None);
+        assert_eq!(offsets.complete(12), None);
+        assert_eq!(offsets.complete(10), Some(13));
+
+        offsets.start(13);
+        assert_eq!(offsets.complete(13), Some(14));
+    }
+
+    #[test]
+    fn processing_order_from_str() {
+        assert_eq!(
+            "partition".parse::<ProcessingOrder>().unwrap(),
+            ProcessingOrder::Partition
+        );
+        assert_eq!(
+            "unordered".parse::<ProcessingOrder>().unwrap(),
+            ProcessingOrder::Unordered
+        );
+        match "unknown".parse::<ProcessingOrder>() {
+            Err(Error::UnknownProcessingOrder(order)) => assert_eq!(order, "unknown"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_partition_order() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer(ProcessingOrder::Partition, tokens.clone());
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        for (partition, offset) in [(0, 0), (0, 1), (1, 0)] {
+            let token = format!("{}/{}", partition, offset);
+            consumer.start(&mut state, partition, offset, events(&token));
+        }
+        assert_eq!(state.outstanding, 3);
+        assert_eq!(state.queued[&0].len(), 1);
+
+        // The second message of partition 0 waits for the first one
+        let mut completions = vec![];
+        for _ in 0..2 {
+            completions.push(completion_receiver.recv().await.unwrap());
+        }
+        completions.sort_unstable_by_key(|completion| completion.partition);
+        assert_eq!(completions, vec![completion(0, 0), completion(1, 0)]);
+        for completion in completions {
+            consumer.complete(&mut state, completion);
+        }
+
+        assert_eq!(completion_receiver.recv().await.unwrap(), completion(0, 1));
+        consumer.complete(&mut state, completion(0, 1));
+        assert_eq!(state.outstanding, 0);
+        assert!(state.busy.is_empty());
+
+        let tokens = tokens.lock().unwrap();
+        let position = |token: &str| tokens.iter().position(|t| t == token).unwrap();
+        assert!(position("0/0") < position("0/1"));
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_unordered() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer(ProcessingOrder::Unordered, tokens.clone());
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        for (partition, offset) in [(0, 0), (0, 1), (1, 0)] {
+            let token = format!("{}/{}", partition, offset);
+            consumer.start(&mut state, partition, offset, events(&token));
+        }
+        assert!(state.queued.is_empty());
+
+        for _ in 0..3 {
+            let completion = completion_receiver.recv().await.unwrap();
+            consumer.complete(&mut state, completion);
+        }
+        assert_eq!(state.outstanding, 0);
+        assert_eq!(tokens.lock().unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_retry() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer_with(
+            ProcessingOrder::Partition,
+            Arc::new(FailingEventsConsumer {
+                tokens: tokens.clone(),
+                failures: 2,
+            }),
+        );
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        consumer.start(&mut state, 0, 0, events("token"));
+        assert_eq!(completion_receiver.recv().await.unwrap(), completion(0, 0));
+        assert_eq!(tokens.lock().unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_max_attempts() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut consumer = kafka_stream_consumer_with(
+            ProcessingOrder::Partition,
+            Arc::new(FailingEventsConsumer {
+                tokens: tokens.clone(),
+                failures: usize::MAX,
+            }),
+        );
+        consumer.max_attempts = 3;
+        let dropped = metrics::EVENTS_DROPPED.with_label_values(&["kafka"]);
+        let before = dropped.get();
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        // Given up on and committed past once the attempts run out
+        consumer.start(&mut state, 0, 0, events("token"));
+        assert_eq!(completion_receiver.recv().await.unwrap(), completion(0, 0));
+        assert_eq!(tokens.lock().unwrap().len(), 3);
+        assert!(dropped.get() > before);
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_rejected() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer_with(
+            ProcessingOrder::Partition,
+            Arc::new(RejectingEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        );
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        // Not retried
+        consumer.start(&mut state, 0, 0, events("token"));
+        assert_eq!(completion_receiver.recv().await.unwrap(), completion(0, 0));
+        assert_eq!(tokens.lock().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_stop_retrying() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer_with(
+            ProcessingOrder::Unordered,
+            Arc::new(FailingEventsConsumer {
+                tokens: tokens.clone(),
+                failures: usize::MAX,
+            }),
+        );
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        consumer.start(&mut state, 0, 0, events("token"));
+        consumer.start(&mut state, 0, 1, events("other"));
+        state.offsets.entry(0).or_default().start(0);
+        state.offsets.entry(0).or_default().start(1);
+
+        // Closing stops the retries, the failed message is not committed
+        consumer.generation.next();
+        for _ in 0..2 {
+            let completion = completion_receiver.recv().await.unwrap();
+            assert!(!completion.processed);
+            consumer.complete(&mut state, completion);
+        }
+        assert_eq!(state.outstanding, 0);
+        assert_eq!(state.offsets[&0].offsets.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_close_in_flight() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = Arc::new(kafka_stream_consumer_with(
+            ProcessingOrder::Unordered,
+            Arc::new(FailingEventsConsumer {
+                tokens: tokens.clone(),
+                failures: usize::MAX,
+            }),
+        ));
+        {
+            let mut state = consumer.state.lock().await;
+            for offset in 0..2 {
+                state.offsets.entry(0).or_default().start(offset);
+            }
+            // The next message is processed, the one being retried holds back its commit
+            consumer.start(&mut state, 0, 0, events("token"));
+            consumer.processed(&mut state, 0, 1);
+            assert!(state.committed.is_empty());
+        }
+
+        // Shutdown while it is in flight, nothing past it is committed
+        consumer.close().await;
+        let state = consumer.state.lock().await;
+        assert_eq!(state.outstanding, 0);
+        assert!(state.committed.is_empty());
+        assert_eq!(state.offsets[&0].offsets.len(), 2);
+        assert!(!tokens.lock().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn kafka_stream_consumer_rebalanced() {
+        let tokens = Arc::new(std::sync::Mutex::new(vec![]));
+        let consumer = kafka_stream_consumer(ProcessingOrder::Partition, tokens.clone());
+        let mut state = consumer.state.lock().await;
+        let mut completion_receiver = consumer.completion_receiver.lock().await;
+
+        for offset in 0..2 {
+            state.offsets.entry(0).or_default().start(offset);
+            consumer.start(&mut state, 0, offset, events("token"));
+        }
+        assert_eq!(state.queued[&0].len(), 1);
+
+        // Messages received before the rebalance are forgotten, their completions ignored
+        consumer.generation.next();
+        consumer.rebalanced(&mut state);
+        assert_eq!(state.generation, 1);
+        assert_eq!(state.outstanding, 0);
+        assert!(state.offsets.is_empty());
+        assert!(state.busy.is_empty());
+        assert!(state.queued.is_empty());
+
+        consumer.complete(&mut state, completion_receiver.recv().await.unwrap());
+        assert_eq!(state.outstanding, 0);
+        assert!(state.offsets.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn reloadable_events_consumer() {
+        let config = |destination: &str| {
+            let mut config = config::Config::default();
+            let _ = config.set("destination.connector", destination);
+            Config::from(config)
+        };
+
+        let events_consumer = ReloadableEventsConsumer::new(&config("debug")).unwrap();
+        assert!(events_consumer.consume(&events("token")).await.is_ok());
+
+        assert!(events_consumer.reload(&config("http")).is_ok());
+        assert_eq!(events_consumer.destination(), "http");
+
+        // The current destination is kept
+        match events_consumer.reload(&config("unknown")) {
+            Err(Error::UnknownConnector(connector)) => assert_eq!(connector, "unknown"),
+            _ => unreachable!(),
+        }
+        assert_eq!(events_consumer.destination(), "http");
+    }
+
+    #[test]
+    fn stream_consumer_builder_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "redis");
+        let _ = config.set("stream.redis.url", "not a url");
+        let _ = config.set("stream.redis.key", "events");
+        let _ = config.set("stream.redis.reclaim_idle", "soon");
+        let _ = config.set("destination.connector", "debug");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        StreamConsumerBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(crate::config::Error::Validation(problems)) => {
+                assert_eq!(problems.len(), 2);
+                assert!(problems[0].starts_with("stream.redis.url: "));
+                assert!(problems[1].starts_with("stream.redis.reclaim_idle: "));
+            }
+            _ => unreachable!(),
+        }
+
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "channel");
+        let _ = config.set("stream.channel.name", "events");
+        let config = Config::from(config);
+        let mut validation = Validation::new(&config);
+        StreamConsumerBuilder::validate(&mut validation);
+        assert!(validation.finish().is_ok());
+    }
+
+    // Build needs no key that validate does not report missing
+    #[actix_rt::test]
+    async fn stream_consumer_builder_validate_required_keys() {
+        let path = std::env::temp_dir().join("ucdp-stream-consumer-required-keys");
+        let _ = std::fs::File::create(&path);
+        for connector in ["kafka", "channel", "file"] {
+            let mut config = config::Config::default();
+            let _ = config.set("stream.connector", connector);
+            let _ = config.set("destination.connector", "debug");
+
+            let validated = Config::from(config.clone());
+            let mut validation = Validation::new(&validated);
+            StreamConsumerBuilder::validate(&mut validation);
+            if let Err(crate::config::Error::Validation(problems)) = validation.finish() {
+                for problem in problems {
+                    let key = problem.strip_suffix(": missing").unwrap();
+                    let value = match key.ends_with(".path") {
+                        true => path.to_str().unwrap(),
+                        false => key,
+                    };
+                    let _ = config.set(key, value);
+                }
+            }
+            assert!(StreamConsumerBuilder::build(&Config::from(config)).is_ok());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn stream_consumer_builder_err_negative_reclaim_idle() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "redis");
+        let _ = config.set("stream.redis.url", "redis://127.0.0.1:1");
+        let _ = config.set("stream.redis.key", "events");
+        let _ = config.set("stream.redis.reclaim_idle", -1);
+        let _ = config.set("destination.connector", "debug");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        StreamConsumerBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(crate::config::Error::Validation(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["stream.redis.reclaim_idle: negative duration"]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        match StreamConsumerBuilder::build(&config) {
+            Err(Error::Config(crate::config::Error::Invalid(key, _))) => {
+                assert_eq!(key, "stream.redis.reclaim_idle")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stream_consumer_builder_ok() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let config = Config::from(config);
+
+        let res = StreamConsumerBuilder::build(&config);
+        assert!(res.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn stream_consumer_builder_ok_consumer_config() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.consumer.group.id", "group");
+        let _ = config.set("stream.kafka.consumer.auto.offset.reset", "earliest");
+        let config = Config::from(config);
+
+        let res = StreamConsumerBuilder::build(&config);
+        assert!(res.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn stream_consumer_builder_ok_concurrency() {
         let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
         let _ = config.set("stream.kafka.topic", "topic");
         let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.concurrency", 8);
+        let _ = config.set("stream.kafka.order", "unordered");
         let config = Config::from(config);
 
         let res = StreamConsumerBuilder::build(&config);
         assert!(res.is_ok());
     }
 
+    #[actix_rt::test]
+    async fn stream_consumer_builder_err_order() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.order", "unknown");
+        let config = Config::from(config);
+
+        match StreamConsumerBuilder::build(&config) {
+            Err(Error::UnknownProcessingOrder(order)) => assert_eq!(order, "unknown"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn stream_consumer_builder_err_consumer_config() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.consumer.not.a.property", "value");
+        let config = Config::from(config);
+
+        let res = StreamConsumerBuilder::build(&config);
+        assert!(res.is_err());
+    }
+
     #[test]
     fn stream_consumer_builder_err() {
         let config = config::Config::default();
@@ -143,4 +1407,50 @@ mod tests {
         let res = StreamConsumerBuilder::build(&config);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn stream_consumer_builder_ok_connectors() {
+        let path = std::env::temp_dir().join("ucdp-stream-consumer-builder");
+        let _ = std::fs::File::create(&path);
+        let mut config = config::Config::default();
+        let _ = config.set(
+            "stream.channel.name",
+            "stream_consumer_builder_ok_connectors",
+        );
+        let _ = config.set("stream.file.path", path.to_str().unwrap());
+
+        for connector in ["channel", "file"] {
+            let _ = config.set("stream.connector", connector);
+            let config = Config::from(config.clone());
+            let res = StreamConsumerBuilder::build(&config);
+            assert!(res.is_ok());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn stream_consumer_builder_err_unknown_destination_connector() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "channel");
+        let _ = config.set("stream.channel.name", "unknown_destination_connector");
+        let _ = config.set("destination.connector", "unknown");
+        let config = Config::from(config);
+
+        match StreamConsumerBuilder::build(&config) {
+            Err(Error::UnknownConnector(connector)) => assert_eq!(connector, "unknown"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn stream_consumer_builder_err_unknown_connector() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "unknown");
+        let config = Config::from(config);
+
+        match StreamConsumerBuilder::build(&config) {
+            Err(Error::UnknownConnector(connector)) => assert_eq!(connector, "unknown"),
+            _ => unreachable!(),
+        }
+    }
 }
@@ -1,12 +1,35 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Event {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Block of the chain consent was checked at, to prove delivered events against chain state
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConsentBlock {
+    pub number: u64,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Events {
     pub token: String,
+    #[serde(default)]
+    pub partner_id: String,
+    #[serde(default)]
+    pub user_id: String,
     pub events: Vec<Event>,
+    // Id of the gateway request the events were received with, to correlate logs
+    #[serde(default)]
+    pub request_id: String,
+    // None when consent is not checked on a blockchain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent_block: Option<ConsentBlock>,
+    // W3C trace context, carried in the message headers rather than in the payload
+    #[serde(skip)]
+    pub trace_context: HashMap<String, String>,
 }
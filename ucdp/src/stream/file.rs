@@ -0,0 +1,169 @@
+use crate::stream::codec::{Codec, JsonCodec};
+use crate::stream::consumer::{EventsConsumer, StreamConsumer};
+use crate::stream::events::Events;
+use crate::stream::metrics;
+use crate::stream::producer::{Delivery, Error, StreamProducer};
+use async_trait::async_trait;
+use futures_timer::Delay;
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Events are appended to the file as json lines so that they can be replayed later on
+pub struct FileStreamProducer {
+    file: Mutex<File>,
+    codec: JsonCodec,
+}
+
+impl FileStreamProducer {
+    pub fn new(path: &str) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileStreamProducer {
+            file: Mutex::new(file),
+            codec: JsonCodec {},
+        })
+    }
+}
+
+#[async_trait]
+impl StreamProducer for FileStreamProducer {
+    async fn produce(&self, events: &Events) -> Result<Delivery, Error> {
+        let mut line = self.codec.encode(events)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().map_err(|_| Error::Lock)?;
+        let offset = file.metadata()?.len() as i64;
+        file.write_all(&line)?;
+
+        Ok(Delivery {
+            partition: 0,
+            offset,
+        })
+    }
+}
+
+// Replay events from the beginning of the file, then wait for new ones
+pub struct FileStreamConsumer {
+    reader: Mutex<BufReader<File>>,
+    codec: JsonCodec,
+    events_consumer: Box<dyn EventsConsumer>,
+}
+
+impl FileStreamConsumer {
+    pub fn new(
+        path: &str,
+        events_consumer: Box<dyn EventsConsumer>,
+    ) -> Result<Self, std::io::Error> {
+        let file = File::open(path)?;
+        Ok(FileStreamConsumer {
+            reader: Mutex::new(BufReader::new(file)),
+            codec: JsonCodec {},
+            events_consumer,
+        })
+    }
+
+    fn read_line(&self) -> Option<String> {
+        let mut reader = self.reader.lock().ok()?;
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) if !line.ends_with('\n') => {
+                // Partially written line, read it again once complete
+                let _ = reader.seek_relative(-(line.len() as i64));
+                None
+            }
+            Ok(_) => Some(line),
+            Err(error) => {
+                warn!("Error while reading stream file: {:?}", error);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StreamConsumer for FileStreamConsumer {
+    async fn consume(&self) {
+        match self.read_line() {
+            Some(line) => {
+                metrics::consumed("file");
+                match self.codec.decode(line.trim_end().as_bytes()) {
+                    Ok(events) => {
+                        if let Err(error) = self.events_consumer.consume(&events).await {
+                            warn!("Error while consuming events: {:?}", error)
+                        }
+                    }
+                    Err(error) => {
+                        metrics::deserialization_failed("file");
+                        warn!("Error while deserializing stream file line: {:?}", error)
+                    }
+                }
+            }
+            None => Delay::new(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileStreamConsumer, FileStreamProducer};
+    use crate::stream::consumer::{Error, EventsConsumer, StreamConsumer};
+    use crate::stream::events::Events;
+    use crate::stream::producer::StreamProducer;
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use std::sync::{Arc, Mutex};
+
+    struct TestEventsConsumer {
+        tokens: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for TestEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), Error> {
+            self.tokens.lock().unwrap().push(events.token.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn file_stream_produce_and_replay() {
+        let path = std::env::temp_dir().join(format!("ucdp-file-stream-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let producer = FileStreamProducer::new(path).unwrap();
+        let mut offsets = vec![];
+        for token in ["token1", "token2"] {
+            let events = Events {
+                token: token.into(),
+                partner_id: "partner".into(),
+                user_id: "user".into(),
+                events: vec![],
+                request_id: Default::default(),
+                consent_block: None,
+                trace_context: Default::default(),
+            };
+            offsets.push(block_on(producer.produce(&events)).unwrap().offset);
+        }
+        assert_eq!(offsets[0], 0);
+        assert!(offsets[1] > 0);
+
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let consumer = FileStreamConsumer::new(
+            path,
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        )
+        .unwrap();
+        for _ in 0..3 {
+            block_on(consumer.consume());
+        }
+        assert_eq!(*tokens.lock().unwrap(), vec!["token1", "token2"]);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,120 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use rdkafka::Statistics;
+use std::time::Instant;
+
+pub static MESSAGES_CONSUMED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "workers_messages_consumed_total",
+        "Messages received from the stream by connector",
+        &["connector"]
+    )
+    .unwrap()
+});
+
+pub static DESERIALIZATION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "workers_deserialization_failures_total",
+        "Messages that could not be decoded by connector",
+        &["connector"]
+    )
+    .unwrap()
+});
+
+pub static DELIVERY_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "workers_delivery_duration_seconds",
+        "Events delivery latency by destination",
+        &["destination"]
+    )
+    .unwrap()
+});
+
+pub static DELIVERY_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "workers_delivery_errors_total",
+        "Events that could not be delivered by destination",
+        &["destination"]
+    )
+    .unwrap()
+});
+
+pub static EVENTS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "workers_events_dropped_total",
+        "Events given up on after failing to be delivered by connector",
+        &["connector"]
+    )
+    .unwrap()
+});
+
+// Only known for kafka, from the statistics reported by librdkafka
+pub static CONSUMER_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "workers_consumer_lag",
+        "Messages not consumed yet by topic and partition",
+        &["topic", "partition"]
+    )
+    .unwrap()
+});
+
+pub fn consumed(connector: &str) {
+    MESSAGES_CONSUMED.with_label_values(&[connector]).inc();
+}
+
+pub fn deserialization_failed(connector: &str) {
+    DESERIALIZATION_FAILURES
+        .with_label_values(&[connector])
+        .inc();
+}
+
+pub fn dropped(connector: &str) {
+    EVENTS_DROPPED.with_label_values(&[connector]).inc();
+}
+
+pub fn observe_delivery(destination: &str, start: Instant, delivered: bool) {
+    DELIVERY_DURATION
+        .with_label_values(&[destination])
+        .observe(start.elapsed().as_secs_f64());
+    if !delivered {
+        DELIVERY_ERRORS.with_label_values(&[destination]).inc();
+    }
+}
+
+pub fn observe_kafka_statistics(statistics: &Statistics) {
+    for (topic, stats) in &statistics.topics {
+        for (partition, stats) in &stats.partitions {
+            // Internal partition -1 and lag -1 when unknown (partition not assigned yet)
+            if *partition >= 0 && stats.consumer_lag >= 0 {
+                CONSUMER_LAG
+                    .with_label_values(&[topic, partition.to_string().as_str()])
+                    .set(stats.consumer_lag);
+            }
+        }
+    }
+}
+
+// Metrics of the default registry in the Prometheus text format
+pub fn encode() -> Result<(String, String), prometheus::Error> {
+    let encoder = TextEncoder::new();
+    let body = encoder.encode_to_string(&prometheus::gather())?;
+    Ok((encoder.format_type().into(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{observe_delivery, DELIVERY_ERRORS};
+    use std::time::Instant;
+
+    #[test]
+    fn metrics_observe_delivery() {
+        let errors = DELIVERY_ERRORS.with_label_values(&["metrics_observe_delivery"]);
+        observe_delivery("metrics_observe_delivery", Instant::now(), true);
+        assert_eq!(errors.get(), 0);
+        observe_delivery("metrics_observe_delivery", Instant::now(), false);
+        assert_eq!(errors.get(), 1);
+    }
+}
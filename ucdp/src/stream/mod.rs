@@ -1,3 +1,14 @@
+pub mod avro;
+pub mod channel;
+pub mod codec;
 pub mod consumer;
 pub mod events;
+pub mod file;
+pub mod metrics;
+pub mod nats;
 pub mod producer;
+pub mod redis;
+mod runtime;
+
+// Streams events can be produced to and consumed from
+pub(crate) const CONNECTORS: [&str; 5] = ["kafka", "nats", "redis", "channel", "file"];
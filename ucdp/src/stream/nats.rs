@@ -0,0 +1,416 @@
+use crate::stream::codec::{Codec, CodecBuilder, CONTENT_TYPE_HEADER};
+use crate::stream::consumer::{self, EventsConsumer, StreamConsumer};
+use crate::stream::events::Events;
+use crate::stream::metrics;
+use crate::stream::producer::{self, Delivery, PartitionKey, StreamProducer};
+use crate::stream::runtime::RUNTIME;
+use async_nats::jetstream::consumer::pull;
+use async_nats::jetstream::{self, AckKind};
+use async_nats::HeaderMap;
+use async_trait::async_trait;
+use futures::executor::block_on;
+use futures::StreamExt;
+use log::warn;
+use std::time::Duration;
+
+// The stream is created on first use, capturing every subject under `{subject}.`
+async fn jetstream(
+    url: String,
+    stream: String,
+    subject: String,
+) -> Result<(jetstream::Context, jetstream::stream::Stream), String> {
+    let client = async_nats::connect(url)
+        .await
+        .map_err(|error| error.to_string())?;
+    let context = jetstream::new(client);
+    let stream = context
+        .get_or_create_stream(jetstream::stream::Config {
+            name: stream,
+            subjects: vec![format!("{}.>", subject)],
+            ..Default::default()
+        })
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok((context, stream))
+}
+
+// Subject tokens cannot be empty nor contain dots, wildcards or whitespaces
+fn subject_token(key: &str) -> String {
+    let token: String = key
+        .chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect();
+    if token.is_empty() {
+        "_".into()
+    } else {
+        token
+    }
+}
+
+// Events are published to `{subject}.{partition key}` so that consumers can filter on them
+pub struct NatsStreamProducer {
+    context: jetstream::Context,
+    subject: String,
+    codec: Box<dyn Codec>,
+    partition_key: PartitionKey,
+}
+
+impl NatsStreamProducer {
+    pub fn new(
+        url: &str,
+        stream: &str,
+        subject: &str,
+        codec: Box<dyn Codec>,
+        partition_key: PartitionKey,
+    ) -> Result<Self, producer::Error> {
+        let (context, _) =
+            block_on(RUNTIME.spawn(jetstream(url.into(), stream.into(), subject.into())))
+                .map_err(|error| producer::Error::Nats(error.to_string()))?
+                .map_err(producer::Error::Nats)?;
+
+        Ok(NatsStreamProducer {
+            context,
+            subject: subject.into(),
+            codec,
+            partition_key,
+        })
+    }
+}
+
+#[async_trait]
+impl StreamProducer for NatsStreamProducer {
+    async fn produce(&self, events: &Events) -> Result<Delivery, producer::Error> {
+        let payload = self.codec.encode(events)?;
+        let subject = format!(
+            "{}.{}",
+            self.subject,
+            subject_token(&self.partition_key.key(events))
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE_HEADER, self.codec.content_type());
+
+        let context = self.context.clone();
+        let ack = RUNTIME
+            .spawn(async move {
+                // Wait for the stream to acknowledge the message
+                context
+                    .publish_with_headers(subject, headers, payload.into())
+                    .await
+                    .map_err(|error| error.to_string())?
+                    .await
+                    .map_err(|error| error.to_string())
+            })
+            .await
+            .map_err(|error| producer::Error::Nats(error.to_string()))?
+            .map_err(producer::Error::Nats)?;
+
+        // JetStream streams are not partitioned, the sequence orders all the messages
+        Ok(Delivery {
+            partition: 0,
+            offset: ack.sequence as i64,
+        })
+    }
+}
+
+// Durable pull consumer, messages are acked once consumed, nacked to be redelivered otherwise
+pub struct NatsStreamConsumer {
+    consumer: jetstream::consumer::Consumer<pull::Config>,
+    events_consumer: Box<dyn EventsConsumer>,
+}
+
+impl NatsStreamConsumer {
+    pub fn new(
+        url: &str,
+        stream: &str,
+        subject: &str,
+        name: &str,
+        events_consumer: Box<dyn EventsConsumer>,
+    ) -> Result<Self, consumer::Error> {
+        let (url, stream, subject, name) = (
+            url.to_string(),
+            stream.to_string(),
+            subject.to_string(),
+            name.to_string(),
+        );
+        let consumer = block_on(RUNTIME.spawn(async move {
+            let (_, stream) = jetstream(url, stream, subject.clone()).await?;
+            stream
+                .get_or_create_consumer(
+                    &name,
+                    pull::Config {
+                        durable_name: Some(name.clone()),
+                        ack_policy: jetstream::consumer::AckPolicy::Explicit,
+                        filter_subject: format!("{}.>", subject),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(|error| error.to_string())
+        }))
+        .map_err(|error| consumer::Error::Nats(error.to_string()))?
+        .map_err(consumer::Error::Nats)?;
+
+        Ok(NatsStreamConsumer {
+            consumer,
+            events_consumer,
+        })
+    }
+
+    async fn fetch(&self) -> Result<Option<jetstream::Message>, consumer::Error> {
+        let pull_consumer = self.consumer.clone();
+        RUNTIME
+            .spawn(async move {
+                // Do not block forever so that the caller gets a chance to stop consuming
+                let mut messages = pull_consumer
+                    .fetch()
+                    .max_messages(1)
+                    .expires(Duration::from_millis(100))
+                    .messages()
+                    .await
+                    .map_err(|error| error.to_string())?;
+                messages
+                    .next()
+                    .await
+                    .transpose()
+                    .map_err(|error| error.to_string())
+            })
+            .await
+            .map_err(|error| consumer::Error::Nats(error.to_string()))?
+            .map_err(consumer::Error::Nats)
+    }
+
+    async fn ack(&self, message: jetstream::Message, kind: AckKind) {
+        let res = RUNTIME
+            .spawn(async move { message.ack_with(kind).await.map_err(|e| e.to_string()) })
+            .await;
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => warn!("Error while acking message: {}", error),
+            Err(error) => warn!("Error while acking message: {}", error),
+        }
+    }
+}
+
+#[async_trait]
+impl StreamConsumer for NatsStreamConsumer {
+    async fn consume(&self) {
+        let message = match self.fetch().await {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(error) => {
+                warn!("Error while fetching message: {:?}", error);
+                return;
+            }
+        };
+
+        metrics::consumed("nats");
+        let content_type = message
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get(CONTENT_TYPE_HEADER))
+            .map(|value| value.as_str());
+        let kind = match CodecBuilder::build_for_content_type(content_type)
+            .and_then(|codec| codec.decode(&message.payload))
+        {
+            Ok(events) => match self.events_consumer.consume(&events).await {
+                Ok(()) => AckKind::Ack,
+                Err(error) => {
+                    warn!("Error while consuming events: {:?}", error);
+                    AckKind::Nak(None)
+                }
+            },
+            Err(error) => {
+                // Redelivering a message that cannot be decoded would not help
+                metrics::deserialization_failed("nats");
+                warn!("Error while deserializing message payload: {:?}", error);
+                AckKind::Term
+            }
+        };
+
+        self.ack(message, kind).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{subject_token, NatsStreamConsumer, NatsStreamProducer};
+    use crate::stream::codec::JsonCodec;
+    use crate::stream::consumer::{self, EventsConsumer, StreamConsumer};
+    use crate::stream::events::Events;
+    use crate::stream::producer::{self, PartitionKey, StreamProducer};
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::process::{Child, Command};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    // Fails the first time it sees a token so that the message gets redelivered
+    struct TestEventsConsumer {
+        tokens: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for TestEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), consumer::Error> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let failed = !tokens.contains(&events.token);
+            tokens.push(events.token.clone());
+            if failed {
+                Err(consumer::Error::Destination("first attempt".into()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    struct NatsServer {
+        process: Child,
+        store: PathBuf,
+    }
+
+    impl NatsServer {
+        // None when nats-server is not in PATH
+        fn launch(port: u16) -> Option<NatsServer> {
+            let store = std::env::temp_dir().join(format!("ucdp-nats-{}", port));
+            let process = Command::new("nats-server")
+                .args(["-js", "-p", &port.to_string(), "-sd"])
+                .arg(&store)
+                .spawn()
+                .ok()?;
+            while TcpStream::connect(("127.0.0.1", port)).is_err() {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Some(NatsServer { process, store })
+        }
+    }
+
+    impl Drop for NatsServer {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+            let _ = std::fs::remove_dir_all(&self.store);
+        }
+    }
+
+    fn events(token: &str) -> Events {
+        Events {
+            token: token.into(),
+            partner_id: "partner".into(),
+            user_id: "0x0000000000000000000000000000000000000001".into(),
+            events: vec![],
+            request_id: Default::default(),
+            consent_block: None,
+            trace_context: Default::default(),
+        }
+    }
+
+    #[test]
+    fn nats_subject_token() {
+        assert_eq!(subject_token("partner/user"), "partner/user");
+        assert_eq!(subject_token("a.b*c>d e"), "a_b_c_d_e");
+        assert_eq!(subject_token(""), "_");
+    }
+
+    #[test]
+    fn nats_stream_producer_err_connect() {
+        match NatsStreamProducer::new(
+            "127.0.0.1:1",
+            "EVENTS",
+            "events",
+            Box::new(JsonCodec {}),
+            PartitionKey::User,
+        ) {
+            Err(producer::Error::Nats(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nats_stream_consumer_err_connect() {
+        let tokens = Arc::new(Mutex::new(vec![]));
+        match NatsStreamConsumer::new(
+            "127.0.0.1:1",
+            "EVENTS",
+            "events",
+            "workers",
+            Box::new(TestEventsConsumer { tokens }),
+        ) {
+            Err(consumer::Error::Nats(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nats_stream_produce_and_consume() {
+        let _server = match NatsServer::launch(14222) {
+            Some(server) => server,
+            None => return eprintln!("nats-server is not in PATH, skipping"),
+        };
+        let url = "127.0.0.1:14222";
+
+        let producer = NatsStreamProducer::new(
+            url,
+            "EVENTS",
+            "events",
+            Box::new(JsonCodec {}),
+            PartitionKey::User,
+        )
+        .unwrap();
+        let mut offsets = vec![];
+        for token in ["token1", "token2"] {
+            offsets.push(block_on(producer.produce(&events(token))).unwrap().offset);
+        }
+        assert_eq!(offsets, vec![1, 2]);
+
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let consumer = NatsStreamConsumer::new(
+            url,
+            "EVENTS",
+            "events",
+            "workers",
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        )
+        .unwrap();
+        // Each message is nacked once then acked
+        for _ in 0..50 {
+            if tokens.lock().unwrap().len() == 4 {
+                break;
+            }
+            block_on(consumer.consume());
+        }
+        let mut consumed = tokens.lock().unwrap().clone();
+        consumed.sort();
+        assert_eq!(consumed, vec!["token1", "token1", "token2", "token2"]);
+        drop(consumer);
+
+        // The durable consumer resumes after the acked messages
+        block_on(producer.produce(&events("token3"))).unwrap();
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let consumer = NatsStreamConsumer::new(
+            url,
+            "EVENTS",
+            "events",
+            "workers",
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        )
+        .unwrap();
+        for _ in 0..50 {
+            if tokens.lock().unwrap().len() == 2 {
+                break;
+            }
+            block_on(consumer.consume());
+        }
+        assert_eq!(*tokens.lock().unwrap(), vec!["token3", "token3"]);
+    }
+}
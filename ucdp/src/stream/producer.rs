@@ -1,93 +1,383 @@
-use crate::config::Config;
+use crate::config::{Config, Validation};
+use crate::shutdown;
+use crate::stream::channel::ChannelStreamProducer;
+use crate::stream::codec::{Codec, CodecBuilder, CONTENT_TYPE_HEADER};
 use crate::stream::events::Events;
+use crate::stream::file::FileStreamProducer;
+use crate::stream::nats::NatsStreamProducer;
+use crate::stream::redis::RedisStreamProducer;
+use crate::stream::CONNECTORS;
+use crate::telemetry;
 use async_trait::async_trait;
-use crossbeam_channel::select;
+use crossbeam_channel::TryRecvError;
 use futures::executor::block_on;
-use rdkafka::producer::FutureRecord;
-use std::thread;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::task::Poll;
+use log::{trace, warn};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt};
+use opentelemetry::KeyValue;
+use rdkafka::message::OwnedHeaders;
+use rdkafka::producer::{FutureRecord, Producer};
 use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("config error")]
+    Config(#[from] crate::config::Error),
+
+    #[error("codec error")]
+    Codec(#[from] crate::stream::codec::Error),
+
+    #[error("kafka error")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("nats error: {0}")]
+    Nats(String),
+
+    #[error("redis error")]
+    Redis(#[from] redis::RedisError),
+
+    #[error("unknown connector: {0}")]
+    UnknownConnector(String),
+
+    #[error("unknown partition key: {0}")]
+    UnknownPartitionKey(String),
+
+    #[error("channel disconnected")]
+    Disconnected,
+
+    #[error("lock error")]
+    Lock,
+}
+
+// Where a batch of events has been written to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub partition: i32,
+    pub offset: i64,
+}
 
 #[async_trait]
 pub trait StreamProducer: Send + Sync {
-    async fn produce(&self, events: &Events);
+    async fn produce(&self, events: &Events) -> Result<Delivery, Error>;
+
+    // Wait for the buffered events to be written
+    async fn flush(&self, _timeout: Duration) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Deliveries awaited at the same time.
+    // More than one only when events are written in order as soon as produce is first polled.
+    fn max_in_flight(&self) -> usize {
+        1
+    }
+}
+
+// Events sharing the same key are written to the same partition, in order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartitionKey {
+    Token,
+    User,
+    Partner,
+    PartnerUser,
+}
+
+impl PartitionKey {
+    pub fn key(&self, events: &Events) -> String {
+        match self {
+            PartitionKey::Token => events.token.clone(),
+            PartitionKey::User => events.user_id.clone(),
+            PartitionKey::Partner => events.partner_id.clone(),
+            PartitionKey::PartnerUser => format!("{}/{}", events.partner_id, events.user_id),
+        }
+    }
+}
+
+const PARTITION_KEYS: [&str; 4] = ["token", "user", "partner", "partner-user"];
+
+impl std::str::FromStr for PartitionKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "token" => Ok(PartitionKey::Token),
+            "user" => Ok(PartitionKey::User),
+            "partner" => Ok(PartitionKey::Partner),
+            "partner-user" => Ok(PartitionKey::PartnerUser),
+            unknown => Err(Error::UnknownPartitionKey(unknown.into())),
+        }
+    }
 }
 
 pub struct KafkaStreamProducer {
     pub topic: String,
     pub producer: rdkafka::producer::FutureProducer,
+    pub codec: Box<dyn Codec>,
+    pub partition_key: PartitionKey,
 }
 
 #[async_trait]
 impl StreamProducer for KafkaStreamProducer {
-    async fn produce(&self, events: &Events) {
-        thread::sleep(Duration::from_secs(3));
-        println!("{}", events.token);
+    async fn produce(&self, events: &Events) -> Result<Delivery, Error> {
+        let payload = self.codec.encode(events)?;
 
-        let _ = self
+        let cx = telemetry::span(
+            format!("{} publish", self.topic),
+            SpanKind::Producer,
+            &telemetry::extract(&events.trace_context),
+        );
+        cx.span().set_attributes([
+            KeyValue::new("messaging.system", "kafka"),
+            KeyValue::new("messaging.destination.name", self.topic.clone()),
+        ]);
+
+        // W3C trace context headers let the consumers continue the trace
+        let mut headers = OwnedHeaders::new().add(CONTENT_TYPE_HEADER, self.codec.content_type());
+        for (name, value) in telemetry::inject(&cx) {
+            headers = headers.add(&name, &value);
+        }
+
+        let res = self
             .producer
             .send(
                 FutureRecord::to(&self.topic)
-                    .payload(&serde_json::to_string(&events).unwrap_or_default())
-                    .key(&events.token),
+                    .payload(&payload)
+                    .key(&self.partition_key.key(events))
+                    .headers(headers),
                 Duration::from_secs(0),
             )
-            .await;
+            .await
+            .map(|(partition, offset)| Delivery { partition, offset })
+            .map_err(|(error, _)| Error::Kafka(error));
+        if let Err(error) = &res {
+            cx.span().set_status(Status::error(error.to_string()));
+        }
+        res
+    }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), Error> {
+        self.producer.flush(timeout);
+        Ok(())
+    }
+
+    // Records are queued by librdkafka when sent, batches fill up while deliveries are awaited
+    fn max_in_flight(&self) -> usize {
+        KAFKA_MAX_IN_FLIGHT
     }
 }
 
-pub fn spawn_stream_producer_thread(receiver: crossbeam_channel::Receiver<Events>) {
-    let config = Config::new(String::from("config/Main"));
-    let stream_producer = StreamProducerBuilder::build(&config).unwrap();
-    thread::spawn(|| block_on(stream_producer_loop(stream_producer, receiver)));
+const KAFKA_MAX_IN_FLIGHT: usize = 1000;
+
+// The thread finishes once all the senders are dropped and the events left in the channel produced
+pub fn spawn_stream_producer_thread(
+    config: &Config,
+    receiver: crossbeam_channel::Receiver<Events>,
+) -> Result<shutdown::Worker, Error> {
+    let stream_producer = StreamProducerBuilder::build(config)?;
+    let drain_timeout = shutdown::drain_timeout(config);
+    Ok(shutdown::spawn(move || {
+        block_on(stream_producer_loop(
+            stream_producer,
+            receiver,
+            drain_timeout,
+        ))
+    }))
+}
+
+fn delivered(events: &Events, res: Result<Delivery, Error>) {
+    match res {
+        Ok(delivery) => trace!(
+            request_id = events.request_id.as_str(),
+            token = events.token.as_str(),
+            partition = delivery.partition,
+            offset = delivery.offset;
+            "Events produced"
+        ),
+        Err(error) => warn!(
+            request_id = events.request_id.as_str(),
+            token = events.token.as_str();
+            "Error while producing events: {:?}", error
+        ),
+    }
 }
 
 async fn stream_producer_loop(
     stream_producer: Box<dyn StreamProducer>,
     receiver: crossbeam_channel::Receiver<Events>,
+    flush_timeout: Duration,
 ) {
+    let stream_producer = &*stream_producer;
+    let max_in_flight = stream_producer.max_in_flight().max(1);
+    let mut in_flight = FuturesUnordered::new();
     loop {
-        select! {
-            recv(receiver) -> res => match res {
-                Ok(events) => {
-                    stream_producer.produce(&events).await
+        // Wait for the events only when no delivery is in flight
+        let events = match receiver.try_recv() {
+            Ok(events) => events,
+            Err(TryRecvError::Empty) if !in_flight.is_empty() => {
+                if let Some((events, res)) = in_flight.next().await {
+                    delivered(&events, res);
                 }
-                Err(_) => return
+                continue;
+            }
+            Err(TryRecvError::Empty) => match receiver.recv() {
+                Ok(events) => events,
+                Err(_) => break,
+            },
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        let mut delivery = Box::pin(async move {
+            let res = stream_producer.produce(&events).await;
+            (events, res)
+        });
+        // Polled once right away so that the events are written in the order they are received
+        match futures::poll!(&mut delivery) {
+            Poll::Ready((events, res)) => delivered(&events, res),
+            Poll::Pending => in_flight.push(delivery),
+        }
+        if in_flight.len() >= max_in_flight {
+            if let Some((events, res)) = in_flight.next().await {
+                delivered(&events, res);
             }
         }
     }
-}
 
-#[derive(Debug)]
-pub struct Error {}
+    while let Some((events, res)) = in_flight.next().await {
+        delivered(&events, res);
+    }
+    if let Err(error) = stream_producer.flush(flush_timeout).await {
+        warn!("Error while flushing stream producer: {:?}", error);
+    }
+}
 
 pub struct StreamProducerBuilder {}
 
 impl StreamProducerBuilder {
     pub fn build(config: &Config) -> Result<Box<dyn StreamProducer>, Error> {
+        match config.get_str("stream.connector")?.as_str() {
+            "kafka" => StreamProducerBuilder::build_kafka(config),
+            "nats" => StreamProducerBuilder::build_nats(config),
+            "redis" => {
+                // Keep the stream from growing forever unless told otherwise
+                let maxlen = match config.get_int("stream.redis.maxlen") {
+                    Ok(maxlen) if maxlen > 0 => Some(maxlen as usize),
+                    _ => None,
+                };
+                let stream_producer = RedisStreamProducer::new(
+                    config.get_url("stream.redis.url")?.as_str(),
+                    config.get_str("stream.redis.key")?.as_str(),
+                    maxlen,
+                    CodecBuilder::build(config)?,
+                )?;
+                Ok(Box::new(stream_producer))
+            }
+            "channel" => {
+                let name = config.get_str("stream.channel.name")?;
+                Ok(Box::new(ChannelStreamProducer::new(name.as_str())))
+            }
+            "file" => {
+                let path = config.get_str("stream.file.path")?;
+                Ok(Box::new(FileStreamProducer::new(path.as_str())?))
+            }
+            connector => Err(Error::UnknownConnector(connector.into())),
+        }
+    }
+
+    pub fn validate(validation: &mut Validation) {
+        CodecBuilder::validate(validation);
+        match validation
+            .one_of("stream.connector", &CONNECTORS, None)
+            .as_deref()
+        {
+            Some("kafka") => {
+                validation.required("stream.kafka.broker", Config::get_str);
+                validation.required("stream.kafka.topic", Config::get_str);
+                validation.one_of("stream.kafka.key", &PARTITION_KEYS, Some("user"));
+                validation.optional("stream.kafka.producer", Config::get_flat_table);
+            }
+            Some("nats") => {
+                validation.required("stream.nats.url", Config::get_str);
+                validation.required("stream.nats.stream", Config::get_str);
+                validation.required("stream.nats.subject", Config::get_str);
+                validation.one_of("stream.nats.key", &PARTITION_KEYS, Some("user"));
+            }
+            Some("redis") => {
+                validation.required("stream.redis.url", Config::get_url);
+                validation.required("stream.redis.key", Config::get_str);
+                validation.optional("stream.redis.maxlen", Config::get_int);
+            }
+            Some("channel") => {
+                validation.required("stream.channel.name", Config::get_str);
+            }
+            Some("file") => {
+                validation.required("stream.file.path", Config::get_str);
+            }
+            _ => (),
+        }
+    }
+
+    fn build_kafka(config: &Config) -> Result<Box<dyn StreamProducer>, Error> {
+        let mut client_config = rdkafka::config::ClientConfig::new();
+        client_config.set("bootstrap.servers", config.get_str("stream.kafka.broker")?);
+        // Pass through any librdkafka producer property (linger.ms, compression.type, sasl.*...)
+        for (key, value) in config
+            .get_flat_table("stream.kafka.producer")
+            .unwrap_or_default()
+        {
+            client_config.set(key, value);
+        }
+
+        // Default to user so that the events of a user are consumed in order
+        let partition_key = config
+            .get_str("stream.kafka.key")
+            .unwrap_or_else(|_| "user".into())
+            .parse::<PartitionKey>()?;
+
         let stream_producer = KafkaStreamProducer {
-            topic: config.get_str("stream.kafka.topic").map_err(|_| Error {})?,
-            producer: rdkafka::config::ClientConfig::new()
-                .set(
-                    "bootstrap.servers",
-                    config
-                        .get_str("stream.kafka.broker")
-                        .map_err(|_| Error {})?,
-                )
-                .create()
-                .map_err(|_| Error {})?,
+            topic: config.get_str("stream.kafka.topic")?,
+            producer: client_config.create()?,
+            codec: CodecBuilder::build(config)?,
+            partition_key,
         };
 
         Ok(Box::new(stream_producer))
     }
+
+    fn build_nats(config: &Config) -> Result<Box<dyn StreamProducer>, Error> {
+        let partition_key = config
+            .get_str("stream.nats.key")
+            .unwrap_or_else(|_| "user".into())
+            .parse::<PartitionKey>()?;
+
+        let stream_producer = NatsStreamProducer::new(
+            config.get_str("stream.nats.url")?.as_str(),
+            config.get_str("stream.nats.stream")?.as_str(),
+            config.get_str("stream.nats.subject")?.as_str(),
+            CodecBuilder::build(config)?,
+            partition_key,
+        )?;
+
+        Ok(Box::new(stream_producer))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{async_trait, block_on, stream_producer_loop};
-    use crate::config::Config;
+    use crate::config::{Config, Validation};
     use crate::stream::events::Events;
-    use crate::stream::producer::{StreamProducer, StreamProducerBuilder};
+    use crate::stream::producer::{
+        Delivery, Error, PartitionKey, StreamProducer, StreamProducerBuilder,
+    };
     use crossbeam_channel::{unbounded, RecvError};
+    use futures_timer::Delay;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
 
     impl PartialEq for Events {
         fn eq(&self, other: &Self) -> bool {
@@ -95,25 +385,49 @@ mod tests {
         }
     }
 
-    struct TestStreamProducer {}
+    #[derive(Default)]
+    struct TestStreamProducer {
+        produced: Arc<AtomicUsize>,
+        flushed: Arc<AtomicBool>,
+    }
 
     #[async_trait]
     impl StreamProducer for TestStreamProducer {
-        async fn produce(&self, _: &Events) {}
+        async fn produce(&self, _: &Events) -> Result<Delivery, Error> {
+            // Events must all be produced before flushing
+            assert!(!self.flushed.load(Ordering::SeqCst));
+            self.produced.fetch_add(1, Ordering::SeqCst);
+            Ok(Delivery {
+                partition: 0,
+                offset: 0,
+            })
+        }
+
+        async fn flush(&self, _: Duration) -> Result<(), Error> {
+            self.flushed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
     }
 
     #[test]
     fn stream_producer_loop_receive_and_leave() {
         let (sender, receiver) = unbounded::<Events>();
 
-        let stream_producer = TestStreamProducer {};
+        let stream_producer = TestStreamProducer::default();
+        let produced = stream_producer.produced.clone();
+        let flushed = stream_producer.flushed.clone();
 
         let tokens = vec!["token1", "token2", "token3"];
         for token in tokens {
             sender
                 .send(Events {
                     token: String::from(token),
+                    partner_id: String::from("partner"),
+                    user_id: String::from("user"),
                     events: vec![],
+                    request_id: Default::default(),
+                    consent_block: None,
+                    trace_context: Default::default(),
                 })
                 .unwrap();
         }
@@ -122,22 +436,128 @@ mod tests {
         block_on(stream_producer_loop(
             Box::new(stream_producer),
             receiver.clone(),
+            Duration::from_secs(1),
         ));
 
-        assert_eq!(receiver.recv(), Err(RecvError))
+        assert_eq!(receiver.recv(), Err(RecvError));
+        assert_eq!(produced.load(Ordering::SeqCst), 3);
+        assert!(flushed.load(Ordering::SeqCst));
+    }
+
+    // Each delivery takes a while, several are awaited at the same time
+    #[derive(Default)]
+    struct SlowStreamProducer {
+        in_flight: AtomicUsize,
+        max_in_flight: Arc<AtomicUsize>,
+        produced: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl StreamProducer for SlowStreamProducer {
+        async fn produce(&self, events: &Events) -> Result<Delivery, Error> {
+            self.produced.lock().unwrap().push(events.token.clone());
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            Delay::new(Duration::from_millis(50)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Delivery {
+                partition: 0,
+                offset: 0,
+            })
+        }
+
+        fn max_in_flight(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn stream_producer_loop_in_flight() {
+        let (sender, receiver) = unbounded::<Events>();
+
+        let stream_producer = SlowStreamProducer::default();
+        let max_in_flight = stream_producer.max_in_flight.clone();
+        let produced = stream_producer.produced.clone();
+
+        let tokens = vec!["token1", "token2", "token3", "token4", "token5"];
+        for token in &tokens {
+            sender
+                .send(Events {
+                    token: String::from(*token),
+                    partner_id: String::from("partner"),
+                    user_id: String::from("user"),
+                    events: vec![],
+                    request_id: Default::default(),
+                    consent_block: None,
+                    trace_context: Default::default(),
+                })
+                .unwrap();
+        }
+        drop(sender);
+
+        block_on(stream_producer_loop(
+            Box::new(stream_producer),
+            receiver,
+            Duration::from_secs(1),
+        ));
+
+        // Bounded, and produced in the order received
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(*produced.lock().unwrap(), tokens);
     }
 
     #[test]
     fn stream_producer_builder_ok() {
         let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let config = Config::from(config);
+
+        let res = StreamProducerBuilder::build(&config);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn stream_producer_builder_ok_producer_config() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
         let _ = config.set("stream.kafka.topic", "topic");
         let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.producer.linger.ms", 5);
+        let _ = config.set("stream.kafka.producer.compression.type", "gzip");
         let config = Config::from(config);
 
         let res = StreamProducerBuilder::build(&config);
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn stream_producer_builder_err_producer_config() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.producer.not.a.property", "value");
+        let config = Config::from(config);
+
+        let res = StreamProducerBuilder::build(&config);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn stream_producer_builder_err_codec() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.codec", "unknown");
+        let config = Config::from(config);
+
+        let res = StreamProducerBuilder::build(&config);
+        assert!(res.is_err());
+    }
+
     #[test]
     fn stream_producer_builder_err() {
         let config = config::Config::default();
@@ -146,4 +566,126 @@ mod tests {
         let res = StreamProducerBuilder::build(&config);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn stream_producer_builder_validate() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.key", "unknown");
+        let _ = config.set("stream.codec", "unknown");
+        let config = Config::from(config);
+
+        let mut validation = Validation::new(&config);
+        StreamProducerBuilder::validate(&mut validation);
+        match validation.finish() {
+            Err(crate::config::Error::Validation(problems)) => {
+                let keys: Vec<&str> = problems
+                    .iter()
+                    .map(|problem| problem.split(':').next().unwrap())
+                    .collect();
+                assert_eq!(
+                    keys,
+                    vec!["stream.codec", "stream.kafka.topic", "stream.kafka.key"]
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Build needs no key that validate does not report missing
+    #[test]
+    fn stream_producer_builder_validate_required_keys() {
+        let path = std::env::temp_dir().join("ucdp-stream-producer-required-keys");
+        for connector in ["kafka", "channel", "file"] {
+            let mut config = config::Config::default();
+            let _ = config.set("stream.connector", connector);
+
+            let validated = Config::from(config.clone());
+            let mut validation = Validation::new(&validated);
+            StreamProducerBuilder::validate(&mut validation);
+            if let Err(crate::config::Error::Validation(problems)) = validation.finish() {
+                for problem in problems {
+                    let key = problem.strip_suffix(": missing").unwrap();
+                    let value = match key.ends_with(".path") {
+                        true => path.to_str().unwrap(),
+                        false => key,
+                    };
+                    let _ = config.set(key, value);
+                }
+            }
+            assert!(StreamProducerBuilder::build(&Config::from(config)).is_ok());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn stream_producer_builder_err_partition_key() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "kafka");
+        let _ = config.set("stream.kafka.topic", "topic");
+        let _ = config.set("stream.kafka.broker", "0.0.0.0:0000");
+        let _ = config.set("stream.kafka.key", "unknown");
+        let config = Config::from(config);
+
+        match StreamProducerBuilder::build(&config) {
+            Err(Error::UnknownPartitionKey(key)) => assert_eq!(key, "unknown"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn stream_producer_builder_ok_connectors() {
+        let path = std::env::temp_dir().join("ucdp-stream-producer-builder");
+        let mut config = config::Config::default();
+        let _ = config.set(
+            "stream.channel.name",
+            "stream_producer_builder_ok_connectors",
+        );
+        let _ = config.set("stream.file.path", path.to_str().unwrap());
+
+        for connector in ["channel", "file"] {
+            let _ = config.set("stream.connector", connector);
+            let config = Config::from(config.clone());
+            let res = StreamProducerBuilder::build(&config);
+            assert!(res.is_ok());
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn stream_producer_builder_err_unknown_connector() {
+        let mut config = config::Config::default();
+        let _ = config.set("stream.connector", "unknown");
+        let config = Config::from(config);
+
+        match StreamProducerBuilder::build(&config) {
+            Err(Error::UnknownConnector(connector)) => assert_eq!(connector, "unknown"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn partition_key() {
+        let events = Events {
+            token: String::from("token"),
+            partner_id: String::from("partner"),
+            user_id: String::from("user"),
+            events: vec![],
+            request_id: Default::default(),
+            consent_block: None,
+            trace_context: Default::default(),
+        };
+
+        let cases = vec![
+            ("token", "token"),
+            ("user", "user"),
+            ("partner", "partner"),
+            ("partner-user", "partner/user"),
+        ];
+        for (partition_key, key) in cases {
+            let partition_key = partition_key.parse::<PartitionKey>().unwrap();
+            assert_eq!(partition_key.key(&events), key);
+        }
+    }
 }
@@ -0,0 +1,471 @@
+use crate::stream::codec::{Codec, CodecBuilder, CONTENT_TYPE_HEADER};
+use crate::stream::consumer::{self, EventsConsumer, StreamConsumer};
+use crate::stream::events::Events;
+use crate::stream::metrics;
+use crate::stream::producer::{self, Delivery, StreamProducer};
+use crate::stream::runtime::RUNTIME;
+use async_trait::async_trait;
+use futures::executor::block_on;
+use log::warn;
+use redis::aio::ConnectionManager;
+use redis::streams::{
+    StreamClaimReply, StreamId, StreamMaxlen, StreamPendingCountReply, StreamReadOptions,
+    StreamReadReply,
+};
+use redis::{AsyncCommands, ErrorKind, RedisError, RedisResult};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const PAYLOAD_FIELD: &str = "payload";
+
+// Entry ids are `{milliseconds}-{sequence}`, packed into an offset that keeps their order
+fn offset(id: &str) -> i64 {
+    let mut parts = id
+        .splitn(2, '-')
+        .map(|part| part.parse::<i64>().unwrap_or(0));
+    let milliseconds = parts.next().unwrap_or(0);
+    let sequence = parts.next().unwrap_or(0);
+    (milliseconds << 20) | (sequence & 0xfffff)
+}
+
+// Connecting and reconnecting are retried 3 times, waiting up to 200ms, 400ms then 800ms
+fn connect(url: &str) -> RedisResult<ConnectionManager> {
+    let client = redis::Client::open(url)?;
+    block_on(RUNTIME.spawn(ConnectionManager::new_with_backoff(client, 2, 100, 3)))
+        .map_err(|error| RedisError::from((ErrorKind::IoError, "runtime", error.to_string())))?
+}
+
+// Commands run on the shared runtime, the connection manager reconnects when needed
+async fn run<T, F, R>(connection: &ConnectionManager, command: F) -> RedisResult<T>
+where
+    F: FnOnce(ConnectionManager) -> R,
+    R: Future<Output = RedisResult<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    RUNTIME
+        .spawn(command(connection.clone()))
+        .await
+        .map_err(|error| RedisError::from((ErrorKind::IoError, "runtime", error.to_string())))?
+}
+
+// Events are appended to a single stream, trimmed to about `maxlen` entries if set
+pub struct RedisStreamProducer {
+    connection: ConnectionManager,
+    key: String,
+    maxlen: Option<usize>,
+    codec: Box<dyn Codec>,
+}
+
+impl RedisStreamProducer {
+    pub fn new(
+        url: &str,
+        key: &str,
+        maxlen: Option<usize>,
+        codec: Box<dyn Codec>,
+    ) -> Result<Self, producer::Error> {
+        Ok(RedisStreamProducer {
+            connection: connect(url)?,
+            key: key.into(),
+            maxlen,
+            codec,
+        })
+    }
+}
+
+#[async_trait]
+impl StreamProducer for RedisStreamProducer {
+    async fn produce(&self, events: &Events) -> Result<Delivery, producer::Error> {
+        let payload = self.codec.encode(events)?;
+        let items = vec![
+            (
+                CONTENT_TYPE_HEADER,
+                self.codec.content_type().as_bytes().to_vec(),
+            ),
+            (PAYLOAD_FIELD, payload),
+        ];
+
+        let (key, maxlen) = (self.key.clone(), self.maxlen);
+        let id: String = run(&self.connection, move |mut connection| async move {
+            match maxlen {
+                // Approximate trimming is much cheaper than an exact one
+                Some(maxlen) => {
+                    connection
+                        .xadd_maxlen(&key, StreamMaxlen::Approx(maxlen), "*", &items)
+                        .await
+                }
+                None => connection.xadd(&key, "*", &items).await,
+            }
+        })
+        .await?;
+
+        Ok(Delivery {
+            partition: 0,
+            offset: offset(&id),
+        })
+    }
+}
+
+// Consumer group member, entries are acked once consumed.
+// Entries left pending for longer than `reclaim_idle` (crashed worker, failed delivery) are
+// claimed and consumed again.
+pub struct RedisStreamConsumer {
+    connection: ConnectionManager,
+    key: String,
+    group: String,
+    name: String,
+    reclaim_idle: Duration,
+    last_reclaim: Mutex<Instant>,
+    events_consumer: Box<dyn EventsConsumer>,
+}
+
+impl RedisStreamConsumer {
+    pub fn new(
+        url: &str,
+        key: &str,
+        group: &str,
+        name: &str,
+        reclaim_idle: Duration,
+        events_consumer: Box<dyn EventsConsumer>,
+    ) -> Result<Self, consumer::Error> {
+        let connection = connect(url)?;
+
+        // Consume the stream from the beginning when the group does not exist yet
+        let (stream_key, stream_group) = (key.to_string(), group.to_string());
+        let res: RedisResult<()> = block_on(run(&connection, move |mut connection| async move {
+            connection
+                .xgroup_create_mkstream(&stream_key, &stream_group, "0")
+                .await
+        }));
+        match res {
+            Err(error) if error.code() != Some("BUSYGROUP") => return Err(error.into()),
+            _ => {}
+        }
+
+        Ok(RedisStreamConsumer {
+            connection,
+            key: key.into(),
+            group: group.into(),
+            name: name.into(),
+            reclaim_idle,
+            last_reclaim: Mutex::new(Instant::now()),
+            events_consumer,
+        })
+    }
+
+    async fn read(&self) -> Result<Option<StreamId>, consumer::Error> {
+        if let Some(entry) = self.reclaim().await? {
+            return Ok(Some(entry));
+        }
+
+        // Do not block forever so that the caller gets a chance to stop consuming
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.name)
+            .count(1)
+            .block(100);
+        let key = self.key.clone();
+        let reply: StreamReadReply = run(&self.connection, move |mut connection| async move {
+            connection.xread_options(&[&key], &[">"], &options).await
+        })
+        .await?;
+        Ok(reply.keys.into_iter().flat_map(|key| key.ids).next())
+    }
+
+    fn reclaim_due(&self) -> Result<bool, consumer::Error> {
+        let last_reclaim = self
+            .last_reclaim
+            .lock()
+            .map_err(|_| consumer::Error::Lock)?;
+        Ok(last_reclaim.elapsed() >= self.reclaim_idle)
+    }
+
+    // Look for entries left behind at most once every `reclaim_idle`, until there are none left
+    async fn reclaim(&self) -> Result<Option<StreamId>, consumer::Error> {
+        if !self.reclaim_due()? {
+            return Ok(None);
+        }
+
+        let reclaim_idle = self.reclaim_idle.as_millis() as usize;
+        let (key, group, name) = (self.key.clone(), self.group.clone(), self.name.clone());
+        let entry = run(&self.connection, move |mut connection| async move {
+            let pending: StreamPendingCountReply = connection
+                .xpending_count(&key, &group, "-", "+", 10)
+                .await?;
+            match pending
+                .ids
+                .into_iter()
+                .find(|pending| pending.last_delivered_ms >= reclaim_idle)
+            {
+                Some(pending) => {
+                    // Another consumer may have claimed it in the meantime
+                    let reply: StreamClaimReply = connection
+                        .xclaim(&key, &group, &name, reclaim_idle, &[pending.id])
+                        .await?;
+                    Ok(reply.ids.into_iter().next())
+                }
+                None => Ok(None),
+            }
+        })
+        .await?;
+
+        if entry.is_none() {
+            *self
+                .last_reclaim
+                .lock()
+                .map_err(|_| consumer::Error::Lock)? = Instant::now();
+        }
+        Ok(entry)
+    }
+
+    async fn ack(&self, id: &str) -> Result<(), consumer::Error> {
+        let (key, group, id) = (self.key.clone(), self.group.clone(), id.to_string());
+        let _: i64 = run(&self.connection, move |mut connection| async move {
+            connection.xack(&key, &group, &[id]).await
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+fn decode(entry: &StreamId) -> Result<Events, consumer::Error> {
+    let content_type: Option<String> = entry.get(CONTENT_TYPE_HEADER);
+    let payload: Vec<u8> = entry
+        .get(PAYLOAD_FIELD)
+        .ok_or(consumer::Error::Deserialization)?;
+    CodecBuilder::build_for_content_type(content_type.as_deref())
+        .and_then(|codec| codec.decode(&payload))
+        .map_err(|_| consumer::Error::Deserialization)
+}
+
+#[async_trait]
+impl StreamConsumer for RedisStreamConsumer {
+    async fn consume(&self) {
+        let entry = match self.read().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(error) => {
+                warn!("Error while reading stream entry: {:?}", error);
+                return;
+            }
+        };
+
+        metrics::consumed("redis");
+        match decode(&entry) {
+            Ok(events) => {
+                if let Err(error) = self.events_consumer.consume(&events).await {
+                    // Left pending, it will be reclaimed
+                    warn!("Error while consuming events: {:?}", error);
+                    return;
+                }
+            }
+            // Consuming an entry that cannot be decoded again would not help
+            Err(error) => {
+                metrics::deserialization_failed("redis");
+                warn!("Error while deserializing stream entry: {:?}", error)
+            }
+        }
+
+        if let Err(error) = self.ack(&entry.id).await {
+            warn!("Error while acking stream entry: {:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{offset, RedisStreamConsumer, RedisStreamProducer};
+    use crate::stream::codec::JsonCodec;
+    use crate::stream::consumer::{self, EventsConsumer, StreamConsumer};
+    use crate::stream::events::Events;
+    use crate::stream::producer::{self, StreamProducer};
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+    use redis::Commands;
+    use std::net::TcpStream;
+    use std::process::{Child, Command};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    // Fails the first time it sees a token so that the entry gets reclaimed
+    struct TestEventsConsumer {
+        tokens: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EventsConsumer for TestEventsConsumer {
+        async fn consume(&self, events: &Events) -> Result<(), consumer::Error> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let failed = !tokens.contains(&events.token);
+            tokens.push(events.token.clone());
+            if failed {
+                Err(consumer::Error::Destination("first attempt".into()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    struct RedisServer {
+        process: Child,
+    }
+
+    impl RedisServer {
+        // None when redis-server is not in PATH
+        fn launch(port: u16) -> Option<RedisServer> {
+            let process = Command::new("redis-server")
+                .args([
+                    "--port",
+                    &port.to_string(),
+                    "--save",
+                    "",
+                    "--appendonly",
+                    "no",
+                ])
+                .spawn()
+                .ok()?;
+            while TcpStream::connect(("127.0.0.1", port)).is_err() {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Some(RedisServer { process })
+        }
+    }
+
+    impl Drop for RedisServer {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    fn events(token: &str) -> Events {
+        Events {
+            token: token.into(),
+            partner_id: "partner".into(),
+            user_id: "user".into(),
+            events: vec![],
+            request_id: Default::default(),
+            consent_block: None,
+            trace_context: Default::default(),
+        }
+    }
+
+    #[test]
+    fn redis_stream_offset() {
+        assert_eq!(offset("0-1"), 1);
+        assert_eq!(offset("1-0"), 1 << 20);
+        assert!(offset("1526919030474-55") < offset("1526919030474-56"));
+        assert!(offset("1526919030474-56") < offset("1526919030475-0"));
+    }
+
+    #[test]
+    fn redis_stream_producer_err_connect() {
+        match RedisStreamProducer::new(
+            "redis://127.0.0.1:1",
+            "events",
+            None,
+            Box::new(JsonCodec {}),
+        ) {
+            Err(producer::Error::Redis(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn redis_stream_consumer_err_connect() {
+        let tokens = Arc::new(Mutex::new(vec![]));
+        match RedisStreamConsumer::new(
+            "redis://127.0.0.1:1",
+            "events",
+            "workers",
+            "worker",
+            Duration::from_secs(60),
+            Box::new(TestEventsConsumer { tokens }),
+        ) {
+            Err(consumer::Error::Redis(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn redis_stream_produce_and_consume() {
+        let _server = match RedisServer::launch(16379) {
+            Some(server) => server,
+            None => return eprintln!("redis-server is not in PATH, skipping"),
+        };
+        let url = "redis://127.0.0.1:16379";
+
+        let producer =
+            RedisStreamProducer::new(url, "events", None, Box::new(JsonCodec {})).unwrap();
+        let mut offsets = vec![];
+        for token in ["token1", "token2"] {
+            offsets.push(block_on(producer.produce(&events(token))).unwrap().offset);
+        }
+        assert!(offsets[0] < offsets[1]);
+
+        let tokens = Arc::new(Mutex::new(vec![]));
+        let consumer = RedisStreamConsumer::new(
+            url,
+            "events",
+            "workers",
+            "worker1",
+            Duration::from_millis(200),
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        )
+        .unwrap();
+        block_on(consumer.consume());
+        block_on(consumer.consume());
+        assert_eq!(*tokens.lock().unwrap(), vec!["token1", "token2"]);
+        drop(consumer);
+
+        // Another worker of the group reclaims the entries left pending
+        let consumer = RedisStreamConsumer::new(
+            url,
+            "events",
+            "workers",
+            "worker2",
+            Duration::from_millis(200),
+            Box::new(TestEventsConsumer {
+                tokens: tokens.clone(),
+            }),
+        )
+        .unwrap();
+        for _ in 0..20 {
+            if tokens.lock().unwrap().len() == 4 {
+                break;
+            }
+            block_on(consumer.consume());
+        }
+        assert_eq!(
+            *tokens.lock().unwrap(),
+            vec!["token1", "token2", "token1", "token2"]
+        );
+
+        // Nothing left
+        block_on(consumer.consume());
+        thread::sleep(Duration::from_millis(200));
+        block_on(consumer.consume());
+        assert_eq!(tokens.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn redis_stream_produce_maxlen() {
+        let _server = match RedisServer::launch(16380) {
+            Some(server) => server,
+            None => return eprintln!("redis-server is not in PATH, skipping"),
+        };
+        let url = "redis://127.0.0.1:16380";
+
+        let producer =
+            RedisStreamProducer::new(url, "events", Some(10), Box::new(JsonCodec {})).unwrap();
+        for _ in 0..1000 {
+            block_on(producer.produce(&events("token"))).unwrap();
+        }
+
+        let mut connection = redis::Client::open(url).unwrap().get_connection().unwrap();
+        let len: usize = connection.xlen("events").unwrap();
+        assert!(len < 1000);
+    }
+}
@@ -0,0 +1,13 @@
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+
+// Tokio based clients (async-nats, redis) need a reactor that the callers (actix, futures executor)
+// do not always provide. Connections of every producer and consumer of the process are driven by this one.
+pub(crate) static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("stream")
+        .enable_all()
+        .build()
+        .expect("stream runtime")
+});
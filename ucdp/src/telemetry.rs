@@ -0,0 +1,135 @@
+use crate::config::{Config, Validation};
+use log::{info, warn};
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
+use opentelemetry::Context;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("otlp exporter error")]
+    Exporter(#[from] opentelemetry_otlp::ExporterBuildError),
+}
+
+// W3C trace context is always propagated.
+// Spans are exported over OTLP/HTTP when telemetry.otlp.endpoint is set, dropped otherwise.
+pub fn init(config: &Config, service_name: &str) -> Result<Option<SdkTracerProvider>, Error> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let endpoint = match config.get_str("telemetry.otlp.endpoint") {
+        Ok(endpoint) => endpoint,
+        Err(_) => return Ok(None),
+    };
+    info!("Exporting traces to {}", endpoint);
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+    global::set_tracer_provider(provider.clone());
+
+    Ok(Some(provider))
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.optional("telemetry.otlp.endpoint", Config::get_url);
+}
+
+// Export the spans that have not been exported yet
+pub fn shutdown(provider: Option<SdkTracerProvider>) {
+    if let Some(provider) = provider {
+        if let Err(error) = provider.shutdown() {
+            warn!("Error while shutting down tracer provider: {:?}", error);
+        }
+    }
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer("ucdp")
+}
+
+// Start a span, it ends once the returned context and its clones are dropped
+pub fn span<T>(name: T, kind: SpanKind, parent: &Context) -> Context
+where
+    T: Into<Cow<'static, str>>,
+{
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+// Trace context headers (traceparent, tracestate) of a context
+pub fn inject(cx: &Context) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut carrier));
+    carrier
+}
+
+// Context of trace context headers, the empty context if there are none
+pub fn extract(carrier: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, init, inject, span};
+    use crate::config::Config;
+    use opentelemetry::trace::{
+        SpanContext, SpanKind, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::{Context, SpanId};
+    use std::collections::HashMap;
+
+    #[test]
+    fn telemetry_init_without_endpoint() {
+        let config = Config::from(config::Config::default());
+        assert!(init(&config, "test").unwrap().is_none());
+    }
+
+    #[test]
+    fn telemetry_propagation() {
+        let config = Config::from(config::Config::default());
+        let _ = init(&config, "test");
+
+        let span_context = SpanContext::new(
+            TraceId::from(0x4bf92f3577b34da6a3ce929d0e0e4736),
+            SpanId::from(0x00f067aa0ba902b7),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(span_context);
+
+        let carrier = inject(&cx);
+        assert_eq!(
+            carrier["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        // Spans started from the extracted context belong to the same trace
+        let cx = span("test", SpanKind::Internal, &extract(&carrier));
+        assert_eq!(
+            cx.span().span_context().trace_id(),
+            TraceId::from(0x4bf92f3577b34da6a3ce929d0e0e4736)
+        );
+        let cx = extract(&HashMap::new());
+        assert_eq!(cx.span().span_context().trace_id(), TraceId::INVALID);
+    }
+}
@@ -1,14 +1,65 @@
-use ucdp::config::Config;
-use ucdp::stream::consumer::{Error, StreamConsumerBuilder};
+mod web;
+
+use actix_web::web::Data;
+use std::sync::Arc;
+use ucdp::cli::{self, Args};
+use ucdp::config::{self, Validation};
+use ucdp::shutdown;
+use ucdp::stream::consumer::{Error, ReloadableEventsConsumer, StreamConsumerBuilder};
+use ucdp::telemetry;
+
+fn validate(validation: &mut Validation) {
+    telemetry::validate(validation);
+    shutdown::validate(validation);
+    StreamConsumerBuilder::validate(validation);
+    web::validate(validation);
+}
 
 #[actix_web::main]
 async fn main() -> Result<(), Error> {
-    env_logger::init();
+    let args = Args::parse();
+    let config = match cli::init(&args, validate) {
+        Ok(Some(config)) => config,
+        Ok(None) => return Ok(()),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
 
-    let config = Config::new(String::from("config/Main"));
+    let tracer_provider = telemetry::init(&config, "workers").map_err(std::io::Error::other)?;
 
-    let stream_consumer = StreamConsumerBuilder::build(&config)?;
-    loop {
+    let terminate = shutdown::on_terminate(shutdown::drain_timeout(&config))?;
+
+    // Health and metrics are served while the stream is consumed
+    let health = Data::new(web::Health::new(&config));
+    let server = web::run_http_server(&config, health.clone())?;
+    let server_handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    // The destination follows the changes of the configuration file, the stream needs a restart
+    let events_consumer = Arc::new(ReloadableEventsConsumer::new(&config)?);
+    let stream_consumer = StreamConsumerBuilder::build_with_events_consumer(
+        &config,
+        Box::new(web::HealthEventsConsumer {
+            health: health.clone(),
+            events_consumer: events_consumer.clone(),
+        }),
+    )?;
+    let _config_watcher = config::watch(&args.source(), validate, move |config| {
+        events_consumer
+            .reload(config)
+            .map_err(|error| error.to_string())
+    })?;
+    health.set_ready(true);
+    while !terminate.is_set() {
         stream_consumer.consume().await;
     }
+    health.set_ready(false);
+
+    // In-flight events have been consumed, save progress before exiting
+    stream_consumer.close().await;
+    server_handle.stop(true).await;
+    telemetry::shutdown(tracer_provider);
+    Ok(())
 }
@@ -0,0 +1,298 @@
+use actix_web::dev::Server;
+use actix_web::{get, middleware::Logger, web, App, HttpResponse, HttpServer};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ucdp::config::{Config, Validation};
+use ucdp::stream::consumer::{Error, EventsConsumer};
+use ucdp::stream::events::Events;
+use ucdp::stream::metrics;
+
+#[derive(Default)]
+struct Deliveries {
+    next_id: u64,
+    // Start of the deliveries not completed yet, the oldest first
+    in_flight: BTreeMap<u64, Instant>,
+    // First failure since the last successful delivery
+    failing_since: Option<Instant>,
+}
+
+// Shared by the events consumer and the HTTP server
+pub struct Health {
+    ready: AtomicBool,
+    deliveries: Mutex<Deliveries>,
+    // Workers with a delivery pending or failing for that long are considered stuck
+    stall_timeout: Duration,
+}
+
+impl Health {
+    pub fn new(config: &Config) -> Self {
+        Health {
+            ready: AtomicBool::new(false),
+            deliveries: Mutex::new(Deliveries::default()),
+            stall_timeout: config
+                .get_duration("health.stall_timeout")
+                .unwrap_or_else(|_| Duration::from_secs(60)),
+        }
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    fn started(&self) -> u64 {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let id = deliveries.next_id;
+        deliveries.next_id += 1;
+        deliveries.in_flight.insert(id, Instant::now());
+        id
+    }
+
+    fn completed(&self, id: u64, delivered: bool) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        deliveries.in_flight.remove(&id);
+        if delivered {
+            deliveries.failing_since = None;
+        } else if deliveries.failing_since.is_none() {
+            deliveries.failing_since = Some(Instant::now());
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    // Idle workers are alive, nothing is waiting on them
+    fn is_alive(&self) -> bool {
+        self.deliveries
+            .lock()
+            .map(|deliveries| {
+                deliveries
+                    .in_flight
+                    .values()
+                    .next()
+                    .into_iter()
+                    .chain(deliveries.failing_since.iter())
+                    .all(|since| since.elapsed() < self.stall_timeout)
+            })
+            .unwrap_or(false)
+    }
+}
+
+// Removes the delivery from the in-flight ones, even when its future is dropped
+struct Delivery<'a> {
+    health: &'a Health,
+    id: u64,
+    delivered: bool,
+}
+
+impl Drop for Delivery<'_> {
+    fn drop(&mut self) {
+        self.health.completed(self.id, self.delivered);
+    }
+}
+
+// Reports the deliveries of the wrapped consumer to the health checks
+pub struct HealthEventsConsumer<T: EventsConsumer> {
+    pub health: web::Data<Health>,
+    pub events_consumer: T,
+}
+
+#[async_trait]
+impl<T: EventsConsumer> EventsConsumer for HealthEventsConsumer<T> {
+    async fn consume(&self, events: &Events) -> Result<(), Error> {
+        let mut delivery = Delivery {
+            health: &self.health,
+            id: self.health.started(),
+            delivered: false,
+        };
+        let res = self.events_consumer.consume(events).await;
+        delivery.delivered = res.is_ok();
+        res
+    }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    status: &'static str,
+}
+
+fn status_response(ok: bool, ko: &'static str) -> HttpResponse {
+    if ok {
+        HttpResponse::Ok().json(&StatusResponse { status: "ok" })
+    } else {
+        HttpResponse::ServiceUnavailable().json(&StatusResponse { status: ko })
+    }
+}
+
+#[get("/healthz")]
+async fn get_healthz(health: web::Data<Health>) -> HttpResponse {
+    status_response(health.is_alive(), "stalled")
+}
+
+#[get("/readyz")]
+async fn get_readyz(health: web::Data<Health>) -> HttpResponse {
+    status_response(health.is_ready(), "not ready")
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> HttpResponse {
+    match metrics::encode() {
+        Ok((content_type, body)) => HttpResponse::Ok().content_type(content_type).body(body),
+        Err(error) => HttpResponse::InternalServerError().body(error.to_string()),
+    }
+}
+
+pub fn validate(validation: &mut Validation) {
+    validation.optional("server.bind", Config::get_socket_addr);
+    validation.optional("health.stall_timeout", Config::get_duration);
+}
+
+// Signals are handled by the consume loop, the server is stopped once it has drained
+pub fn run_http_server(config: &Config, health: web::Data<Health>) -> std::io::Result<Server> {
+    let server_binding_address = config
+        .get_str("server.bind")
+        .unwrap_or_else(|_| "0.0.0.0:8081".into());
+
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(health.clone())
+            .wrap(Logger::default())
+            .service(get_healthz)
+            .service(get_readyz)
+            .service(get_metrics)
+    })
+    .bind(server_binding_address)?
+    .disable_signals()
+    .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_healthz, get_metrics, get_readyz, validate, Health, HealthEventsConsumer};
+    use actix_web::dev::Service;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{init_service, read_body, TestRequest};
+    use actix_web::{web, App};
+    use async_trait::async_trait;
+    use ucdp::config::{Config, Source, Validation};
+    use ucdp::stream::consumer::{Error, EventsConsumer, StreamConsumerBuilder};
+    use ucdp::stream::events::Events;
+
+    fn new_health(stall_timeout: i64) -> web::Data<Health> {
+        let mut config = config::Config::default();
+        let _ = config.set("health.stall_timeout", stall_timeout);
+        web::Data::new(Health::new(&Config::from(config)))
+    }
+
+    async fn get_status(health: web::Data<Health>, uri: &str) -> StatusCode {
+        let service = init_service(
+            App::new()
+                .app_data(health)
+                .service(get_healthz)
+                .service(get_readyz),
+        )
+        .await;
+        let request = TestRequest::default()
+            .uri(uri)
+            .method(Method::GET)
+            .to_request();
+        service.call(request).await.unwrap().status()
+    }
+
+    struct FailingEventsConsumer {}
+
+    #[async_trait]
+    impl EventsConsumer for FailingEventsConsumer {
+        async fn consume(&self, _events: &Events) -> Result<(), Error> {
+            Err(Error::Destination("unavailable".into()))
+        }
+    }
+
+    fn new_events() -> Events {
+        Events {
+            token: "token".into(),
+            partner_id: "".into(),
+            user_id: "".into(),
+            events: vec![],
+            request_id: "".into(),
+            consent_block: None,
+            trace_context: Default::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn workers_healthz() {
+        // Alive while idle, even with no time allowed
+        let health = new_health(0);
+        assert_eq!(get_status(health.clone(), "/healthz").await, StatusCode::OK);
+
+        // Stalled by a pending delivery
+        let id = health.started();
+        assert_eq!(
+            get_status(health.clone(), "/healthz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        health.completed(id, true);
+        assert_eq!(get_status(health.clone(), "/healthz").await, StatusCode::OK);
+
+        // Stalled by failing deliveries until one succeeds
+        let events_consumer = HealthEventsConsumer {
+            health: health.clone(),
+            events_consumer: FailingEventsConsumer {},
+        };
+        assert!(events_consumer.consume(&new_events()).await.is_err());
+        assert_eq!(
+            get_status(health.clone(), "/healthz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let id = health.started();
+        health.completed(id, true);
+        assert_eq!(get_status(health, "/healthz").await, StatusCode::OK);
+
+        let health = new_health(60);
+        health.started();
+        assert_eq!(get_status(health, "/healthz").await, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn workers_readyz() {
+        let health = new_health(60);
+        assert_eq!(
+            get_status(health.clone(), "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        health.set_ready(true);
+        assert_eq!(get_status(health, "/readyz").await, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn workers_metrics() {
+        ucdp::stream::metrics::consumed("workers_metrics");
+
+        let service = init_service(App::new().service(get_metrics)).await;
+        let request = TestRequest::default()
+            .uri("/metrics")
+            .method(Method::GET)
+            .to_request();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = read_body(response).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("workers_messages_consumed_total{connector=\"workers_metrics\"} 1"));
+    }
+
+    #[test]
+    fn workers_validate_main_config() {
+        let config = Config::load(&Source::new("config/Main", None)).unwrap();
+        let mut validation = Validation::new(&config);
+        StreamConsumerBuilder::validate(&mut validation);
+        validate(&mut validation);
+        assert!(validation.finish().is_ok());
+    }
+}